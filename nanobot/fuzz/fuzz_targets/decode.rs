@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate nanobot_lib;
+
+fuzz_target!(|data: &[u8]| {
+    nanobot_lib::fuzz::fuzz_decode(data);
+});
@@ -2,17 +2,24 @@ extern crate rand;
 
 use self::rand::Rng;
 use self::rand::XorShiftRng;
-use ai::config::Config;
+use ai::config::*;
+use ai::reservation::*;
 use ai::AssembleAI;
+use ai::ReassembleAI;
 use common::*;
+use generator::seeded_rng;
 use model::*;
 use state::State;
-use std::cmp::min;
 use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 
+// 掘る voxel が浮く voxel を作るときに、そこを通らない経路を探し直す回数
+const DIG_RETRY: usize = 4;
+// 閉じ込められていない bot が、行けない候補に向かって掘り始めるまでの失敗の数
+const DIG_PATIENCE: i32 = 100;
+
 struct BotState {
     bot: Nanobot,
     fusions_bid: Option<Bid>,
@@ -34,6 +41,10 @@ impl BotState {
 
 pub struct BfsAI {
     rng: XorShiftRng,
+    // 次に扱う voxel を選ぶときの評価値の重み (Config を参照)
+    distance_weight: i32,
+    height_weight: i32,
+    noise: i32,
     state: State,
     current: Model,
     target: Model,
@@ -41,19 +52,44 @@ pub struct BfsAI {
     volatiles: HashSet<Position>,
     candidates: Vec<Position>,
     visited: HashSet<Position>, // candidatesとして入ったことがあるやつのリスト
+    void_candidates: Vec<Position>, // Voidしなければいけないvoxelのうち、まだ誰も担当していないもの
+    voiding: HashSet<Position>,     // Voidしにいっている途中のvoxel
+    dug: Vec<Position>, // 掘って消したtargetのvoxel。閉じ込められたbotも行けない候補もなくなってからcandidatesに戻す
+    dig_count: usize,
     trace: Vec<Command>,
     added_bot_list: Vec<Nanobot>,
     deleted_bot_list: Vec<Bid>,
 }
 
 impl BfsAI {
-    #[allow(deprecated)]
-    pub fn new(_config: &Config, source: &Model, target: &Model) -> Self {
+    pub fn params() -> Vec<Param> {
+        vec![BFS_DISTANCE_WEIGHT, BFS_HEIGHT_WEIGHT, BFS_NOISE]
+    }
+
+    pub fn new(config: &Config, source: &Model, target: &Model) -> Self {
+        let r = source.matrix.len();
         let mut volatiles = HashSet::new();
         volatiles.insert(Position::zero());
-        // TODO sourceでFullなのをvolatilesに追加する
+        // Fullのvoxelはvolatile扱いにする
+        let mut void_candidates = vec![];
+        for x in 0..r {
+            for y in 0..r {
+                for z in 0..r {
+                    let p = Position::new(x as i32, y as i32, z as i32);
+                    if source.voxel_at(p) == Voxel::Full {
+                        volatiles.insert(p);
+                        if target.voxel_at(p) == Voxel::Void {
+                            void_candidates.push(p);
+                        }
+                    }
+                }
+            }
+        }
         BfsAI {
-            rng: XorShiftRng::new_unseeded(),
+            rng: seeded_rng(config.seed),
+            distance_weight: config.bfs_distance_weight,
+            height_weight: config.bfs_height_weight,
+            noise: config.bfs_noise,
             state: State::initial_with_model(source),
             current: source.clone(),
             target: target.clone(),
@@ -61,6 +97,10 @@ impl BfsAI {
             volatiles: volatiles,
             candidates: vec![],
             visited: HashSet::new(),
+            void_candidates,
+            voiding: HashSet::new(),
+            dug: vec![],
+            dig_count: 0,
             trace: vec![],
             added_bot_list: vec![],
             deleted_bot_list: vec![],
@@ -91,7 +131,7 @@ impl BfsAI {
         let mut target = self.candidates.len();
         let mut best = 1 << 30;
         'outer_loop: for (i, c) in self.candidates.iter().enumerate() {
-            if self.volatiles.contains(c) {
+            if self.volatiles.contains(c) || !self.is_supported(c) {
                 continue;
             }
             for b in self.bots.iter() {
@@ -104,9 +144,9 @@ impl BfsAI {
             // TODO candidateの選択をもう少しましにする
             // groundからの距離が近いやつをなるべく優先する
             // 暫定でyが小さいやつを優先させる
-            let mut score = ((*from - c).manhattan_length() + 2) / 5 * 100;
-            score += c.y * 1000;
-            score += self.rng.gen_range(0, 130);
+            let mut score = ((*from - c).manhattan_length() + 2) / 5 * self.distance_weight;
+            score += c.y * self.height_weight;
+            score += self.rng.gen_range(0, self.noise);
             if score < best {
                 target = i;
                 best = score;
@@ -119,6 +159,15 @@ impl BfsAI {
         self.candidates.remove(target);
         Some(ret)
     }
+    // 床か、消しにいっていないFullのvoxelに接していれば埋めても浮かない
+    // (掘ったvoxelに接しているだけのcandidateがあるので確かめる)
+    fn is_supported(&self, p: &Position) -> bool {
+        p.y == 0 || adjacent(*p).iter().any(|n| {
+            self.is_valid_coordinate(n)
+                && self.current.voxel_at(*n) == Voxel::Full
+                && !self.voiding.contains(n)
+        })
+    }
     // posからvolatileしないncdの位置を全部返す
     // ただしfromはvolatileしていても許可
     fn pos_ncd_all(&self, from: &Position, to: &Position) -> Vec<Position> {
@@ -132,32 +181,6 @@ impl BfsAI {
         }
         poss
     }
-    // posからSMoveで移動可能な位置とそのCommandを返す
-    fn pos_smove_all(&self, pos: &Position, max_dist: i32) -> Vec<(Position, Command)> {
-        assert!(max_dist >= 1);
-        let max_dist = min(max_dist, 15);
-        let mut ret = vec![];
-        for dir in 0..6 {
-            // yが高いほうが優先
-            let dy = [1, 0, 0, 0, 0, -1];
-            let dx = [0, 1, -1, 0, 0, 0];
-            let dz = [0, 0, 0, 1, -1, 0];
-            for dist in 1..max_dist + 1 {
-                let llcd = LLCD::new(dist * dx[dir], dist * dy[dir], dist * dz[dir]);
-                let npos = *pos + &llcd;
-                if !self.is_safe_coordinate(&npos) {
-                    break;
-                }
-                ret.push((npos, Command::SMove(llcd)));
-            }
-        }
-        ret
-    }
-    // // posからLMoveで移動可能な位置とそのCommandを返す
-    // fn pos_lmove_all(&self, _pos: &Position) -> Vec<(Position, Command)> {
-    //     // TODO
-    //     unimplemented!();
-    // }
     // SMove・LMoveの系列をbfsで作って移動してfillする
     fn make_target_fill_command(&mut self, from: &Position, to: &Position) -> Option<Vec<Command>> {
         let tos = self.pos_ncd_all(from, to);
@@ -180,63 +203,309 @@ impl BfsAI {
         ret.append(&mut commands);
         Some(ret)
     }
-    // tosのいずれかに移動する系列をbfsで作る
+    // tosのいずれかに移動する系列を reservation.rs の探索で作る
+    // 他のbotの通り道はvolatilesに入っているので、予約表は使わない
     // 戻り値はついた場所とCommandの系列
     fn make_move_any_command(
         &self,
         from: &Position,
         tos: &Vec<Position>,
     ) -> Option<(Position, Vec<Command>)> {
-        let tos0 = tos[0];
-        let tos = tos.iter().map(|p| *p).collect::<HashSet<Position>>();
-        let mut que = BinaryHeap::<(i32, Position, i32)>::new();
-        que.push((0, *from, 0));
-        let mut parents = HashMap::<Position, (Position, Command)>::new(); // visit + 経路復元用
-        parents.insert(*from, (*from, Command::Wait));
-        let mut max_dist = 15;
-        let max_cnt = (*from - &tos0).manhattan_length() / max_dist + 5;
-        for to in tos.iter() {
-            max_dist = min(max_dist, (*from - to).chessboard_length());
-        }
-        while let Some((_score, f, cnt)) = que.pop() {
-            if tos.contains(&f) {
-                // 経路復元してreverseで正順にしてコマンドの系列を返す
-                let mut ret = vec![];
-                let mut next = f;
-                while next != *from {
-                    let (prev, command) = parents[&next];
-                    ret.push(command);
-                    next = prev;
+        let is_free = |p: &Position| self.is_safe_coordinate(p);
+        let (to, commands) = find_timed_path_any(&is_free, &Reservations::new(), from, tos)?;
+        // 経路が自分自身と交差するとvolatileを二重に設定してしまうので、行けないことにする
+        if !self.is_simple_path(from, &commands) {
+            return None;
+        }
+        Some((to, commands))
+    }
+    // fromからcommandsで移動したときに、同じ位置を2回通らなければtrue
+    fn is_simple_path(&self, from: &Position, commands: &[Command]) -> bool {
+        let mut visited = HashSet::new();
+        visited.insert(*from);
+        let mut pos = *from;
+        for command in commands.iter() {
+            let path = match command {
+                Command::SMove(llcd) => Region(pos, pos + llcd).iter().collect::<Vec<_>>(),
+                Command::LMove(slcd1, slcd2) => {
+                    let mid = pos + slcd1;
+                    Region(pos, mid)
+                        .iter()
+                        .chain(Region(mid, mid + slcd2).iter().filter(move |p| *p != mid))
+                        .collect()
+                }
+                _ => vec![],
+            };
+            for p in path {
+                if p != pos && !visited.insert(p) {
+                    return false;
+                }
+            }
+            pos = self.simulate_move(&pos, command);
+        }
+        true
+    }
+    // fromから移動してtoをVoidする系列を作る
+    fn make_target_void_command(&mut self, from: &Position, to: &Position) -> Option<Vec<Command>> {
+        let tos = self.pos_ncd_all(from, to);
+        if tos.len() == 0 {
+            return None;
+        }
+        let (nto, mut commands) = self.make_move_any_command(from, &tos)?;
+        let cd = *to - &nto;
+        commands.push(Command::Void(NCD::new(cd.x, cd.y, cd.z)));
+        Some(commands)
+    }
+    // toとVoidしにいっている途中のvoxelを全部消しても浮くvoxelができなければtrue
+    // 途中のvoxelはどの順で消えてもよいように、それぞれが床か残るvoxelに接していることも確かめる
+    // Fillしにいっている途中のvoxelも、床か残るvoxelに接していなければいけない
+    fn can_void(&self, to: &Position) -> bool {
+        let r = self.current.matrix.len();
+        let removed = |p: &Position| p == to || self.voiding.contains(p);
+        let mut grounded = vec![false; r * r * r];
+        let mut que = VecDeque::new();
+        let mut full_count = 0;
+        for x in 0..r {
+            for y in 0..r {
+                for z in 0..r {
+                    let p = Position::new(x as i32, y as i32, z as i32);
+                    if self.current.voxel_at(p) == Voxel::Void || removed(&p) {
+                        continue;
+                    }
+                    full_count += 1;
+                    if y == 0 {
+                        grounded[p.index(r)] = true;
+                        que.push_back(p);
+                    }
+                }
+            }
+        }
+        let mut grounded_count = que.len();
+        while let Some(p) = que.pop_front() {
+            for next in adjacent(p) {
+                if !self.is_valid_coordinate(&next)
+                    || grounded[next.index(r)]
+                    || self.current.voxel_at(next) == Voxel::Void
+                    || removed(&next)
+                {
+                    continue;
+                }
+                grounded[next.index(r)] = true;
+                grounded_count += 1;
+                que.push_back(next);
+            }
+        }
+        if grounded_count != full_count {
+            return false;
+        }
+        let fills = self.pending_fills();
+        self.voiding.iter().chain(Some(*to).iter()).chain(fills.iter()).all(|p| {
+            p.y == 0
+                || adjacent(*p)
+                    .iter()
+                    .any(|n| self.is_valid_coordinate(n) && grounded[n.index(r)])
+        })
+    }
+    // botのnext_commandsに入っているFillの位置
+    fn pending_fills(&self) -> Vec<Position> {
+        let mut ret = vec![];
+        for b in self.bots.iter() {
+            let mut pos = b.bot.pos;
+            for command in b.next_commands.iter() {
+                if let Command::Fill(ncd) = command {
+                    ret.push(pos + ncd);
+                }
+                pos = self.simulate_move(&pos, command);
+            }
+        }
+        ret
+    }
+    // 一番上の層の空いているvoxelから、空いているvoxelだけを通って行ける位置
+    // botの位置や移動の予定は見ない。dugのvoxelは埋める予定なのでFull扱いにする。
+    // ここにいないbotはFullのvoxelに閉じ込められている
+    fn open_cells(&self) -> Vec<bool> {
+        let r = self.current.matrix.len();
+        let mut blocked = vec![false; r * r * r];
+        for p in self.dug.iter() {
+            blocked[p.index(r)] = true;
+        }
+        let is_free = |p: &Position| {
+            self.is_valid_coordinate(p)
+                && self.current.voxel_at(*p) == Voxel::Void
+                && !blocked[p.index(r)]
+        };
+        let mut open = vec![false; r * r * r];
+        let mut que = VecDeque::new();
+        for x in 0..r {
+            for z in 0..r {
+                let p = Position::new(x as i32, r as i32 - 1, z as i32);
+                if is_free(&p) {
+                    open[p.index(r)] = true;
+                    que.push_back(p);
+                }
+            }
+        }
+        while let Some(p) = que.pop_front() {
+            for next in adjacent(p) {
+                if !is_free(&next) || open[next.index(r)] {
+                    continue;
                 }
-                ret.reverse();
-                return Some((f, ret));
+                open[next.index(r)] = true;
+                que.push_back(next);
             }
-            if cnt == max_cnt {
+        }
+        open
+    }
+    // 一番上から行けないcandidateと、隣に一番上から行ける位置がないvoid_candidates
+    fn pocket_cells(&self, open: &[bool]) -> Vec<bool> {
+        let r = self.current.matrix.len();
+        let mut pockets = vec![false; r * r * r];
+        for c in self.candidates.iter() {
+            if !open[c.index(r)] {
+                pockets[c.index(r)] = true;
+            }
+        }
+        for c in self.void_candidates.iter() {
+            let exposed = all_ncd().iter().any(|ncd| {
+                let n = *c + ncd;
+                self.is_valid_coordinate(&n) && open[n.index(r)]
+            });
+            if !exposed {
+                pockets[c.index(r)] = true;
+            }
+        }
+        pockets
+    }
+    // fromからgoalのどこかまで、通るFullのvoxelの数が一番少ない経路 (fromとgoalを含む)
+    // 消しにいっている途中のvoxelは数えない
+    fn search_dig_path(
+        &self,
+        from: &Position,
+        goal: &[bool],
+        banned: &HashSet<Position>,
+    ) -> Option<Vec<Position>> {
+        let r = self.current.matrix.len();
+        let full_cost = (r * r * r) as i64;
+        let mut costs = vec![i64::max_value(); r * r * r];
+        let mut parents = HashMap::new();
+        let mut que = BinaryHeap::new();
+        costs[from.index(r)] = 0;
+        que.push((0, *from));
+        while let Some((cost, p)) = que.pop() {
+            let cost = -cost;
+            if cost > costs[p.index(r)] {
                 continue;
             }
-            // TODO lmoveも追加する
-            let next = self.pos_smove_all(&f, max_dist);
-            for &(t, command) in next.iter() {
-                if parents.contains_key(&t) {
+            if goal[p.index(r)] {
+                let mut path = vec![p];
+                while let Some(&prev) = parents.get(path.last().unwrap()) {
+                    path.push(prev);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for next in adjacent(p) {
+                if !self.is_valid_coordinate(&next) || banned.contains(&next) {
                     continue;
                 }
-                parents.insert(t, (f, command));
-                let score = self.calc_astar_score(cnt + 1, &t, &tos0);
-                que.push((score, t, cnt + 1));
+                let mut next_cost = cost + 1;
+                if self.current.voxel_at(next) == Voxel::Full && !self.voiding.contains(&next) {
+                    next_cost += full_cost;
+                }
+                if next_cost < costs[next.index(r)] {
+                    costs[next.index(r)] = next_cost;
+                    parents.insert(next, p);
+                    que.push((-next_cost, next));
+                }
+            }
+        }
+        None
+    }
+    // goalに向かって、途中のFullのvoxelを掘りながら進むコマンドを作る
+    // 掘るときは掘るvoxelも返す。掘らずに行けるときはgoalまで動く (candidateの上には乗らずに手前で止まる)
+    // 先に誰かが掘っているvoxelを通るときは、掘り終わるのを待つのでNone
+    fn make_dig_command(
+        &self,
+        from: &Position,
+        goal: &[bool],
+    ) -> Option<(Option<Position>, Vec<Command>)> {
+        let mut banned = HashSet::new();
+        for _ in 0..DIG_RETRY {
+            let path = self.search_dig_path(from, goal, &banned)?;
+            let k = match path
+                .iter()
+                .position(|p| self.current.voxel_at(*p) == Voxel::Full)
+            {
+                None => {
+                    if path.len() < 2 {
+                        return None;
+                    }
+                    let mut to = path[path.len() - 1];
+                    if self.candidates.contains(&to) {
+                        to = path[path.len() - 2];
+                    }
+                    if to == *from {
+                        return None;
+                    }
+                    let (_, commands) = self.make_move_any_command(from, &vec![to])?;
+                    return Some((None, commands));
+                }
+                Some(k) => k,
+            };
+            let (q, d) = (path[k - 1], path[k]);
+            if self.voiding.contains(&d) {
+                return None;
             }
+            if !self.can_void(&d) {
+                banned.insert(d);
+                continue;
+            }
+            let (_, mut commands) = self.make_move_any_command(from, &vec![q])?;
+            let cd = d - &q;
+            commands.push(Command::Void(NCD::new(cd.x, cd.y, cd.z)));
+            return Some((Some(d), commands));
         }
-        // どこにもたどり着けなかった場合
         None
     }
-    // tos[0]を基準にする
-    fn calc_astar_score(&self, cnt: i32, from: &Position, to: &Position) -> i32 {
-        let mut score = cnt * 5;
-        let d = (*from - to).manhattan_length() / 2;
-        score += d;
-        if from.y <= to.y {
-            score += 2;
+    // goalに向かって掘るコマンドをbot_indexに入れる。入れられなければNone、voxelを掘るならSome(true)
+    fn dig(&mut self, bot_index: usize, goal: &[bool]) -> Option<bool> {
+        let from = self.bots[bot_index].bot.pos;
+        let (dug, commands) = self.make_dig_command(&from, goal)?;
+        if let Some(d) = dug {
+            self.dig_count += 1;
+            self.voiding.insert(d);
+            self.void_candidates.retain(|p| *p != d);
+            if self.target.voxel_at(d) == Voxel::Full {
+                self.dug.push(d);
+            }
         }
-        -score
+        let commands = commands.into_iter().collect();
+        self.set_volatiles(&from, &commands);
+        self.bots[bot_index].next_commands = commands;
+        Some(dug.is_some())
+    }
+    // void_candidatesから今消しても大丈夫なものを1つ選択
+    fn select_one_void_candidate(&mut self, from: &Position) -> Option<Position> {
+        // 上にあるものから順に消す
+        let mut order = vec![];
+        for (i, c) in self.void_candidates.iter().enumerate() {
+            let mut score = ((*from - c).manhattan_length() + 2) / 5 * self.distance_weight;
+            score -= c.y * self.height_weight;
+            order.push((score, i));
+        }
+        for o in order.iter_mut() {
+            o.0 += self.rng.gen_range(0, self.noise);
+        }
+        order.sort();
+        // 全部調べると重いので上位だけ
+        for &(_, i) in order.iter().take(8) {
+            let c = self.void_candidates[i];
+            if self.can_void(&c) {
+                self.void_candidates.remove(i);
+                return Some(c);
+            }
+        }
+        None
     }
     // fromからtoをfillするコマンドを発行
     // fromとtoはncdの距離
@@ -256,6 +525,12 @@ impl BfsAI {
             }
         }
     }
+    // fromの真上にcandidateがあればtrue
+    fn is_under_candidate(&self, from: &Position) -> bool {
+        self.candidates
+            .iter()
+            .any(|c| c.x == from.x && c.z == from.z && c.y > from.y)
+    }
     fn make_up_or_random_move_command(&mut self, from: &Position) -> Command {
         let llcd = LLCD::new(0, 1, 0);
         let to = *from + &llcd;
@@ -390,16 +665,30 @@ impl BfsAI {
                 }
                 ret
             }
-            Command::LMove(_slcd1, _slcd2) => {
-                unimplemented!();
+            Command::LMove(slcd1, slcd2) => {
+                let mut ret = vec![];
+                let mid = *from + slcd1;
+                let to = mid + slcd2;
+                for p in Region(*from, mid).iter().chain(Region(mid, to).iter()) {
+                    if p == *from || ret.contains(&p) {
+                        continue;
+                    }
+                    ret.push(p);
+                }
+                ret
             }
             Command::Fission(ncd, _) => vec![*from + ncd],
             Command::Fill(ncd) => vec![*from + ncd],
             Command::Void(_) => vec![], // Fullのvoxelはもともとvolatile扱いにするので
             Command::FusionP(_) => vec![],
             Command::FusionS(_) => vec![],
-            Command::GFill(_, _) => {
-                unimplemented!();
+            Command::GFill(ncd, fcd) => {
+                // 同じグループの他のbotが先に設定していることがある
+                let region = Region(*from + ncd, *from + ncd + fcd);
+                region
+                    .iter()
+                    .filter(|p| !self.volatiles.contains(p))
+                    .collect()
             }
             Command::GVoid(_, _) => vec![],
         };
@@ -428,8 +717,17 @@ impl BfsAI {
                 }
                 ret
             }
-            Command::LMove(_slcd1, _slcd2) => {
-                unimplemented!();
+            Command::LMove(slcd1, slcd2) => {
+                let mut ret = vec![];
+                let mid = *from + slcd1;
+                let to = mid + slcd2;
+                for p in Region(*from, mid).iter().chain(Region(mid, to).iter()) {
+                    if p == to || ret.contains(&p) {
+                        continue;
+                    }
+                    ret.push(p);
+                }
+                ret
             }
             Command::Fission(_, _) => vec![],
             Command::Fill(_) => vec![], // Fullになるので何もしなくてよい
@@ -437,8 +735,13 @@ impl BfsAI {
             Command::FusionP(_) => vec![],
             Command::FusionS(_) => vec![*from],
             Command::GFill(_, _) => vec![],
-            Command::GVoid(_ncd, _fcd) => {
-                unimplemented!();
+            Command::GVoid(ncd, fcd) => {
+                // 同じグループの他のbotが先に解除していることがある
+                let region = Region(*from + ncd, *from + ncd + fcd);
+                region
+                    .iter()
+                    .filter(|p| self.volatiles.contains(p))
+                    .collect()
             }
         };
         // println!("{:?} {:?}", from, command);
@@ -476,7 +779,7 @@ impl BfsAI {
             }
             Command::Wait => {}
             Command::Flip => {
-                unimplemented!();
+                // harmonicsはstateの方で管理している
             }
             Command::SMove(llcd) => {
                 self.bots[bot_index].bot.pos = from + &llcd;
@@ -495,9 +798,8 @@ impl BfsAI {
             }
             Command::Void(ncd) => {
                 let to = from + &ncd;
-                self.current.set_voxel_at(to, Voxel::Full);
-                // TODO update_void_candidateを実装する
-                // TODO 穴ほって移動する場合にどうするか考える
+                self.current.set_voxel_at(to, Voxel::Void);
+                self.voiding.remove(&to);
             }
             Command::FusionP(_) => {
                 let s_bid = self.bots[bot_index].fusions_bid.unwrap();
@@ -514,11 +816,21 @@ impl BfsAI {
             Command::FusionS(_) => {
                 // FusionPの方で処理するので何もしなくてよい
             }
-            Command::GFill(_, _) => {
-                unimplemented!();
+            Command::GFill(ncd, fcd) => {
+                let region = Region(from + &ncd, from + &ncd + &fcd);
+                for p in region.iter() {
+                    if self.current.voxel_at(p) == Voxel::Void {
+                        self.current.set_voxel_at(p, Voxel::Full);
+                        self.update_full_candidate(&p);
+                    }
+                }
             }
-            Command::GVoid(_ncd, _fcd) => {
-                unimplemented!();
+            Command::GVoid(ncd, fcd) => {
+                let region = Region(from + &ncd, from + &ncd + &fcd);
+                for p in region.iter() {
+                    self.current.set_voxel_at(p, Voxel::Void);
+                    self.voiding.remove(&p);
+                }
             }
         }
     }
@@ -559,16 +871,30 @@ impl BfsAI {
     }
 }
 
-impl AssembleAI for BfsAI {
-    fn assemble(&mut self, _model: &Model) -> Vec<Command> {
+impl BfsAI {
+    // 床か、targetでもFullになっているvoxelに接していれば置ける
+    fn is_initial_candidate(&self, p: &Position) -> bool {
+        if self.target.voxel_at(*p) == Voxel::Void || self.current.voxel_at(*p) == Voxel::Full {
+            return false;
+        }
+        p.y == 0 || adjacent(*p).iter().any(|n| {
+            self.is_valid_coordinate(n)
+                && self.current.voxel_at(*n) == Voxel::Full
+                && self.target.voxel_at(*n) == Voxel::Full
+        })
+    }
+    // 詰んだら None
+    fn solve(&mut self) -> Option<Vec<Command>> {
         let r = self.target.matrix.len();
         // 次に置ける候補
         for x in 0..r {
-            for z in 0..r {
-                let p = Position::new(x as i32, 0, z as i32);
-                if self.target.voxel_at(p) == Voxel::Full {
-                    self.candidates.push(p);
-                    self.visited.insert(p);
+            for y in 0..r {
+                for z in 0..r {
+                    let p = Position::new(x as i32, y as i32, z as i32);
+                    if self.is_initial_candidate(&p) {
+                        self.candidates.push(p);
+                        self.visited.insert(p);
+                    }
                 }
             }
         }
@@ -598,7 +924,37 @@ impl AssembleAI for BfsAI {
         }
         // ブロック埋め
         let mut ng_count = 0;
-        while self.candidates.len() > 0 || self.is_all_bot_command_done() {
+        loop {
+            // Fullのvoxelに閉じ込められたbotは掘って出る。行けない候補があれば掘って入る。
+            // 閉じ込められたbotも行けない候補もなくなったら、掘ったvoxelを埋め直す
+            let open = self.open_cells();
+            let enclosed = self
+                .bots
+                .iter()
+                .any(|b| !open[b.bot.pos.index(r)]);
+            let pockets = self.pocket_cells(&open);
+            let has_pocket = pockets.iter().any(|&p| p);
+            if !enclosed && !has_pocket {
+                for p in self.dug.clone() {
+                    if self.current.voxel_at(p) == Voxel::Void {
+                        self.dug.retain(|q| *q != p);
+                        self.candidates.push(p);
+                        self.visited.insert(p);
+                    }
+                }
+            }
+            if self.candidates.is_empty()
+                && self.void_candidates.is_empty()
+                && !self.is_all_bot_command_done()
+                && !enclosed
+            {
+                break;
+            }
+            if self.dig_count > r * r {
+                // 掘っては埋めを繰り返している
+                return None;
+            }
+            let mut pocket_digger = false;
             // println!("All Candidate: {}", self.visited.len());
             // println!("Rest Candidate: {}", self.candidates.len());
             // 1 time step 実行
@@ -608,6 +964,23 @@ impl AssembleAI for BfsAI {
                 }
 
                 let from = self.bots[i].bot.pos;
+                // 消さなければいけないvoxelを先に処理する
+                if let Some(to) = self.select_one_void_candidate(&from) {
+                    match self.make_target_void_command(&from, &to) {
+                        None => {
+                            // 行けない
+                            self.void_candidates.push(to);
+                        }
+                        Some(commands) => {
+                            ng_count = 0;
+                            self.voiding.insert(to);
+                            let commands = commands.into_iter().collect();
+                            self.set_volatiles(&from, &commands);
+                            self.bots[i].next_commands = commands;
+                            continue;
+                        }
+                    }
+                }
                 // candidateから1個取って処理する
                 let to = self.select_one_candidate(&from);
                 if to.is_some() {
@@ -627,6 +1000,30 @@ impl AssembleAI for BfsAI {
                     }
                 }
 
+                // 閉じ込められていれば、一番上から行ける位置まで掘る
+                if self.bots[i].next_commands.len() == 0 && !open[from.index(r)] {
+                    if let Some(dug) = self.dig(i, &open) {
+                        if dug {
+                            ng_count = 0;
+                        }
+                        continue;
+                    }
+                }
+                // しばらく何もできていなければ、一番上から行けない候補に向かって 1 step に 1 体だけ掘る
+                if self.bots[i].next_commands.len() == 0
+                    && has_pocket
+                    && ng_count > DIG_PATIENCE
+                    && !pocket_digger
+                {
+                    pocket_digger = true;
+                    if let Some(dug) = self.dig(i, &pockets) {
+                        if dug {
+                            ng_count = 0;
+                        }
+                        continue;
+                    }
+                }
+
                 // なんか失敗した場合
                 if self.bots[i].next_commands.len() == 0 {
                     ng_count += 1;
@@ -639,10 +1036,14 @@ impl AssembleAI for BfsAI {
                 }
 
                 // やる事がない場合は上優先でランダムムーブする
+                // 真上にcandidateがあるときは、どかないと誰も埋められないので上を優先しない
                 if self.bots[i].next_commands.len() == 0 {
-                    let commands = vec![self.make_up_or_random_move_command(&from)]
-                        .into_iter()
-                        .collect();
+                    let command = if self.is_under_candidate(&from) {
+                        self.make_random_move_command(&from)
+                    } else {
+                        self.make_up_or_random_move_command(&from)
+                    };
+                    let commands = vec![command].into_iter().collect();
                     self.set_volatiles(&from, &commands);
                     self.bots[i].next_commands = commands;
                 }
@@ -651,11 +1052,7 @@ impl AssembleAI for BfsAI {
             // println!("{}", ng_count);
             if ng_count >= 1000 {
                 // 詰んだっぽい
-                // TODO
-                println!("Give Up fill!");
-                println!("All Candidate: {}", self.visited.len());
-                println!("Rest Candidate: {}", self.candidates.len());
-                return vec![];
+                return None;
             }
         }
         //  集合
@@ -663,10 +1060,7 @@ impl AssembleAI for BfsAI {
         while self.bots.len() > 1 {
             if ng_count > 100 {
                 // 詰んだっぽい
-                println!("Give Up fusion!");
-                println!("All Candidate: {}", self.visited.len());
-                println!("Rest Candidate: {}", self.candidates.len());
-                return vec![];
+                return None;
             }
             for s_index in 0..self.bots.len() {
                 if self.bots[s_index].next_commands.len() == 0 {
@@ -696,17 +1090,31 @@ impl AssembleAI for BfsAI {
         }
         // (0, 0, 0)に戻る
         {
-            let mut commands = match self.make_return_command() {
-                None => {
-                    println!("Give Up return!");
-                    return vec![];
-                }
-                Some(commands) => commands,
-            };
+            let mut commands = self.make_return_command()?;
             self.trace.append(&mut commands);
         }
         // println!("{} {:?}", self.trace.len(), self.trace);
-        self.trace.clone()
+        Some(self.trace.clone())
+    }
+}
+
+// 詰んだときは途中までの trace を返すので、State の検査で失敗する。
+// dflt の trace に切り替えるのは呼び出し側 (anytime.rs) で、失敗したことも報告される。
+impl AssembleAI for BfsAI {
+    fn assemble(&mut self, _model: &Model) -> Vec<Command> {
+        match self.solve() {
+            Some(trace) => trace,
+            None => self.trace.clone(),
+        }
+    }
+}
+
+impl ReassembleAI for BfsAI {
+    fn reassemble(&mut self, _source: &Model, _target: &Model) -> Vec<Command> {
+        match self.solve() {
+            Some(trace) => trace,
+            None => self.trace.clone(),
+        }
     }
 }
 
@@ -716,19 +1124,26 @@ fn select_one_candidate_test() {
     let config = Config::new();
     {
         let mut bfs_ai = BfsAI::new(&config, &model, &model);
-        let c = Position::new(1, 1, 1);
+        let c = Position::new(1, 0, 1);
         bfs_ai.candidates.push(c);
         let p = bfs_ai.select_one_candidate(&Position::zero()).unwrap();
         assert_eq!(p, c);
     }
     {
         let mut bfs_ai = BfsAI::new(&config, &model, &model);
-        let c = Position::new(1, 1, 1);
+        let c = Position::new(1, 0, 1);
         bfs_ai.candidates.push(c);
         bfs_ai.volatiles.insert(c);
         let result = bfs_ai.select_one_candidate(&c);
         assert!(result.is_none());
     }
+    {
+        // 宙に浮いた candidate は埋めない
+        let mut bfs_ai = BfsAI::new(&config, &model, &model);
+        bfs_ai.candidates.push(Position::new(1, 1, 1));
+        let result = bfs_ai.select_one_candidate(&Position::zero());
+        assert!(result.is_none());
+    }
 }
 
 #[test]
@@ -757,30 +1172,6 @@ fn pos_ncd_all_test() {
         assert_eq!(poss.len(), 13);
     }
 }
-#[test]
-fn pos_smove_all_test() {
-    let model = Model::initial(100);
-    let config = Config::new();
-    let mut bfs_ai = BfsAI::new(&config, &model, &model);
-    {
-        let llcds = bfs_ai.pos_smove_all(&Position::new(30, 30, 30), 15);
-        assert_eq!(llcds.len(), 90);
-    }
-    {
-        let llcds = bfs_ai.pos_smove_all(&Position::new(1, 1, 1), 15);
-        assert_eq!(llcds.len(), 48);
-    }
-    {
-        let llcds = bfs_ai.pos_smove_all(&Position::new(98, 98, 98), 15);
-        assert_eq!(llcds.len(), 48);
-    }
-    {
-        bfs_ai.volatiles.insert(Position::new(28, 30, 30));
-        let llcds = bfs_ai.pos_smove_all(&Position::new(30, 30, 30), 15);
-        assert_eq!(llcds.len(), 76);
-    }
-}
-
 #[test]
 fn make_fill_command_test() {
     let model = Model::initial(3);
@@ -821,7 +1212,12 @@ fn make_move_any_command_test() {
             .make_move_any_command(&Position::zero(), &vec![to])
             .unwrap();
         assert_eq!(pos, to);
-        assert_eq!(commands.len(), 3);
+        // LMove と SMove の 2 手で行ける
+        assert_eq!(commands.len(), 2);
+        assert!(commands.iter().any(|c| match c {
+            Command::LMove(_, _) => true,
+            _ => false,
+        }));
     }
     {
         let to = Position::new(3, 3, 3);
@@ -941,3 +1337,81 @@ fn do_command_test() {
         // assert_eq!(bfs_ai.volatiles.len(), 2 + 1);
     }
 }
+
+#[test]
+fn lmove_setunset_volatiles_test() {
+    let model = Model::initial(30);
+    let config = Config::new();
+    let mut bfs_ai = BfsAI::new(&config, &model, &model);
+    let command = Command::LMove(SLCD::new(3, 0, 0), SLCD::new(0, 0, 2));
+    bfs_ai.set_volatile(&Position::zero(), &command);
+    assert_eq!(bfs_ai.volatiles.len(), 1 + 3 + 2);
+    bfs_ai.unset_volatile(&Position::zero(), &command);
+    assert_eq!(bfs_ai.volatiles.len(), 1);
+    assert!(bfs_ai.volatiles.contains(&Position::new(3, 0, 2)));
+}
+
+#[test]
+fn can_void_test() {
+    let mut source = Model::initial(5);
+    source.matrix[2][0][2] = Voxel::Full;
+    source.matrix[2][1][2] = Voxel::Full;
+    let target = Model::initial(5);
+    let config = Config::new();
+    let mut bfs_ai = BfsAI::new(&config, &source, &target);
+    assert_eq!(bfs_ai.void_candidates.len(), 2);
+    // 下を先に消すと上が浮く
+    assert!(!bfs_ai.can_void(&Position::new(2, 0, 2)));
+    assert!(bfs_ai.can_void(&Position::new(2, 1, 2)));
+    let p = bfs_ai.select_one_void_candidate(&Position::zero()).unwrap();
+    assert_eq!(p, Position::new(2, 1, 2));
+    // 上を消しにいっている途中でも、下は消せない
+    bfs_ai.voiding.insert(p);
+    assert!(!bfs_ai.can_void(&Position::new(2, 0, 2)));
+}
+
+#[test]
+fn reassemble_test() {
+    let mut source = Model::initial(5);
+    let mut target = Model::initial(5);
+    for y in 0..3 {
+        source.matrix[1][y][1] = Voxel::Full;
+        target.matrix[3][y][3] = Voxel::Full;
+    }
+    target.matrix[1][0][1] = Voxel::Full;
+    let config = Config::new();
+    let mut bfs_ai = BfsAI::new(&config, &source, &target);
+    let trace = bfs_ai.reassemble(&source, &target);
+    let mut state = State::initial_with_model(&source);
+    state.execute_trace(&trace).unwrap();
+    state.end_check(&target).unwrap();
+}
+
+#[test]
+fn give_up_test() {
+    use generator::{generate, generate_pair, Shape};
+    // どれも、閉じ込められた bot が掘って出るか、最後の candidate の真下から bot がどかないと詰む。
+    // 詰んでも dflt には切り替えないので、BfsAI 自身の trace が通ることを確かめている。
+    let config = Config::new();
+    let shapes = [(Shape::Tower, 1), (Shape::Arch, 3), (Shape::HollowShell, 3)];
+    for &(shape, seed) in shapes.iter() {
+        let target = generate(shape, 12, seed);
+        let source = Model::initial(12);
+        let trace = BfsAI::new(&config, &source, &target).assemble(&target);
+        let mut state = State::initial(12);
+        state.execute_trace(&trace).unwrap();
+        state.end_check(&target).unwrap();
+    }
+    let pairs = [
+        (Shape::HollowShell, Shape::Tower, 1),
+        (Shape::Pillars, Shape::HollowShell, 0),
+        (Shape::Arch, Shape::Arch, 1),
+    ];
+    for &(s, t, seed) in pairs.iter() {
+        let (source, target) = generate_pair(s, t, 12, seed, 0.5);
+        let trace = BfsAI::new(&config, &source, &target).reassemble(&source, &target);
+        let mut state = State::initial_with_model(&source);
+        state.execute_trace(&trace).unwrap();
+        state.end_check(&target).unwrap();
+    }
+}
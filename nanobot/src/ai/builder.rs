@@ -1,8 +1,15 @@
 use ai::bfs::BfsAI;
-use ai::config::Config;
+use ai::config::{Config, Param};
+use ai::cuboid::CuboidAI;
+use ai::dflt::DfltAI;
+use ai::diff_reassemble::DiffReassembleAI;
 use ai::grid_fission::GridFissionAI;
+use ai::layer_fill::LayerFillAI;
+use ai::layer_void::LayerVoidAI;
 use ai::naive_reassemble::NaiveReassembleAI;
 use ai::reassemble_brute_force::ReassembleBruteForceAI;
+use ai::reverse::*;
+use ai::symmetric::*;
 use ai::void::VoidAI;
 use ai::void_assemble::VoidAssembleAI;
 use ai::gvoid::GvoidAI;
@@ -14,6 +21,35 @@ use model::Model;
 
 use std::process;
 
+// 登録されている AI の名前。テストハーネスは harness.rs の KNOWN_FAILURES にあるもの以外を全部試す。
+// reverse_ で始まるものは、残りの名前の AI の trace を反転して使う。
+// symmetric_ で始まるものは、対称変換した問題を残りの名前の AI で解いて一番良いものを使う。
+pub const ASSEMBLER_NAMES: &[&str] = &[
+    "default",
+    "kichi",
+    "bfs",
+    "cuboid",
+    "layer_fill",
+    "dflt",
+    "reverse_default",
+    "reverse_gvoid",
+    "reverse_gvoid_2d",
+    "symmetric_default",
+    "symmetric_kichi",
+];
+pub const DISASSEMBLER_NAMES: &[&str] = &[
+    "default",
+    "gvoid",
+    "gvoid_2d",
+    "layer_void",
+    "dflt",
+    "reverse_default",
+    "reverse_kichi",
+    "reverse_bfs",
+    "symmetric_default",
+];
+pub const REASSEMBLER_NAMES: &[&str] = &["default", "bruteforce", "bfs", "diff", "concurrent", "dflt", "symmetric_bfs"];
+
 pub fn build_assembler(name: &String, config: &Config, target: &Model) -> Box<AssembleAI> {
     let r = target.matrix.len();
     let source = Model::initial(r);
@@ -21,20 +57,45 @@ pub fn build_assembler(name: &String, config: &Config, target: &Model) -> Box<As
         "default" => Box::new(GridFissionAI::new(config)),
         "kichi" => Box::new(VoidAssembleAI::new(config)),
         "bfs" => Box::new(BfsAI::new(config, &source, &target)),
+        "cuboid" => Box::new(CuboidAI::new(config)),
+        "layer_fill" => Box::new(LayerFillAI::new(config)),
+        "dflt" => Box::new(DfltAI::new(config)),
+        "reverse_default" | "reverse_gvoid" | "reverse_gvoid_2d" => Box::new(
+            ReverseAssembleAI::new(&name["reverse_".len()..].to_string(), config, target),
+        ),
+        "symmetric_default" | "symmetric_kichi" => Box::new(SymmetricAssembleAI::new(
+            &name["symmetric_".len()..],
+            config,
+        )),
         _ => {
-            eprintln!("failed to build assembler AI (name = {})", name);
+            eprintln!(
+                "failed to build assembler AI (name = {}, available = {:?})",
+                name, ASSEMBLER_NAMES
+            );
             process::exit(1);
         }
     }
 }
 
-pub fn build_disassembler(name: &String, config: &Config, _source: &Model) -> Box<DisassembleAI> {
+pub fn build_disassembler(name: &String, config: &Config, source: &Model) -> Box<DisassembleAI> {
     match name.as_str() {
         "default" => Box::new(VoidAI::new(config)),
         "gvoid" => Box::new(GvoidAI::new(config)),
         "gvoid_2d" => Box::new(Gvoid2dAI::new(config)),
+        "layer_void" => Box::new(LayerVoidAI::new(config)),
+        "dflt" => Box::new(DfltAI::new(config)),
+        "reverse_default" | "reverse_kichi" | "reverse_bfs" => Box::new(
+            ReverseDisassembleAI::new(&name["reverse_".len()..].to_string(), config, source),
+        ),
+        "symmetric_default" => Box::new(SymmetricDisassembleAI::new(
+            &name["symmetric_".len()..],
+            config,
+        )),
         _ => {
-            eprintln!("failed to build assembler AI (name = {})", name);
+            eprintln!(
+                "failed to build assembler AI (name = {}, available = {:?})",
+                name, DISASSEMBLER_NAMES
+            );
             process::exit(1);
         }
     }
@@ -49,9 +110,69 @@ pub fn build_reassembler(
     match name.as_str() {
         "default" => Box::new(NaiveReassembleAI::new(config, source, target)),
         "bruteforce" => Box::new(ReassembleBruteForceAI::new(config, source, target)),
+        "bfs" => Box::new(BfsAI::new(config, source, target)),
+        "diff" => Box::new(DiffReassembleAI::new(config)),
+        "concurrent" => Box::new(DiffReassembleAI::concurrent(config)),
+        "dflt" => Box::new(DfltAI::new(config)),
+        "symmetric_bfs" => Box::new(SymmetricReassembleAI::new(
+            &name["symmetric_".len()..],
+            config,
+        )),
         _ => {
-            eprintln!("failed to build assembler AI (name = {})", name);
+            eprintln!(
+                "failed to build assembler AI (name = {}, available = {:?})",
+                name, REASSEMBLER_NAMES
+            );
             process::exit(1);
         }
     }
 }
+
+// name の AI が読む、チューニングできるパラメータ。
+// reverse_ と symmetric_ で始まるものは、中で使う AI のパラメータを返す。
+pub fn assembler_params(name: &str, config: &Config) -> Vec<Param> {
+    match name {
+        "default" => GridFissionAI::params(),
+        "kichi" => VoidAssembleAI::params(),
+        "bfs" => BfsAI::params(),
+        "layer_fill" => LayerFillAI::params(),
+        _ if name.starts_with("reverse_") => disassembler_params(&name["reverse_".len()..], config),
+        _ if name.starts_with("symmetric_") => {
+            assembler_params(&name["symmetric_".len()..], config)
+        }
+        _ => vec![],
+    }
+}
+
+pub fn disassembler_params(name: &str, config: &Config) -> Vec<Param> {
+    match name {
+        "default" => VoidAI::params(),
+        "layer_void" => LayerVoidAI::params(),
+        _ if name.starts_with("reverse_") => assembler_params(&name["reverse_".len()..], config),
+        _ if name.starts_with("symmetric_") => {
+            disassembler_params(&name["symmetric_".len()..], config)
+        }
+        _ => vec![],
+    }
+}
+
+pub fn reassembler_params(name: &str, config: &Config) -> Vec<Param> {
+    match name {
+        // NaiveReassembleAI は config の assembler と disassembler を使う
+        "default" => {
+            let mut params = disassembler_params(&config.disassembler, config);
+            for p in assembler_params(&config.assembler, config) {
+                if params.iter().all(|q| q.name != p.name) {
+                    params.push(p);
+                }
+            }
+            params
+        }
+        "bfs" => BfsAI::params(),
+        "diff" | "concurrent" => DiffReassembleAI::params(),
+        _ if name.starts_with("symmetric_") => {
+            reassembler_params(&name["symmetric_".len()..], config)
+        }
+        _ => vec![],
+    }
+}
@@ -1,24 +1,142 @@
+use std::cmp::{max, min};
 use std::env;
 
+#[derive(Clone)]
 pub struct Config {
     // for NaiveReassembleAI
     pub assembler: String,
     pub disassembler: String,
     // for VoidAI, GridFissionAI
     pub dry_run_max_resolution: i32,
+    // for BfsAI
+    pub seed: u64,
+    // for BfsAI (次に埋める voxel を選ぶときの、距離と高さの重みと乱数の幅)
+    pub bfs_distance_weight: i32,
+    pub bfs_height_weight: i32,
+    pub bfs_noise: i32,
+    // for VoidAI, GridFissionAI (足場を分ける長方形の数の上限)
+    pub max_regions: usize,
+    // for LayerFillAI, LayerVoidAI, DiffReassembleAI (同時に扱う長方形の数の上限)
+    pub max_groups: usize,
+}
+
+// 環境変数 param.name の値。なければ、または読めなければ default。
+// 範囲の外の値は AI が panic することがあるので (乱数の幅が 0 など)、param の min と max に収める。
+fn env_param(param: &Param, default: i64) -> i64 {
+    parse_param(param, env::var(param.name).ok(), default)
+}
+
+fn parse_param(param: &Param, value: Option<String>, default: i64) -> i64 {
+    let value = value.and_then(|s| s.parse::<i64>().ok()).unwrap_or(default);
+    min(max(value, param.min), param.max)
 }
 
 impl Config {
     pub fn new() -> Self {
-        let dry_run_max_resolution = env::var("GOLD_DRY_RUN_MAX_RESOLUTION")
-            .unwrap_or(String::from("30"))
-            .parse::<i32>()
+        let seed = env::var("GOLD_SEED")
+            .unwrap_or(String::from("0"))
+            .parse::<u64>()
             .unwrap_or(0);
 
-        Config {
+        let mut config = Config {
             assembler: env::var("GOLD_ASSEMBLER").unwrap_or(String::from("")),
             disassembler: env::var("GOLD_DISASSEMBLER").unwrap_or(String::from("")),
-            dry_run_max_resolution,
+            dry_run_max_resolution: 30,
+            seed,
+            bfs_distance_weight: 100,
+            bfs_height_weight: 1000,
+            bfs_noise: 130,
+            max_regions: 40,
+            max_groups: 9,
+        };
+        // 上の既定値を環境変数の値で置き換える
+        for param in PARAMS.iter() {
+            let value = env_param(param, (param.get)(&config));
+            (param.set)(&mut config, value);
         }
+        config
     }
 }
+
+// AI が読む数値のパラメータ。name は同じ値を指定するときの環境変数の名前。
+// tuning.rs は min から max までの値を試して、get と set で Config を読み書きする。
+#[derive(Clone, Copy)]
+pub struct Param {
+    pub name: &'static str,
+    pub min: i64,
+    pub max: i64,
+    pub get: fn(&Config) -> i64,
+    pub set: fn(&mut Config, i64),
+}
+
+pub const DRY_RUN_MAX_RESOLUTION: Param = Param {
+    name: "GOLD_DRY_RUN_MAX_RESOLUTION",
+    min: 0,
+    max: 250,
+    get: |c| c.dry_run_max_resolution as i64,
+    set: |c, v| c.dry_run_max_resolution = v as i32,
+};
+
+pub const BFS_DISTANCE_WEIGHT: Param = Param {
+    name: "GOLD_BFS_DISTANCE_WEIGHT",
+    min: 0,
+    max: 1000,
+    get: |c| c.bfs_distance_weight as i64,
+    set: |c, v| c.bfs_distance_weight = v as i32,
+};
+
+pub const BFS_HEIGHT_WEIGHT: Param = Param {
+    name: "GOLD_BFS_HEIGHT_WEIGHT",
+    min: 0,
+    max: 5000,
+    get: |c| c.bfs_height_weight as i64,
+    set: |c, v| c.bfs_height_weight = v as i32,
+};
+
+pub const BFS_NOISE: Param = Param {
+    name: "GOLD_BFS_NOISE",
+    min: 1,
+    max: 1000,
+    get: |c| c.bfs_noise as i64,
+    set: |c, v| c.bfs_noise = v as i32,
+};
+
+pub const MAX_REGIONS: Param = Param {
+    name: "GOLD_MAX_REGIONS",
+    min: 1,
+    max: 40,
+    get: |c| c.max_regions as i64,
+    set: |c, v| c.max_regions = v as usize,
+};
+
+pub const MAX_GROUPS: Param = Param {
+    name: "GOLD_MAX_GROUPS",
+    min: 1,
+    max: 9,
+    get: |c| c.max_groups as i64,
+    set: |c, v| c.max_groups = v as usize,
+};
+
+// 環境変数で指定できる全部のパラメータ
+pub const PARAMS: &[Param] = &[
+    DRY_RUN_MAX_RESOLUTION,
+    BFS_DISTANCE_WEIGHT,
+    BFS_HEIGHT_WEIGHT,
+    BFS_NOISE,
+    MAX_REGIONS,
+    MAX_GROUPS,
+];
+
+#[test]
+fn test_parse_param() {
+    let value = |param: &Param, s: &str| parse_param(param, Some(s.to_string()), 130);
+    assert_eq!(value(&BFS_NOISE, "200"), 200);
+    // 乱数の幅が 0 以下だと gen_range が panic する
+    assert_eq!(value(&BFS_NOISE, "0"), 1);
+    assert_eq!(value(&BFS_NOISE, "-5"), 1);
+    assert_eq!(value(&BFS_NOISE, "100000"), 1000);
+    assert_eq!(value(&BFS_NOISE, "abc"), 130);
+    assert_eq!(parse_param(&BFS_NOISE, None, 130), 130);
+    // 長方形が 0 個では足場を分けられない
+    assert_eq!(value(&MAX_REGIONS, "0"), 1);
+}
@@ -0,0 +1,269 @@
+use ai::config::Config;
+use ai::dflt::dflt_trace;
+use ai::planner::*;
+use ai::AssembleAI;
+use common::*;
+use geometry::*;
+use model::*;
+use std::collections::HashSet;
+
+// target を直方体に分けて、直方体ごとに GFill で一度に埋める AI。
+//
+// 直方体の角の数 (1, 2, 4, 8) だけ bot を角の近くに集めて、対応する GFill を同時に出す。
+// 直方体は床か埋め終わった voxel に接しているものから埋めるので、harmonics は常に Low のまま。
+// 直方体は 1 つずつ埋めるが、bot の移動は経路が重ならない限り同時に行う。
+
+const BOT_COUNT: usize = 8;
+// (bot, 角を埋める位置, 角)
+type Assignment = Vec<(usize, Position, Position)>;
+
+pub struct CuboidAI {}
+
+impl CuboidAI {
+    pub fn new(_config: &Config) -> Self {
+        CuboidAI {}
+    }
+}
+
+impl AssembleAI for CuboidAI {
+    fn assemble(&mut self, model: &Model) -> Vec<Command> {
+        let r = model.matrix.len();
+        let boxes = order_grounded(&Model::initial(r), &decompose(model, Cover::Exact));
+        if boxes.is_empty() {
+            return vec![Command::Halt];
+        }
+        let mut planner = Planner::new(&Model::initial(r));
+        let planned = planner
+            .spawn(BOT_COUNT)
+            .and_then(|_| planner.fill_all(boxes))
+            .and_then(|_| planner.fuse_home());
+        match planned {
+            Some(()) => planner.trace,
+            None => dflt_trace(&Model::initial(r), model),
+        }
+    }
+}
+
+impl Planner {
+    // 床か Full の voxel に接していれば、埋めても浮かない
+    fn is_supported(&self, region: &Region) -> bool {
+        is_grounded(&self.current, region)
+    }
+
+    // 角ごとに、角を埋めに行く bot とその位置を決める。
+    // region を埋めた状態で呼び、埋めた後に閉じ込められる位置 (open にない位置) は使わない。
+    fn assign_corners(
+        &self,
+        region: &Region,
+        open: &HashSet<Position>,
+        banned: &HashSet<Position>,
+    ) -> Option<Assignment> {
+        let corners = corners(region);
+        let reachable = (0..self.bots.len())
+            .map(|i| self.reachable(i))
+            .collect::<Vec<_>>();
+        let mut candidates = vec![];
+        for (ci, c) in corners.iter().enumerate() {
+            for ncd in all_ncd() {
+                let q = *c - &ncd;
+                if region.contains(q) || banned.contains(&q) || !open.contains(&q) {
+                    continue;
+                }
+                for (bi, b) in self.bots.iter().enumerate() {
+                    if !reachable[bi].contains(&q) {
+                        continue;
+                    }
+                    candidates.push(((*b - &q).manhattan_length(), bi, ci, q));
+                }
+            }
+        }
+        candidates.sort();
+        let mut assigned: Vec<Option<(usize, Position)>> = vec![None; corners.len()];
+        let mut used_bots = HashSet::new();
+        let mut used_positions = HashSet::new();
+        for &(_, bi, ci, q) in candidates.iter() {
+            if assigned[ci].is_some() || used_bots.contains(&bi) || used_positions.contains(&q) {
+                continue;
+            }
+            // 他の bot が立っている位置はその bot しか使えない
+            if self.bots.iter().enumerate().any(|(j, b)| j != bi && *b == q) {
+                continue;
+            }
+            assigned[ci] = Some((bi, q));
+            used_bots.insert(bi);
+            used_positions.insert(q);
+        }
+        let mut ret = vec![];
+        for (ci, a) in assigned.into_iter().enumerate() {
+            let (bi, q) = a?;
+            ret.push((bi, q, corners[ci]));
+        }
+        Some(ret)
+    }
+
+    // region を埋めるときの、角を埋める bot とその位置、全部の bot の行き先を決める。
+    // open は region を埋めた後に一番上の面から行ける位置で、banned の位置は角を埋めるのに使わない。
+    fn plan_fill(
+        &mut self,
+        region: &Region,
+        open: &HashSet<Position>,
+        banned: &HashSet<Position>,
+    ) -> Option<(Assignment, Vec<(usize, Position)>)> {
+        self.set_region(region, Voxel::Full);
+        let assignment = self.assign_corners(region, open, banned);
+        // 埋めると閉じ込められる bot と、直方体の中にいる bot は動かす
+        let mut evacuees = (0..self.bots.len())
+            .filter(|&i| match assignment {
+                Some(ref a) => a.iter().all(|g| g.0 != i),
+                None => false,
+            })
+            .filter(|&i| region.contains(self.bots[i]) || !open.contains(&self.bots[i]))
+            .collect::<Vec<_>>();
+        self.set_region(region, Voxel::Void);
+        let assignment = assignment?;
+        // 出口に近い bot から外に出す
+        let top = self.r as i32 - 1;
+        evacuees.sort_by_key(|&i| {
+            self.find_path(&self.bots[i], &HashSet::new(), |q| q.y == top, |q| top - q.y)
+                .map_or(0, |path| path.len())
+        });
+
+        let mut goals = assignment
+            .iter()
+            .map(|&(bi, q, _)| (bi, q))
+            .collect::<Vec<_>>();
+        let mut reserved = goals.iter().map(|g| g.1).collect::<HashSet<_>>();
+        let cells = region.iter().collect::<HashSet<_>>();
+        let movers = goals
+            .iter()
+            .map(|g| g.0)
+            .chain(evacuees.iter().cloned())
+            .collect::<Vec<_>>();
+        for (k, &i) in evacuees.iter().enumerate() {
+            // 埋めた後も外に出られて、まだ動かしていない bot の出口をふさがない一番近い位置に動かす
+            let is_goal = |p: &Position| {
+                if cells.contains(p) || !open.contains(p) {
+                    return false;
+                }
+                let mut blocked = reserved.clone();
+                blocked.insert(*p);
+                evacuees[k + 1..]
+                        .iter()
+                        .all(|&j| self.can_escape(&self.bots[j], &blocked))
+            };
+            let goal = self.find_goal(i, &movers, &reserved, is_goal)?;
+            reserved.insert(goal);
+            goals.push((i, goal));
+        }
+        Some((assignment, goals))
+    }
+
+    // region を埋められれば埋めて true を返す。
+    // rest はまだ埋めていない直方体で、region を埋めるとそこに行けなくなるなら埋めない。
+    // 角を埋める bot が他の bot の出口をふさぐときは、その位置を使わずにもう一度試す。
+    // 動かし始めてからコマンドが実行できなくなれば None
+    fn try_fill(&mut self, region: &Region, rest: &[Region]) -> Option<bool> {
+        self.set_region(region, Voxel::Full);
+        let open = self.open_cells();
+        self.set_region(region, Voxel::Void);
+        if rest.iter().any(|b| b.iter().any(|p| !open.contains(&p))) {
+            return Some(false);
+        }
+
+        let mut banned = HashSet::new();
+        let (assignment, goals) = match self.plan_fill(region, &open, &banned) {
+            Some(plan) => plan,
+            None => {
+                self.set_region(region, Voxel::Full);
+                let assignment = self.assign_corners(region, &open, &banned);
+                self.set_region(region, Voxel::Void);
+                match assignment {
+                    Some(a) => banned.extend(a.iter().map(|g| g.1)),
+                    None => return Some(false),
+                }
+                match self.plan_fill(region, &open, &banned) {
+                    Some(plan) => plan,
+                    None => return Some(false),
+                }
+            }
+        };
+
+        let waves = match self.plan_moves(&goals) {
+            Some(waves) => waves,
+            None => return Some(false),
+        };
+        self.execute_moves(waves)?;
+
+        let mut commands = vec![Command::Wait; self.bots.len()];
+        for &(bi, q, c) in assignment.iter() {
+            let ncd = c - &q;
+            let ncd = NCD::new(ncd.x, ncd.y, ncd.z);
+            commands[bi] = if assignment.len() == 1 {
+                Command::Fill(ncd)
+            } else {
+                let fcd = opposite(region, &c) - &c;
+                Command::GFill(ncd, FCD::new(fcd.x, fcd.y, fcd.z))
+            };
+        }
+        self.step(commands)?;
+        self.set_region(region, Voxel::Full);
+        Some(true)
+    }
+
+    // 分けても埋められる直方体がなければ None
+    pub fn fill_all(&mut self, mut boxes: Vec<Region>) -> Option<()> {
+        while !boxes.is_empty() {
+            // 支えのある直方体を下から順に試す
+            let mut order = (0..boxes.len())
+                .filter(|&i| self.is_supported(&boxes[i]))
+                .collect::<Vec<_>>();
+            order.sort_by_key(|&i| (boxes[i].canonical().0.y, i));
+            let mut filled = None;
+            for &i in order.iter() {
+                let rest = boxes
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .map(|(_, b)| *b)
+                    .collect::<Vec<_>>();
+                if self.try_fill(&boxes[i], &rest)? {
+                    filled = Some(i);
+                    break;
+                }
+            }
+            match filled {
+                Some(i) => {
+                    boxes.remove(i);
+                }
+                None => {
+                    // どれも埋められなければ、支えのある直方体を分けてやり直す
+                    let i = order
+                        .iter()
+                        .cloned()
+                        .find(|&i| boxes[i].dimension() > 0)?;
+                    let (a, b) = split(&boxes[i]);
+                    boxes[i] = a;
+                    boxes.push(b);
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+#[test]
+fn test_cuboid_assemble() {
+    use generator::{generate, Shape};
+    let config = Config::new();
+    for &shape in [Shape::Tower, Shape::Arch, Shape::HollowShell].iter() {
+        let model = generate(shape, 10, 0);
+        let trace = CuboidAI::new(&config).assemble(&model);
+        assert!(trace.iter().any(|c| match *c {
+            Command::GFill(_, _) => true,
+            _ => false,
+        }));
+        let mut state = ::state::State::initial(10);
+        state.execute_trace(&trace).unwrap();
+        state.end_check(&model).unwrap();
+    }
+}
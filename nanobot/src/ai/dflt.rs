@@ -0,0 +1,152 @@
+use ai::config::Config;
+use ai::utils::*;
+use ai::{AssembleAI, DisassembleAI, ReassembleAI};
+use common::*;
+use model::*;
+
+// 公式の default trace と同じやり方の AI。相対スコアの基準になる。
+//
+// 1 体の bot が最初に High にして、モデルのバウンディングボックスの各層のすぐ上を、
+// 全部の (x, z) を 1 マスずつ蛇行しながら回り、真下が target で Full なら Fill、source で Full なら Void する。
+// 消すときは上の層から、埋めるときは下の層からなので、bot のいる層はいつも全部空いていてぶつからない。
+// reassemble では source を全部消してから target を全部埋める。
+// 浮いた voxel ができても High なので通り、最後に原点に戻ってから Low に戻して Halt する。
+// source と target が正しいモデルなら必ず通るので、AI が失敗したときの代わりにも使う (anytime.rs)。
+
+pub struct DfltAI {}
+
+impl DfltAI {
+    pub fn new(_config: &Config) -> Self {
+        DfltAI {}
+    }
+}
+
+impl AssembleAI for DfltAI {
+    fn assemble(&mut self, model: &Model) -> Vec<Command> {
+        dflt_trace(&Model::initial(model.matrix.len()), model)
+    }
+}
+
+impl DisassembleAI for DfltAI {
+    fn disassemble(&mut self, model: &Model) -> Vec<Command> {
+        dflt_trace(model, &Model::initial(model.matrix.len()))
+    }
+}
+
+impl ReassembleAI for DfltAI {
+    fn reassemble(&mut self, source: &Model, target: &Model) -> Vec<Command> {
+        dflt_trace(source, target)
+    }
+}
+
+struct Sweeper {
+    pos: Position,
+    trace: Vec<Command>,
+}
+
+impl Sweeper {
+    // y, x, z の順にまっすぐ動く
+    fn move_to(&mut self, p: Position) {
+        self.trace.extend(move_straight_y(p.y - self.pos.y));
+        self.trace.extend(move_straight_x(p.x - self.pos.x));
+        self.trace.extend(move_straight_z(p.z - self.pos.z));
+        self.pos = p;
+    }
+
+    fn step(&mut self, dx: i32, dz: i32) {
+        self.trace.push(Command::SMove(LLCD::new(dx, 0, dz)));
+        self.pos = Position::new(self.pos.x + dx, self.pos.y, self.pos.z + dz);
+    }
+
+    // 今いる層の、b の範囲の (x, z) を全部回る。z の向きを列ごとに、x の向きを層ごとに変えるので、
+    // 終わった角から次の層を始められる。
+    fn sweep_layer(
+        &mut self,
+        b: &Bounding,
+        dirs: &mut (i32, i32),
+        model: &Model,
+        command: Command,
+    ) {
+        let nx = b.max_x - b.min_x + 1;
+        let nz = b.max_z - b.min_z + 1;
+        for i in 0..nx {
+            for j in 0..nz {
+                let below = Position::new(self.pos.x, self.pos.y - 1, self.pos.z);
+                if model.voxel_at(below) == Voxel::Full {
+                    self.trace.push(command);
+                }
+                if j + 1 < nz {
+                    self.step(0, dirs.1);
+                }
+            }
+            dirs.1 = -dirs.1;
+            if i + 1 < nx {
+                self.step(dirs.0, 0);
+            }
+        }
+        dirs.0 = -dirs.0;
+    }
+}
+
+// source を target にする default trace
+pub fn dflt_trace(source: &Model, target: &Model) -> Vec<Command> {
+    let source_box = calc_bounding_box(source);
+    let target_box = calc_bounding_box(target);
+    if source_box.is_none() && target_box.is_none() {
+        return vec![Command::Halt];
+    }
+    let down = NCD::new(0, -1, 0);
+    let mut sweeper = Sweeper {
+        pos: Position::zero(),
+        trace: vec![Command::Flip],
+    };
+
+    if let Some(b) = source_box {
+        // 原点の列を一番上の voxel の上まで登ってから、バウンディングボックスの角に動く
+        sweeper.move_to(Position::new(0, b.max_y + 1, 0));
+        sweeper.move_to(Position::new(b.min_x, b.max_y + 1, b.min_z));
+        let mut dirs = (1, 1);
+        for y in (b.min_y..b.max_y + 1).rev() {
+            let p = Position::new(sweeper.pos.x, y + 1, sweeper.pos.z);
+            sweeper.move_to(p);
+            sweeper.sweep_layer(&b, &mut dirs, source, Command::Void(down));
+        }
+    }
+
+    if let Some(b) = target_box {
+        // source は全部消してあるので、どこを通ってもよい
+        sweeper.move_to(Position::new(b.min_x, b.min_y + 1, b.min_z));
+        let mut dirs = (1, 1);
+        for y in b.min_y..b.max_y + 1 {
+            let p = Position::new(sweeper.pos.x, y + 1, sweeper.pos.z);
+            sweeper.move_to(p);
+            sweeper.sweep_layer(&b, &mut dirs, target, Command::Fill(down));
+        }
+    }
+
+    // 今の高さより下には Full の voxel があるので、原点の真上まで横に動いてから降りる
+    let height = sweeper.pos.y;
+    sweeper.trace.extend(move_straight_x(-sweeper.pos.x));
+    sweeper.trace.extend(move_straight_z(-sweeper.pos.z));
+    sweeper.trace.extend(move_straight_y(-height));
+    sweeper.trace.push(Command::Flip);
+    sweeper.trace.push(Command::Halt);
+    sweeper.trace
+}
+
+#[test]
+fn test_dflt_trace() {
+    use generator::{generate, generate_pair, Shape};
+    let r = 10;
+    let empty = Model::initial(r);
+    for shape in Shape::all() {
+        let model = generate(shape, r, 0);
+        let (source, target) = generate_pair(shape, Shape::Blob, r, 0, 0.5);
+        for &(s, t) in [(&empty, &model), (&model, &empty), (&source, &target)].iter() {
+            let mut state = ::state::State::initial_with_model(s);
+            state.execute_trace(&dflt_trace(s, t)).unwrap();
+            state.end_check(t).unwrap();
+        }
+    }
+    assert_eq!(dflt_trace(&empty, &empty), vec![Command::Halt]);
+}
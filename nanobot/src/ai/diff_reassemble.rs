@@ -0,0 +1,907 @@
+use ai::config::*;
+use ai::naive_reassemble::NaiveReassembleAI;
+use ai::planner::*;
+use ai::ReassembleAI;
+use common::*;
+use geometry::*;
+use model::*;
+use std::cmp::{max, min};
+use std::collections::HashSet;
+
+// source と target の差分だけを組み替える AI。
+//
+// source にだけある voxel を上から GVoid で消してから、target にだけある voxel を下から GFill で埋める。
+// 両方にある voxel には触らないので、重なりが大きいほど全部壊して作り直すより安い。
+// 消す側も埋める側も厚さ 1 の水平な長方形に分け、bot は長方形の角の近く (上を優先して横や下でもよい) に立つ。
+// 両方にある voxel に囲まれて外から行けない差分があるときは、間の voxel を一度消して後で埋め直す。
+// 後回しにした消す voxel が埋めた voxel に囲まれたときも同じように掘る。それでも bot が届かなければ
+// NaiveReassembleAI で全部壊して作り直す。
+// harmonics は layer_void, layer_fill と同じく、浮く部分があるときだけ High にする。
+//
+// concurrent では、bot を残りの層の数に比例して 2 組に分けて、埋める長方形と、それとは離れた消す長方形を同じ step で扱う。
+// 立つ位置は埋めた後の形で選ぶので、bot の位置と GVoid, GFill の領域は重ならない。
+// 消して埋めた後も全部床につながる組合せだけを選び、選べなくなったら残りを上の順で片付ける。
+// 組に分けずに組み替えた trace と比べて、energy の小さい方を返す。
+
+pub struct DiffReassembleAI {
+    // 同時に扱う長方形の数の上限。9 までなら bot 0 を入れて 40 体に収まる。
+    max_groups: usize,
+    concurrent: bool,
+    // 作り直すときに使う assembler と disassembler
+    config: Config,
+    // 作り直したときは数えない
+    high_steps: Option<usize>,
+}
+
+impl DiffReassembleAI {
+    pub fn params() -> Vec<Param> {
+        vec![MAX_GROUPS]
+    }
+
+    pub fn new(config: &Config) -> Self {
+        DiffReassembleAI {
+            max_groups: config.max_groups,
+            concurrent: false,
+            config: config.clone(),
+            high_steps: None,
+        }
+    }
+
+    // bot を消す組と埋める組に分けて、離れた長方形を同じ step で消して埋める
+    pub fn concurrent(config: &Config) -> Self {
+        DiffReassembleAI {
+            max_groups: config.max_groups,
+            concurrent: true,
+            config: config.clone(),
+            high_steps: None,
+        }
+    }
+
+    // NaiveReassembleAI で source を全部消してから target を全部埋める。
+    // GOLD_ASSEMBLER, GOLD_DISASSEMBLER がなければ default を使う。
+    fn rebuild(&self, source: &Model, target: &Model) -> Vec<Command> {
+        let mut config = self.config.clone();
+        if config.assembler.is_empty() {
+            config.assembler = String::from("default");
+        }
+        if config.disassembler.is_empty() {
+            config.disassembler = String::from("default");
+        }
+        NaiveReassembleAI::new(&config, source, target).reassemble(source, target)
+    }
+
+    // 差分の長方形を組み替えて原点に戻るまでの Planner。bot が届かない voxel が残れば None
+    fn plan(
+        &self,
+        source: &Model,
+        void_regions: &[Region],
+        fill_regions: &[Region],
+        concurrent: bool,
+    ) -> Option<Planner> {
+        let r = source.matrix.len();
+        // 1 つの層の長方形を一度に扱えるだけの bot を用意する
+        let mut per_layer = vec![(0, 0); r];
+        for region in void_regions.iter() {
+            per_layer[region.0.y as usize].0 += 1;
+        }
+        for region in fill_regions.iter() {
+            per_layer[region.0.y as usize].1 += 1;
+        }
+        let widest = per_layer.iter().map(|&(v, f)| max(v, f)).max().unwrap();
+        let groups = max(1, min(min(widest, self.max_groups), r - 2));
+
+        let mut planner = Planner::new(source);
+        planner.spawn(4 * groups + 1)?;
+        // High にしてから形を変えた voxel のまわりの voxel。これが全部床につながれば Low に戻せる。
+        let mut suspects = vec![];
+        let (void_regions, fill_regions) = if concurrent {
+            planner.void_and_fill(void_regions.to_vec(), fill_regions.to_vec())?
+        } else {
+            (void_regions.to_vec(), fill_regions.to_vec())
+        };
+        // 消すと浮く部分は、埋める voxel で支えられることが多いので後回しにする
+        let mut refills = vec![];
+        let deferred = planner.void_difference(void_regions, &mut suspects, false, &mut refills);
+        let mut rest = planner.fill_difference(fill_regions, &deferred, &mut suspects);
+        let left = planner.void_difference(deferred, &mut suspects, true, &mut refills);
+        rest.extend(refills);
+        let rest = planner.fill_difference(rest, &[], &mut suspects);
+        if !left.is_empty() || !rest.is_empty() {
+            return None;
+        }
+        planner.fuse_home()?;
+        Some(planner)
+    }
+}
+
+// void_and_fill で埋める組に回す bot の数。
+// 消す側も埋める側も 1 回に 1 つの層を扱うことが多いので、残りの層の数に比例して分ける。
+fn fill_share(voids: &[Region], fills: &[Region], capacity: usize) -> usize {
+    let layers = |regions: &[Region]| regions.iter().map(|region| region.0.y).collect::<HashSet<_>>().len();
+    let (v, f) = (layers(voids), layers(fills));
+    if v + f == 0 {
+        return capacity;
+    }
+    capacity * f / (v + f)
+}
+
+// gather_batch の結果
+enum Gather {
+    Ready(Vec<(usize, Position)>),
+    // 長方形を 1 つずつの voxel に分けたので、選び直す
+    Split,
+    // 1 voxel の長方形にも bot を集められない
+    Stuck,
+}
+
+impl ReassembleAI for DiffReassembleAI {
+    fn reassemble(&mut self, source: &Model, target: &Model) -> Vec<Command> {
+        let r = source.matrix.len();
+        let mut voids = difference(source, target);
+        let mut fills = difference(target, source);
+        let common = difference(source, &voids);
+
+        // 外から行けない差分があれば、途中にある共通の voxel も消して埋め直す
+        let cells = Region(Position::zero(), Position::new(r as i32 - 1, r as i32 - 1, r as i32 - 1))
+            .iter()
+            .filter(|&p| voids.voxel_at(p) == Voxel::Full || fills.voxel_at(p) == Voxel::Full)
+            .collect::<Vec<_>>();
+        for p in blocking_cells(&common, &cells) {
+            voids.set_voxel_at(p, Voxel::Full);
+            fills.set_voxel_at(p, Voxel::Full);
+        }
+
+        let void_regions = decompose_layers(&voids, Cover::Exact);
+        let fill_regions = decompose_layers(&fills, Cover::Exact);
+        if void_regions.is_empty() && fill_regions.is_empty() {
+            self.high_steps = Some(0);
+            return vec![Command::Halt];
+        }
+
+        let mut planner = self.plan(source, &void_regions, &fill_regions, false);
+        if self.concurrent {
+            // 組に分けると 1 組の bot が減るので、かえって遅くなることがある。
+            // 組に分けずに組み替える方が安ければそちらを使う。
+            let paired = self.plan(source, &void_regions, &fill_regions, true);
+            planner = match (planner, paired) {
+                (Some(sequential), Some(paired)) => {
+                    if paired.state.get_energy() < sequential.state.get_energy() {
+                        Some(paired)
+                    } else {
+                        Some(sequential)
+                    }
+                }
+                (sequential, paired) => sequential.or(paired),
+            };
+        }
+        match planner {
+            Some(planner) => {
+                self.high_steps = Some(planner.high_steps);
+                planner.trace
+            }
+            None => {
+                // 最後まで組み替えられなかったので、全部壊して作り直す
+                self.high_steps = None;
+                self.rebuild(source, target)
+            }
+        }
+    }
+
+    fn high_steps(&self) -> Option<usize> {
+        self.high_steps
+    }
+}
+
+impl Planner {
+    // 角 c に NCD で届く、open の中の位置。上にあるもの、同じなら c に面で接しているものから並べる。
+    fn stand_cells(&self, c: &Position, open: &HashSet<Position>) -> Vec<Position> {
+        let mut ret = all_ncd()
+            .iter()
+            .map(|d| *c - d)
+            .filter(|p| open.contains(p))
+            .collect::<Vec<_>>();
+        ret.sort_by_key(|p| (-p.y, (*p - c).manhattan_length()));
+        ret
+    }
+
+    // regions の角ごとの (立てる位置の候補, 角)。立てない角があれば None。
+    fn region_stands(
+        &self,
+        regions: &[Region],
+        open: &HashSet<Position>,
+    ) -> Option<Vec<(Vec<Position>, Position)>> {
+        let stands = regions
+            .iter()
+            .flat_map(corners)
+            .map(|c| (self.stand_cells(&c, open), c))
+            .collect::<Vec<_>>();
+        match_stands(&stands).map(|_| stands)
+    }
+
+    // 集めた bot に、角から対角までの GVoid か GFill を出させる。flip なら bot 0 が同じ step で Flip する。
+    fn step_regions(
+        &mut self,
+        regions: &[Region],
+        assignment: &[(usize, Position)],
+        fill: bool,
+        flip: bool,
+    ) -> Option<()> {
+        let mut commands = vec![Command::Wait; self.bots.len()];
+        if flip {
+            commands[0] = Command::Flip;
+        }
+        self.region_commands(regions, assignment, fill, &mut commands);
+        self.step(commands)
+    }
+
+    // assignment のうち regions の角に集めた bot のコマンドを commands に書く
+    fn region_commands(
+        &self,
+        regions: &[Region],
+        assignment: &[(usize, Position)],
+        fill: bool,
+        commands: &mut [Command],
+    ) {
+        for &(bi, c) in assignment.iter() {
+            let region = match regions.iter().find(|region| region.contains(c)) {
+                Some(region) => region,
+                None => continue,
+            };
+            let d = c - &self.bots[bi];
+            let nd = NCD::new(d.x, d.y, d.z);
+            let fcd = opposite(region, &c) - &c;
+            commands[bi] = match (fill, region.0 == region.1) {
+                (true, true) => Command::Fill(nd),
+                (true, false) => Command::GFill(nd, FCD::new(fcd.x, fcd.y, fcd.z)),
+                (false, true) => Command::Void(nd),
+                (false, false) => Command::GVoid(nd, FCD::new(fcd.x, fcd.y, fcd.z)),
+            };
+        }
+    }
+
+    // 埋める長方形を選んでから、残りの bot で消せる長方形を選び、両方を同じ step で扱う。
+    // 片方しか選べないときはそれだけを扱い、どちらも選べないか bot を集められなくなったら、そこでやめて残りを返す。
+    // コマンドが実行できなければ None
+    fn void_and_fill(
+        &mut self,
+        mut voids: Vec<Region>,
+        mut fills: Vec<Region>,
+    ) -> Option<(Vec<Region>, Vec<Region>)> {
+        let capacity = self.bots.len() - 1;
+        while !voids.is_empty() || !fills.is_empty() {
+            let share = fill_share(&voids, &fills, capacity);
+            let (mut fill_batch, mut open) = self.choose_fill_regions(&mut fills, &voids, share, true);
+            if fill_batch.is_empty() {
+                open = self.open_cells();
+            }
+            let fill_regions = fill_batch.iter().map(|&i| fills[i]).collect::<Vec<_>>();
+            let used = fill_regions.iter().map(|region| corners(region).len()).sum::<usize>();
+            for region in fill_regions.iter() {
+                self.set_region(region, Voxel::Full);
+            }
+            let candidates = self.accessible_regions(&voids, &open);
+            let mut void_batch = self.choose_grounded_voids(&voids, &candidates, &open, capacity - used);
+            let mut void_regions = void_batch.iter().map(|&i| voids[i]).collect::<Vec<_>>();
+
+            // 埋める長方形が、同じ step で消す voxel だけで床につながっていないか確かめる
+            let cells = void_regions.iter().flat_map(|region| region.iter()).collect::<Vec<_>>();
+            for region in void_regions.iter() {
+                self.set_region(region, Voxel::Void);
+            }
+            let mut check = full_neighbors(&self.current, &cells);
+            check.extend(fill_regions.iter().flat_map(|region| region.iter()));
+            let grounded = is_connected_to_ground(&self.current, &check);
+            for region in void_regions.iter() {
+                self.set_region(region, Voxel::Full);
+            }
+            for region in fill_regions.iter() {
+                self.set_region(region, Voxel::Void);
+            }
+            if !grounded {
+                void_batch.clear();
+                void_regions.clear();
+            }
+            if void_batch.is_empty() && fill_batch.is_empty() {
+                break;
+            }
+
+            let regions = void_regions.iter().chain(fill_regions.iter()).cloned().collect::<Vec<_>>();
+            let stands = match self.region_stands(&regions, &open) {
+                Some(stands) => stands,
+                None => break,
+            };
+            // 埋めた後も外に出られる、埋める層より上の位置で待つ。消すだけなら消す層より上で待つ。
+            // 消す組も埋める層より上で待てばよいので、上の消す層と下の埋める層の間を行き来しなくてすむ。
+            let below = if fill_regions.is_empty() { &void_regions } else { &fill_regions };
+            let y = below.iter().map(|region| region.0.y).max().unwrap();
+            let assignment = match self.gather_near(&regions, &stands, |p| p.y > y && open.contains(p)) {
+                Some(assignment) => assignment,
+                None => break,
+            };
+            for region in void_regions.iter() {
+                self.set_region(region, Voxel::Void);
+            }
+            for region in fill_regions.iter() {
+                self.set_region(region, Voxel::Full);
+            }
+            let mut commands = vec![Command::Wait; self.bots.len()];
+            self.region_commands(&void_regions, &assignment, false, &mut commands);
+            self.region_commands(&fill_regions, &assignment, true, &mut commands);
+            self.step(commands)?;
+
+            void_batch.sort();
+            for &i in void_batch.iter().rev() {
+                voids.remove(i);
+            }
+            fill_batch.sort();
+            for &i in fill_batch.iter().rev() {
+                fills.remove(i);
+            }
+        }
+        Some((voids, fills))
+    }
+
+    // 角の近くに bot を集める。狭い所で待っている bot が道をふさいでいたら、使わない bot を一番上の面で待たせる。
+    // それでも動かせなければ何もせずに None を返す。
+    fn gather_near<F>(
+        &mut self,
+        regions: &[Region],
+        stands: &[(Vec<Position>, Position)],
+        can_wait: F,
+    ) -> Option<Vec<(usize, Position)>>
+    where
+        F: Fn(&Position) -> bool,
+    {
+        if let Some(assignment) = self.gather_at(regions, stands, &can_wait) {
+            return Some(assignment);
+        }
+        let top = self.r as i32 - 1;
+        self.gather_at(regions, stands, |p| p.y == top && can_wait(p))
+    }
+
+    // batch の長方形の角に bot を集める。動かせなければ batch を最初の長方形だけにして試し、
+    // それでもだめならその長方形を 1 つずつの voxel に分けて Split を返す。1 voxel でも動かせなければ Stuck。
+    fn gather_batch<F>(
+        &mut self,
+        regions: &mut Vec<Region>,
+        batch: &mut Vec<usize>,
+        open: &HashSet<Position>,
+        can_wait: F,
+    ) -> Gather
+    where
+        F: Fn(&Position) -> bool,
+    {
+        loop {
+            let batch_regions = batch.iter().map(|&i| regions[i]).collect::<Vec<_>>();
+            let stands = self.region_stands(&batch_regions, open).unwrap();
+            if let Some(assignment) = self.gather_near(&batch_regions, &stands, &can_wait) {
+                return Gather::Ready(assignment);
+            }
+            if batch.len() == 1 {
+                break;
+            }
+            batch.truncate(1);
+        }
+        let region = regions[batch[0]];
+        if region.0 == region.1 {
+            return Gather::Stuck;
+        }
+        regions.remove(batch[0]);
+        regions.extend(region.iter().map(|p| Region(p, p)));
+        Gather::Split
+    }
+
+    // 全部の角に立てる長方形を、上にあるもの、同じなら bot に近いものから並べる
+    fn accessible_regions(&self, regions: &[Region], open: &HashSet<Position>) -> Vec<usize> {
+        let mut ret = vec![];
+        for (i, region) in regions.iter().enumerate() {
+            let stands = match self.region_stands(&[*region], open) {
+                Some(stands) => stands,
+                None => continue,
+            };
+            let dist = stands
+                .iter()
+                .flat_map(|s| s.0.iter())
+                .flat_map(|h| self.bots.iter().skip(1).map(move |b| (*b - h).manhattan_length()))
+                .min()
+                .unwrap_or(0);
+            ret.push((-region.0.y, dist, i));
+        }
+        ret.sort();
+        ret.into_iter().map(|(_, _, i)| i).collect()
+    }
+
+    // candidates のうち、bot が足りて立つ位置が重ならない限り、is_ok を満たすものを選ぶ
+    fn choose_void_regions<F>(
+        &mut self,
+        regions: &[Region],
+        candidates: &[usize],
+        open: &HashSet<Position>,
+        capacity: usize,
+        mut is_ok: F,
+    ) -> Vec<usize>
+    where
+        F: FnMut(&mut Planner, &[usize], usize) -> bool,
+    {
+        let mut batch: Vec<usize> = vec![];
+        let mut used = 0;
+        // 上から降りて来られない長方形は、bot が狭い所で道をふさぎ合うので 1 つだけで消す
+        let mut alone = false;
+        for &i in candidates.iter() {
+            let n = corners(&regions[i]).len();
+            if used + n > capacity || alone {
+                continue;
+            }
+            let exposed = self.is_exposed(&regions[i], open);
+            if !exposed && !batch.is_empty() {
+                continue;
+            }
+            let mut next = batch.iter().map(|&j| regions[j]).collect::<Vec<_>>();
+            next.push(regions[i]);
+            if self.region_stands(&next, open).is_none() || !is_ok(self, &batch, i) {
+                continue;
+            }
+            batch.push(i);
+            used += n;
+            alone = !exposed;
+        }
+        batch
+    }
+
+    // 全部の角に、上の列が一番上まで空いている立つ位置があれば true
+    fn is_exposed(&self, region: &Region, open: &HashSet<Position>) -> bool {
+        let r = self.r as i32;
+        corners(region).iter().all(|c| {
+            self.stand_cells(c, open)
+                .iter()
+                .any(|h| (h.y..r).all(|y| self.is_free(&Position::new(h.x, y, h.z))))
+        })
+    }
+
+    // regions を上から消す。どれを消しても浮くとき、allow_high なら High にして消し、
+    // そうでなければそこでやめて残りを返す。
+    // allow_high で、先に埋めた voxel に囲まれて外から行けない長方形しか残っていなければ、
+    // 途中の voxel を一緒に消して、後で埋め直すように refills に入れる。それでも行けなければ残りを返す。
+    fn void_difference(
+        &mut self,
+        mut regions: Vec<Region>,
+        suspects: &mut Vec<Position>,
+        allow_high: bool,
+        refills: &mut Vec<Region>,
+    ) -> Vec<Region> {
+        let capacity = self.bots.len() - 1;
+        while !regions.is_empty() {
+            let high = self.state.get_harmonics() == Harmonics::High;
+            let open = self.open_cells();
+            let mut candidates = self.accessible_regions(&regions, &open);
+            let mut batch = vec![];
+            if high {
+                // 浮いている部分を優先して消す
+                let floating = floating_cells(&self.current, suspects);
+                batch = self.choose_void_regions(&regions, &candidates, &open, capacity, |_, _, i| {
+                    regions[i].iter().any(|p| floating.contains(&p))
+                });
+            }
+            if batch.is_empty() {
+                batch = self.choose_grounded_voids(&regions, &candidates, &open, capacity);
+            }
+            // どれを消しても浮くなら、一番大きい長方形を半分に分けて試し直す。
+            // 角に立てる長方形がなければ、どれかの長方形の一部は外に接しているので、全部の中から分ける。
+            while batch.is_empty() {
+                let pool = if candidates.is_empty() {
+                    (0..regions.len()).collect()
+                } else {
+                    candidates.clone()
+                };
+                let largest = pool
+                    .into_iter()
+                    .filter(|&i| regions[i].0 != regions[i].1)
+                    .max_by_key(|&i| regions[i].iter().count());
+                let i = match largest {
+                    Some(i) => i,
+                    None => break,
+                };
+                let region = regions.remove(i);
+                let (a, b) = split(&region);
+                regions.push(a);
+                regions.push(b);
+                candidates = self.accessible_regions(&regions, &open);
+                batch = self.choose_grounded_voids(&regions, &candidates, &open, capacity);
+            }
+            if batch.is_empty() && !allow_high {
+                break;
+            }
+            if batch.is_empty() && candidates.is_empty() {
+                let cells = regions.iter().flat_map(|region| region.iter()).collect::<Vec<_>>();
+                let dig = blocking_cells(&self.current, &cells)
+                    .into_iter()
+                    .filter(|p| !cells.contains(p))
+                    .map(|p| Region(p, p))
+                    .collect::<Vec<_>>();
+                if dig.is_empty() {
+                    break;
+                }
+                regions.extend(dig.iter().cloned());
+                refills.extend(dig);
+                continue;
+            }
+            if batch.is_empty() {
+                // どれを消しても浮くので、High にして 1 つずつ消す
+                batch = vec![candidates[0]];
+            }
+
+            // 狭い所で待つと他の bot の道をふさぐので、残りの長方形を囲む箱の外で待つ
+            let (lo, hi) = regions.iter().fold((regions[0].0, regions[0].1), |(lo, hi), region| {
+                let Region(a, b) = region.canonical();
+                (
+                    Position::new(min(lo.x, a.x), min(lo.y, a.y), min(lo.z, a.z)),
+                    Position::new(max(hi.x, b.x), max(hi.y, b.y), max(hi.z, b.z)),
+                )
+            });
+            let outside = |p: &Position| {
+                p.x < lo.x || p.x > hi.x || p.y < lo.y || p.y > hi.y || p.z < lo.z || p.z > hi.z
+            };
+            let assignment = match self.gather_batch(&mut regions, &mut batch, &open, outside) {
+                Gather::Ready(assignment) => assignment,
+                Gather::Split => continue,
+                Gather::Stuck => break,
+            };
+            let batch_regions = batch.iter().map(|&i| regions[i]).collect::<Vec<_>>();
+            let cells = batch_regions.iter().flat_map(|region| region.iter()).collect::<Vec<_>>();
+            let neighbors = full_neighbors(&self.current, &cells);
+            for region in batch_regions.iter() {
+                self.set_region(region, Voxel::Void);
+            }
+            let flip = if high {
+                suspects.extend(neighbors);
+                let current = &self.current;
+                suspects.retain(|p| current.voxel_at(*p) == Voxel::Full);
+                is_connected_to_ground(&self.current, suspects)
+            } else {
+                *suspects = neighbors;
+                !is_connected_to_ground(&self.current, suspects)
+            };
+            if high && flip {
+                suspects.clear();
+            }
+            if self.step_regions(&batch_regions, &assignment, false, flip).is_none() {
+                break;
+            }
+
+            batch.sort();
+            for &i in batch.iter().rev() {
+                regions.remove(i);
+            }
+        }
+        regions
+    }
+
+    // candidates のうち、batch と一緒に消しても残りが全部床につながっているものを選ぶ
+    fn choose_grounded_voids(
+        &mut self,
+        regions: &[Region],
+        candidates: &[usize],
+        open: &HashSet<Position>,
+        capacity: usize,
+    ) -> Vec<usize> {
+        let batch = self.choose_void_regions(regions, candidates, open, capacity, |planner, batch, i| {
+            let cells = batch
+                .iter()
+                .chain(Some(&i))
+                .flat_map(|&j| regions[j].iter())
+                .collect::<Vec<_>>();
+            for p in cells.iter() {
+                planner.current.set_voxel_at(*p, Voxel::Void);
+            }
+            let ok = is_connected_to_ground(&planner.current, &full_neighbors(&planner.current, &cells));
+            for p in cells.iter() {
+                planner.current.set_voxel_at(*p, Voxel::Full);
+            }
+            ok
+        });
+        batch
+    }
+
+    // regions を下から埋める。まだ消していない voids にも外から行けるように残す。
+    // 埋めると他の長方形に行けなくなるものしか残っていなければ、そこでやめて残りを返す。
+    fn fill_difference(
+        &mut self,
+        mut regions: Vec<Region>,
+        voids: &[Region],
+        suspects: &mut Vec<Position>,
+    ) -> Vec<Region> {
+        let capacity = self.bots.len() - 1;
+        while !regions.is_empty() {
+            let high = self.state.get_harmonics() == Harmonics::High;
+            let (mut batch, mut open) = self.choose_fill_regions(&mut regions, voids, capacity, true);
+            let floating = batch.is_empty();
+            if floating {
+                // どの長方形も接していないので、High にして埋める
+                let chosen = self.choose_fill_regions(&mut regions, voids, capacity, false);
+                batch = chosen.0;
+                open = chosen.1;
+            }
+            if batch.is_empty() {
+                break;
+            }
+
+            // 埋めた後も外に出られる、埋める層より上の位置で待つ
+            let y = regions[batch[0]].0.y;
+            let assignment =
+                match self.gather_batch(&mut regions, &mut batch, &open, |p| p.y > y && open.contains(p)) {
+                    Gather::Ready(assignment) => assignment,
+                    Gather::Split => continue,
+                    Gather::Stuck => break,
+                };
+            let batch_regions = batch.iter().map(|&i| regions[i]).collect::<Vec<_>>();
+            for region in batch_regions.iter() {
+                self.set_region(region, Voxel::Full);
+            }
+            let cells = batch_regions.iter().flat_map(|region| region.iter());
+            let flip = if high {
+                suspects.extend(cells);
+                is_connected_to_ground(&self.current, suspects)
+            } else {
+                *suspects = cells.collect();
+                floating
+            };
+            if high && flip {
+                suspects.clear();
+            }
+            if self.step_regions(&batch_regions, &assignment, true, flip).is_none() {
+                break;
+            }
+
+            batch.sort();
+            for &i in batch.iter().rev() {
+                regions.remove(i);
+            }
+        }
+        regions
+    }
+
+    // 一番下の層の長方形から、bot が足りる限り選ぶ。grounded なら床か Full の voxel に接しているものだけ。
+    // 埋めた後も角に立てて、今外から行ける残りの長方形と voids の voxel に、埋めた後も行けるものに限る。
+    // 選んだものと、埋めた後に外から行ける位置を返す。条件を満たさない長方形は半分に分けて試す。
+    fn choose_fill_regions(
+        &mut self,
+        regions: &mut Vec<Region>,
+        voids: &[Region],
+        capacity: usize,
+        grounded: bool,
+    ) -> (Vec<usize>, HashSet<Position>) {
+        let open = self.open_cells();
+        let ncds = all_ncd();
+        let reachable = |p: &Position| ncds.iter().any(|d| open.contains(&(*p + d)));
+        let mut skipped = HashSet::new();
+        loop {
+            let others = regions
+                .iter()
+                .chain(voids.iter())
+                .flat_map(|region| region.iter())
+                .filter(|p| reachable(p))
+                .collect::<Vec<_>>();
+            // 掘るために消す voxel は、消すまで埋めない
+            let eligible = (0..regions.len())
+                .filter(|&i| !skipped.contains(&regions[i]))
+                .filter(|&i| !voids.iter().any(|v| v.iter().any(|p| regions[i].contains(p))))
+                .filter(|&i| !grounded || is_grounded(&self.current, &regions[i]))
+                .collect::<Vec<_>>();
+            let y = match eligible.iter().map(|&i| regions[i].0.y).min() {
+                Some(y) => y,
+                None => return (vec![], HashSet::new()),
+            };
+            let mut batch = vec![];
+            let mut used = 0;
+            for &i in eligible.iter().filter(|&&i| regions[i].0.y == y) {
+                let n = corners(&regions[i]).len();
+                if used + n <= capacity {
+                    batch.push(i);
+                    used += n;
+                }
+            }
+            if let Some(open) = self.check_fill(regions, &batch, &others) {
+                return (batch, open);
+            }
+            // まとめてだめなら 1 つずつ試す
+            let mut failed = vec![];
+            for &i in batch.iter() {
+                match self.check_fill(regions, &[i], &others) {
+                    Some(open) => return (vec![i], open),
+                    None => failed.push(i),
+                }
+            }
+            failed.sort();
+            for &i in failed.iter().rev() {
+                let region = regions[i];
+                if region.0 == region.1 {
+                    skipped.insert(region);
+                } else {
+                    regions.remove(i);
+                    let (a, b) = split(&region);
+                    regions.push(a);
+                    regions.push(b);
+                }
+            }
+        }
+    }
+
+    // batch を埋めても角に立てて、others のうち batch に入らない voxel にも外から届くなら、
+    // 埋めた後に外から行ける位置を返す
+    fn check_fill(
+        &mut self,
+        regions: &[Region],
+        batch: &[usize],
+        others: &[Position],
+    ) -> Option<HashSet<Position>> {
+        let batch_regions = batch.iter().map(|&i| regions[i]).collect::<Vec<_>>();
+        for region in batch_regions.iter() {
+            self.set_region(region, Voxel::Full);
+        }
+        let open = self.open_cells();
+        let ncds = all_ncd();
+        let ok = self.region_stands(&batch_regions, &open).is_some()
+            && others
+                .iter()
+                .filter(|p| !batch_regions.iter().any(|region| region.contains(**p)))
+                .all(|p| ncds.iter().any(|d| open.contains(&(*p + d))));
+        for region in batch_regions.iter() {
+            self.set_region(region, Voxel::Void);
+        }
+        if ok {
+            Some(open)
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_diff_reassemble() {
+    use generator::{generate_pair, Shape};
+    use state::State;
+    let config = Config::new();
+    let pairs = [
+        (Shape::Tower, Shape::Arch),
+        (Shape::HollowShell, Shape::Tower),
+        (Shape::Overhang, Shape::Pillars),
+    ];
+    for &(s, t) in pairs.iter() {
+        let (source, target) = generate_pair(s, t, 10, 0, 0.8);
+        let trace = DiffReassembleAI::new(&config).reassemble(&source, &target);
+        let mut state = State::initial_with_model(&source);
+        state.execute_trace(&trace).unwrap();
+        state.end_check(&target).unwrap();
+    }
+}
+
+#[test]
+fn test_diff_reassemble_enclosed() {
+    use state::State;
+    // 中が詰まった箱から中を抜くには、壁に穴を開けて埋め直すしかない
+    let mut source = Model::initial(8);
+    for p in Region(Position::new(1, 0, 1), Position::new(5, 4, 5)).iter() {
+        source.set_voxel_at(p, Voxel::Full);
+    }
+    let mut target = source.clone();
+    for p in Region(Position::new(2, 1, 2), Position::new(4, 3, 4)).iter() {
+        target.set_voxel_at(p, Voxel::Void);
+    }
+    let trace = DiffReassembleAI::new(&Config::new()).reassemble(&source, &target);
+    let mut state = State::initial_with_model(&source);
+    state.execute_trace(&trace).unwrap();
+    state.end_check(&target).unwrap();
+}
+
+#[test]
+fn test_diff_reassemble_buried() {
+    use generator::{generate_pair, Shape};
+    use state::State;
+    // 後回しにした消す voxel が、先に埋めた voxel に囲まれて外から行けなくなる
+    let config = Config::new();
+    let (source, target) = generate_pair(Shape::Blob, Shape::Blob, 16, 3, 0.5);
+    let mut ais = vec![DiffReassembleAI::new(&config), DiffReassembleAI::concurrent(&config)];
+    for ai in ais.iter_mut() {
+        let trace = ai.reassemble(&source, &target);
+        let mut state = State::initial_with_model(&source);
+        state.execute_trace(&trace).unwrap();
+        state.end_check(&target).unwrap();
+    }
+}
+
+#[test]
+fn test_diff_reassemble_energy() {
+    use generator::{generate_pair, Shape};
+    use state::State;
+    let mut config = Config::new();
+    config.assembler = String::from("default");
+    config.disassembler = String::from("default");
+    let energy = |source: &Model, target: &Model, trace: &[Command]| {
+        let mut state = State::initial_with_model(source);
+        state.execute_trace(trace).unwrap();
+        state.end_check(target).unwrap();
+        state.get_energy()
+    };
+    // よく重なっていれば、全部壊して作り直すより安い
+    for &(s, t) in [(Shape::Tower, Shape::Tower), (Shape::Blob, Shape::Arch)].iter() {
+        let (source, target) = generate_pair(s, t, 20, 0, 0.8);
+        let mut ai = DiffReassembleAI::new(&config);
+        let diff = energy(&source, &target, &ai.reassemble(&source, &target));
+        let naive = NaiveReassembleAI::new(&config, &source, &target).reassemble(&source, &target);
+        assert!(diff < energy(&source, &target, &naive));
+        // 届かない voxel が残ったときの作り直しも通る
+        energy(&source, &target, &ai.rebuild(&source, &target));
+    }
+}
+
+#[test]
+fn test_concurrent_reassemble() {
+    use generator::{generate_pair, Shape};
+    use state::State;
+    let config = Config::new();
+    // trace の step 数と、最初に埋める step の番号
+    let steps = |source: &Model, target: &Model, trace: &[Command]| {
+        let mut state = State::initial_with_model(source);
+        let (mut steps, mut i, mut first_fill) = (0, 0, None);
+        while i < trace.len() {
+            let n = state.get_bot_count();
+            let fill = trace[i..i + n].iter().any(|c| match *c {
+                Command::Fill(_) | Command::GFill(_, _) => true,
+                _ => false,
+            });
+            if fill && first_fill.is_none() {
+                first_fill = Some(steps);
+            }
+            state.update_time_step(&trace[i..i + n]).unwrap();
+            steps += 1;
+            i += n;
+        }
+        state.end_check(target).unwrap();
+        (steps, first_fill.unwrap_or(steps))
+    };
+    // 組に分けて速くなるものと、組に分けると遅くなるので diff と同じ trace を使うもの
+    let pairs = [
+        (Shape::Overhang, Shape::Pillars, 10, true),
+        (Shape::HollowShell, Shape::Tower, 10, true),
+        (Shape::Tower, Shape::Arch, 10, false),
+        (Shape::HollowShell, Shape::Tower, 20, false),
+    ];
+    for &(s, t, r, faster) in pairs.iter() {
+        let (source, target) = generate_pair(s, t, r, 0, 0.8);
+        let diff = DiffReassembleAI::new(&config).reassemble(&source, &target);
+        let concurrent = DiffReassembleAI::concurrent(&config).reassemble(&source, &target);
+        let (diff_steps, concurrent_steps) = (
+            steps(&source, &target, &diff).0,
+            steps(&source, &target, &concurrent).0,
+        );
+        if faster {
+            assert!(concurrent_steps < diff_steps, "{:?} -> {:?}", s, t);
+        } else {
+            assert_eq!(concurrent, diff, "{:?} -> {:?}", s, t);
+        }
+    }
+    // diff の消す step と埋める step の多い方までは縮められないが、差の 1/3 以上は縮まる
+    let pairs = [
+        (Shape::Tower, Shape::Arch),
+        (Shape::Arch, Shape::Blob),
+        (Shape::Blob, Shape::Blob),
+        (Shape::Arch, Shape::Overhang),
+    ];
+    for &(s, t) in pairs.iter() {
+        let (source, target) = generate_pair(s, t, 20, 0, 0.8);
+        let diff = DiffReassembleAI::new(&config).reassemble(&source, &target);
+        let concurrent = DiffReassembleAI::concurrent(&config).reassemble(&source, &target);
+        let (diff_steps, first_fill) = steps(&source, &target, &diff);
+        let (concurrent_steps, _) = steps(&source, &target, &concurrent);
+        let phase = max(first_fill, diff_steps - first_fill);
+        assert!(
+            3 * (concurrent_steps - phase) <= 2 * (diff_steps - phase),
+            "{:?} -> {:?}: diff {}, concurrent {}, phase {}",
+            s,
+            t,
+            diff_steps,
+            concurrent_steps,
+            phase
+        );
+    }
+}
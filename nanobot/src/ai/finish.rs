@@ -0,0 +1,48 @@
+use ai::planner::*;
+use common::*;
+use state::State;
+
+// 途中まで進めた State から、全部の bot を Fusion して原点に戻り、Halt するまでのコマンドを作る。
+//
+// 最後の bot を原点までまっすぐ動かすと、原点の近くやバウンディングボックスの縁に Full の voxel があるときに
+// ぶつかるので、Fusion も原点への移動も Full の voxel をよけて経路を探す (fission_tree.rs の fuse_home)。
+// harmonics が High のときは、床につながっていない voxel がなければ Low に戻してから動く。
+// bot が集まれなかったり、浮いた voxel が残っていたりすれば None
+pub fn finish(state: &State) -> Option<Vec<Command>> {
+    let mut planner = Planner::from_state(state);
+    planner.fuse_home()?;
+    Some(planner.trace)
+}
+
+#[test]
+fn test_finish() {
+    use model::*;
+    // 原点のすぐ上と横をふさいだ壁の向こうに散らばった、High の bot を原点に戻す
+    let r = 8;
+    let mut model = Model::initial(r);
+    for p in Region(Position::new(0, 0, 1), Position::new(3, 3, 1)).iter() {
+        model.set_voxel_at(p, Voxel::Full);
+    }
+    for p in Region(Position::new(1, 0, 0), Position::new(1, 3, 0)).iter() {
+        model.set_voxel_at(p, Voxel::Full);
+    }
+    let mut state = State::initial_with_model(&model);
+    state
+        .execute_trace(&[
+            Command::Flip,
+            Command::SMove(LLCD::new(0, 5, 0)),
+            Command::Fission(NCD::new(1, 0, 0), 3),
+            Command::Wait,
+            Command::SMove(LLCD::new(0, 0, 6)),
+            Command::Wait,
+            Command::Fission(NCD::new(0, 0, -1), 1),
+        ])
+        .unwrap();
+    assert_eq!(state.get_bot_count(), 3);
+
+    let trace = finish(&state).unwrap();
+    // 壁は全部床についているので、最初に Low に戻す
+    assert_eq!(trace[0], Command::Flip);
+    state.execute_trace(&trace).unwrap();
+    state.end_check(&model).unwrap();
+}
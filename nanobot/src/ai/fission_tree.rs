@@ -0,0 +1,235 @@
+use ai::planner::*;
+use common::*;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Fission を木の形に広げて、log(n) 回の Fission で bot を指定の位置に置く道具と、その逆に 2 体ずつ Fusion して
+// 原点の 1 体に戻す道具。
+//
+// 広げるときは、各 bot が受け持つ行き先を一番広がっている軸で半分に分け、遠い方の半分を Fission で作った bot に
+// 渡す (m は渡した行き先の数 - 1)。その後、全部の bot が受け持ちの中で一番近い行き先に向かう。
+// 戻すときは、近い bot を 2 体ずつ組にして、bid の大きい方が小さい方の隣まで動いて Fusion する。
+// harmonics は、床につながっていない voxel がなくなっていれば最初に Low に戻す。
+// どちらも移動は plan_moves を使うので、Full の voxel と他の bot をよける。
+
+// targets を一番広がっている軸で半分に分ける。from に一番近い行き先が入っている方を先に返す。
+pub fn split_targets(targets: &[Position], from: &Position) -> (Vec<Position>, Vec<Position>) {
+    let axes: [fn(&Position) -> i32; 3] = [|p| p.x, |p| p.y, |p| p.z];
+    let extent = |f: fn(&Position) -> i32| {
+        let values = targets.iter().map(f);
+        values.clone().max().unwrap() - values.min().unwrap()
+    };
+    let axis = (0..3).max_by_key(|&k| extent(axes[k])).unwrap();
+    let mut sorted = targets.to_vec();
+    sorted.sort_by_key(|p| (axes[axis](p), *p));
+    let back = sorted.split_off(sorted.len() / 2);
+    let nearest = nearest(targets, from);
+    if sorted.contains(&nearest) {
+        (sorted, back)
+    } else {
+        (back, sorted)
+    }
+}
+
+fn nearest(targets: &[Position], from: &Position) -> Position {
+    *targets
+        .iter()
+        .min_by_key(|p| ((**p - from).manhattan_length(), **p))
+        .unwrap()
+}
+
+impl Planner {
+    // 原点にいる 1 体の bot から、targets の位置に 1 体ずつ bot を置く。bots は bid 順に並び直る。
+    // 分けられなかったり動かせなかったりすれば None
+    pub fn spread(&mut self, targets: &[Position]) -> Option<()> {
+        assert_eq!(
+            self.bots.len(),
+            1,
+            "fission_tree: spread needs a single bot"
+        );
+        // bots と同じ順の、各 bot が受け持つ行き先
+        let mut groups = vec![targets.to_vec()];
+        loop {
+            let mut commands = vec![Command::Wait; self.bots.len()];
+            let mut occupied = self.bots.iter().cloned().collect::<HashSet<_>>();
+            let mut children = HashMap::new();
+            for i in 0..self.bots.len() {
+                if groups[i].len() < 2 {
+                    continue;
+                }
+                let (keep, give) = split_targets(&groups[i], &self.bots[i]);
+                let toward = nearest(&give, &self.bots[i]);
+                let cell = all_ncd()
+                    .iter()
+                    .map(|d| self.bots[i] + d)
+                    .filter(|p| self.is_free(p) && !occupied.contains(p))
+                    .min_by_key(|p| ((toward - p).manhattan_length(), *p));
+                // 隣が全部ふさがっていたら、動いた後の次の回に分ける
+                let cell = match cell {
+                    Some(cell) => cell,
+                    None => continue,
+                };
+                let d = cell - &self.bots[i];
+                commands[i] = Command::Fission(NCD::new(d.x, d.y, d.z), give.len() - 1);
+                occupied.insert(cell);
+                children.insert(cell, give);
+                groups[i] = keep;
+            }
+            if !children.is_empty() {
+                self.step(commands)?;
+                // 新しい bot の bid は親の seeds から決まるので、State から位置を読み直して受け持ちを並べ直す
+                let parents = self
+                    .bots
+                    .iter()
+                    .cloned()
+                    .zip(groups)
+                    .collect::<HashMap<_, _>>();
+                self.bots = self.state.get_bots().iter().map(|b| b.pos).collect();
+                groups = self
+                    .bots
+                    .iter()
+                    .map(|p| children.get(p).or_else(|| parents.get(p)).unwrap().clone())
+                    .collect();
+            }
+
+            let goals = groups
+                .iter()
+                .enumerate()
+                .map(|(i, group)| (i, nearest(group, &self.bots[i])))
+                .filter(|&(i, goal)| self.bots[i] != goal)
+                .collect::<Vec<_>>();
+            if children.is_empty() && goals.is_empty() {
+                if groups.iter().any(|group| group.len() > 1) {
+                    return None;
+                }
+                return Some(());
+            }
+            let waves = self.plan_moves(&goals)?;
+            self.execute_moves(waves)?;
+        }
+    }
+
+    // 近い bot を 2 体ずつ組にして Fusion するのを 1 体になるまで繰り返し、原点に戻って Halt する。
+    // 集まれなかったり、浮いた voxel が残って Low に戻せなかったりすれば None
+    pub fn fuse_home(&mut self) -> Option<()> {
+        self.flip_to_low()?;
+        while self.bots.len() > 1 {
+            let n = self.bots.len();
+            let mut candidates = vec![];
+            for i in 0..n {
+                for j in (i + 1)..n {
+                    candidates.push(((self.bots[j] - &self.bots[i]).manhattan_length(), i, j));
+                }
+            }
+            candidates.sort();
+            let mut paired = HashSet::new();
+            let mut pairs = vec![];
+            for &(_, i, j) in candidates.iter() {
+                if !paired.contains(&i) && !paired.contains(&j) {
+                    paired.insert(i);
+                    paired.insert(j);
+                    pairs.push((i, j));
+                }
+            }
+
+            // bid の大きい方が、小さい方の隣の空いている位置のうち、経路が一番短い所へ行く。
+            // 隣でも Full の voxel に囲まれて行けない位置があるので、道のりで選ぶ。
+            let mut reserved = self.bots.iter().cloned().collect::<HashSet<_>>();
+            let mut goals = vec![];
+            let mut fusions = vec![];
+            for &(i, j) in pairs.iter() {
+                let ncds = all_ncd();
+                let cell = if ncds.iter().any(|d| self.bots[i] + d == self.bots[j]) {
+                    Some(self.bots[j])
+                } else {
+                    let neighbors = ncds
+                        .iter()
+                        .map(|d| self.bots[i] + d)
+                        .filter(|p| self.is_free(p) && !reserved.contains(p))
+                        .collect::<HashSet<_>>();
+                    let mut obstacles = reserved.clone();
+                    obstacles.remove(&self.bots[j]);
+                    self.find_path(&self.bots[j], &obstacles, |p| neighbors.contains(p), |_| 0)
+                        .map(|path| *path.last().unwrap())
+                };
+                // 隣が全部ふさがっている組は、次の回に組み直す
+                if let Some(cell) = cell {
+                    reserved.insert(cell);
+                    goals.push((j, cell));
+                    fusions.push((i, j));
+                }
+            }
+            if fusions.is_empty() {
+                return None;
+            }
+            let waves = self.plan_moves(&goals)?;
+            self.execute_moves(waves)?;
+
+            let mut commands = vec![Command::Wait; n];
+            for &(i, j) in fusions.iter() {
+                let d = self.bots[j] - &self.bots[i];
+                commands[i] = Command::FusionP(NCD::new(d.x, d.y, d.z));
+                commands[j] = Command::FusionS(NCD::new(-d.x, -d.y, -d.z));
+            }
+            self.step(commands)?;
+            self.bots = self.state.get_bots().iter().map(|b| b.pos).collect();
+        }
+        let waves = self.plan_moves(&[(0, Position::zero())])?;
+        self.execute_moves(waves)?;
+        self.flip_to_low()?;
+        if self.state.get_harmonics() != Harmonics::Low {
+            return None;
+        }
+        self.step(vec![Command::Halt])
+    }
+
+    // High のときに、Full の voxel が全部床につながっていれば Low に戻す
+    fn flip_to_low(&mut self) -> Option<()> {
+        if self.state.get_harmonics() == Harmonics::High && !self.state.has_floating_voxel() {
+            let mut commands = vec![Command::Wait; self.bots.len()];
+            commands[0] = Command::Flip;
+            self.step(commands)?;
+        }
+        Some(())
+    }
+}
+
+#[test]
+fn test_split_targets() {
+    let targets = (0..5)
+        .map(|x| Position::new(2 * x, 0, 1))
+        .collect::<Vec<_>>();
+    let (near, far) = split_targets(&targets, &Position::new(9, 0, 0));
+    assert_eq!(
+        near,
+        vec![
+            Position::new(4, 0, 1),
+            Position::new(6, 0, 1),
+            Position::new(8, 0, 1)
+        ]
+    );
+    assert_eq!(far, vec![Position::new(0, 0, 1), Position::new(2, 0, 1)]);
+}
+
+#[test]
+fn test_spread_and_fuse_home() {
+    use model::*;
+    // 真ん中の柱をよけて、上の面の格子に 40 体を置いてから原点に戻す
+    let r = 12;
+    let mut model = Model::initial(r);
+    for p in Region(Position::new(4, 0, 4), Position::new(7, 9, 7)).iter() {
+        model.set_voxel_at(p, Voxel::Full);
+    }
+    let targets = (0..40)
+        .map(|k| Position::new(1 + (k % 8) as i32, 10, 1 + (k / 8) as i32 * 2))
+        .collect::<Vec<_>>();
+    let mut planner = Planner::new(&model);
+    planner.spread(&targets).unwrap();
+    let mut positions = planner.bots.clone();
+    positions.sort();
+    let mut expected = targets.clone();
+    expected.sort();
+    assert_eq!(positions, expected);
+    planner.fuse_home().unwrap();
+    planner.state.end_check(&model).unwrap();
+}
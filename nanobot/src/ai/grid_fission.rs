@@ -1,103 +1,64 @@
-use ai::config::Config;
+use ai::config::*;
+use ai::dflt::dflt_trace;
+use ai::finish::finish;
+use ai::partition::partition_footprint;
+use ai::planner::Planner;
 use ai::utils::*;
 use ai::AssembleAI;
 use common::*;
 use model::*;
 use state::State;
-use std::cmp::min;
 use std::iter::repeat;
 
 pub struct GridFissionAI {
     dry_run_max_resolution: i32,
+    max_regions: usize,
 }
 
 impl GridFissionAI {
+    pub fn params() -> Vec<Param> {
+        vec![DRY_RUN_MAX_RESOLUTION, MAX_REGIONS]
+    }
+
     pub fn new(config: &Config) -> Self {
         GridFissionAI {
             dry_run_max_resolution: config.dry_run_max_resolution,
+            max_regions: config.max_regions,
         }
     }
 }
 
 impl AssembleAI for GridFissionAI {
     fn assemble(&mut self, model: &Model) -> Vec<Command> {
-        let bounding = match calc_bounding_box(model) {
-            Some(b) => b,
-            None => {
-                return vec![Command::Halt];
-            }
-        };
         let r = model.matrix.len();
         let dry_run = r <= self.dry_run_max_resolution as usize;
-        let mut state = State::initial(r);
-
-        let x_size = (bounding.max_x - bounding.min_x + 1) as usize;
-        let z_size = (bounding.max_z - bounding.min_z + 1) as usize;
-
-        // TODO FIX ME;
-        let xsplit = min(x_size, 8);
-        let zsplit = min(z_size, 5);
-
-        let mut harmonity_high = false;
-
-        let mut commands = vec![];
-        commands.extend(move_straight_x(bounding.min_x));
-        commands.extend(move_straight_z(bounding.min_z));
-
-        for m in commands.iter() {
-            let v = vec![m.clone()];
-            state.update_time_step(&v[..]).expect("failed to move");
-        }
 
-        for v in generate_devide_commands((x_size, z_size), (xsplit, zsplit)).into_iter() {
-            state.update_time_step(&v[..]).expect("failed to devide");
-            commands.extend(v);
+        // 足場を仕事量がそろうように長方形に分けて、各長方形の角に Fission の木で bot を置く
+        let rects = partition_footprint(model, self.max_regions);
+        if rects.is_empty() {
+            return vec![Command::Halt];
         }
-
-        let mut commands_list: Vec<Vec<Command>> = vec![];
-
-        let mut x_width_list = vec![];
-        let mut z_width_list = vec![];
-        for i in 0..xsplit {
-            let width = (x_size / xsplit) as i32 + if i < x_size % xsplit { 1 } else { 0 };
-            x_width_list.push(width);
-        }
-        for i in 0..zsplit {
-            let width = (z_size / zsplit) as i32 + if i < z_size % zsplit { 1 } else { 0 };
-            z_width_list.push(width);
+        let mut planner = Planner::new(&Model::initial(r));
+        let targets = rects.iter().map(|rect| rect.0).collect::<Vec<_>>();
+        if planner.spread(&targets).is_none() {
+            return dflt_trace(&Model::initial(r), model);
         }
+        let mut state = planner.state.clone();
+        let mut commands = planner.trace.clone();
 
-        // 1st line
-        for i in 0..xsplit {
-            let mut x = bounding.min_x;
-            for j in 0..i {
-                x += x_width_list[j];
-            }
-            let initial = Position::new(x, 0, bounding.min_z);
-            let size = Position::new(x_width_list[i], bounding.max_y, z_width_list[0]);
-            commands_list.push(generate_region_commands(model, initial, size));
-        }
-        // others
-        for ri in 0..xsplit {
-            let i = xsplit - ri - 1;
-            let mut x = bounding.min_x;
-            for j in 0..i {
-                x += x_width_list[j];
-            }
-            let x_width = x_width_list[i];
+        let mut harmonity_high = false;
 
-            for j in 1..zsplit {
-                let mut z = bounding.min_z;
-                for k in 0..j {
-                    z += z_width_list[k];
-                }
-                let z_width = z_width_list[j];
-
-                let initial = Position::new(x, 0, z);
-                let size = Position::new(x_width, bounding.max_y, z_width);
-                commands_list.push(generate_region_commands(model, initial, size));
-            }
-        }
+        // bid 順に並べた、各 bot が自分の長方形を蛇行するコマンド列
+        let commands_list = planner
+            .bots
+            .iter()
+            .map(|p| {
+                let rect = rects.iter().find(|rect| rect.0 == *p).unwrap();
+                let size =
+                    Position::new(rect.1.x - rect.0.x + 1, rect.1.y, rect.1.z - rect.0.z + 1);
+                generate_region_commands(model, *p, size)
+            })
+            .collect::<Vec<_>>();
 
         let mut index = 0;
         loop {
@@ -116,39 +77,30 @@ impl AssembleAI for GridFissionAI {
             }
 
             if dry_run {
-                let mut cloned = state.clone();
                 if !harmonity_high {
-                    match cloned.update_time_step(&step[..]) {
-                        Ok(_) => {
-                            state = cloned;
-                        }
-                        Err(_) => {
-                            harmonity_high = true;
-                            let mut high = vec![Command::Flip];
-                            high.extend(repeat(Command::Wait).take(commands_list.len() - 1));
-                            state.update_time_step(&high[..]).unwrap();
-                            state.update_time_step(&step[..]).unwrap();
-                            commands.extend(high);
-                        }
+                    if state.try_time_step(&step[..]).is_err() {
+                        harmonity_high = true;
+                        let mut high = vec![Command::Flip];
+                        high.extend(repeat(Command::Wait).take(commands_list.len() - 1));
+                        state.update_time_step(&high[..]).unwrap();
+                        state.update_time_step(&step[..]).unwrap();
+                        commands.extend(high);
                     }
                 } else {
                     let mut low = vec![Command::Flip];
                     low.extend(repeat(Command::Wait).take(commands_list.len() - 1));
 
-                    match cloned.update_time_step(&low[..]) {
-                        Ok(_) => match cloned.update_time_step(&step[..]) {
-                            Ok(_) => {
-                                harmonity_high = false;
-                                state = cloned;
-                                commands.extend(low);
-                            }
-                            Err(_) => {
-                                state.update_time_step(&step[..]).unwrap();
-                            }
-                        },
-                        Err(_) => {
-                            state.update_time_step(&step[..]).unwrap();
-                        }
+                    // Low に戻しても step が通るなら戻す。通らなければ試した分を取り消して High のまま進める
+                    state.checkpoint();
+                    if state.update_time_step(&low[..]).is_ok()
+                        && state.update_time_step(&step[..]).is_ok()
+                    {
+                        state.commit();
+                        harmonity_high = false;
+                        commands.extend(low);
+                    } else {
+                        state.rollback();
+                        state.update_time_step(&step[..]).unwrap();
                     }
                 }
             } else {
@@ -171,16 +123,18 @@ impl AssembleAI for GridFissionAI {
             commands.push(Command::Flip);
             commands.extend(repeat(Command::Wait).take(commands_list.len() - 1));
         }
-        commands.extend(
-            generate_concur_commands((x_size, z_size), (xsplit, zsplit))
-                .iter()
-                .flat_map(|v| v.iter()),
-        );
-        commands.extend(move_straight_x(-bounding.min_x));
-        commands.extend(move_straight_z(-bounding.min_z));
-        commands.extend(move_straight_y(-(bounding.max_y + 1)));
-        commands.push(Command::Halt);
-        commands
+        // back to origin, avoiding full voxels
+        let mut state = State::initial(r);
+        state
+            .execute_trace(&commands)
+            .expect("grid_fission: invalid trace");
+        match finish(&state) {
+            Some(rest) => {
+                commands.extend(rest);
+                commands
+            }
+            None => dflt_trace(&Model::initial(r), model),
+        }
     }
 }
 
@@ -270,3 +224,36 @@ fn test_generate_region_with_3x3x3() {
 
     assert_eq!(expected, commands);
 }
+
+#[test]
+fn test_grid_fission_over_origin() {
+    // 原点の真上に Full の voxel があっても、よけて原点に戻る
+    let r = 6;
+    let mut model = Model::initial(r);
+    for p in [
+        Position::new(1, 0, 0),
+        Position::new(1, 1, 0),
+        Position::new(0, 1, 0),
+    ]
+    .iter()
+    {
+        model.set_voxel_at(*p, Voxel::Full);
+    }
+    let trace = GridFissionAI::new(&Config::new()).assemble(&model);
+    let mut state = State::initial(r);
+    state.execute_trace(&trace).unwrap();
+    state.end_check(&model).unwrap();
+}
+
+#[test]
+fn test_grid_fission_blob() {
+    use generator::{generate, Shape};
+    // 隣の長方形の voxel に囲まれて、Fusion の相手の隣に行けない位置がある
+    for &seed in [1, 3].iter() {
+        let model = generate(Shape::Blob, 12, seed);
+        let trace = GridFissionAI::new(&Config::new()).assemble(&model);
+        let mut state = State::initial(12);
+        state.execute_trace(&trace).unwrap();
+        state.end_check(&model).unwrap();
+    }
+}
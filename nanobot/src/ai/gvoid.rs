@@ -1,5 +1,6 @@
 use ai::config::Config;
 use ai::DisassembleAI;
+use ai::reservation::find_path;
 use ai::utils::*;
 use common::*;
 use model::Model;
@@ -41,18 +42,17 @@ impl DisassembleAI for GvoidAI {
         state.update_time_step(&step).expect("failed to move x");
         commands.push(step.clone());
 
-        // go to (min_x, max_y + 1, min_z)
-        for m in move_straight_x(bounding.min_x) {
-            state.update_time_step(&vec![m.clone()]).expect("failed to move x");
-            commands.push(vec![m.clone()]);
-        }
-        for m in move_straight_y(bounding.max_y + 1) {
-            state.update_time_step(&vec![m.clone()]).expect("failed to move y");
-            commands.push(vec![m.clone()]);
-        }
-        for m in move_straight_z(bounding.min_z) {
-            state.update_time_step(&vec![m.clone()]).expect("failed to move z");
-            commands.push(vec![m.clone()]);
+        // (min_x, max_y + 1, min_z) へ、モデルの Void のところだけを通って行く
+        {
+            let goal = Position::new(bounding.min_x, bounding.max_y + 1, bounding.min_z);
+            let moves = {
+                let is_free = |p: &Position| state.is_valid_coordinate(p) && state.get_voxel(*p) == Voxel::Void;
+                find_path(&is_free, &Position::zero(), &goal).expect("no path to the top")
+            };
+            for m in moves {
+                state.update_time_step(&vec![m.clone()]).expect("failed to move to the top");
+                commands.push(vec![m.clone()]);
+            }
         }
 
         // x-z plane fission
@@ -110,18 +110,17 @@ impl DisassembleAI for GvoidAI {
             commands.push(ms.clone());
         }
 
-        // move to (0,0,0)
-        for m in move_straight_x(-bounding.min_x) {
-            state.update_time_step(&vec![m.clone()]).expect("failed to move x");
-            commands.push(vec![m.clone()]);
-        }
-        for m in move_straight_y(-bounding.min_y) {
-            state.update_time_step(&vec![m.clone()]).expect("failed to move y");
-            commands.push(vec![m.clone()]);
-        }
-        for m in move_straight_z(-bounding.max_z) {
-            state.update_time_step(&vec![m.clone()]).expect("failed to move z");
-            commands.push(vec![m.clone()]);
+        // move to (0,0,0)。全部消えているので、どこを通ってもよい
+        {
+            let from = Position::new(bounding.min_x, bounding.min_y, bounding.max_z);
+            let moves = {
+                let is_free = |p: &Position| state.is_valid_coordinate(p);
+                find_path(&is_free, &from, &Position::zero()).expect("no path to the origin")
+            };
+            for m in moves {
+                state.update_time_step(&vec![m.clone()]).expect("failed to move to the origin");
+                commands.push(vec![m.clone()]);
+            }
         }
 
         commands.push(vec![Command::Flip]);
@@ -1,5 +1,6 @@
 use ai::config::Config;
 use ai::DisassembleAI;
+use ai::reservation::find_path;
 use ai::utils::*;
 use common::*;
 use model::Model;
@@ -41,18 +42,17 @@ impl DisassembleAI for Gvoid2dAI {
 
         let mut harmonics_high = false;
 
-        // go to (min_x, max_y + 1, min_z)
-        for m in move_straight_x(bounding.min_x) {
-            state.update_time_step(&vec![m.clone()]).expect("failed to move x");
-            commands.push(vec![m.clone()]);
-        }
-        for m in move_straight_y(bounding.max_y + 1) {
-            state.update_time_step(&vec![m.clone()]).expect("failed to move y");
-            commands.push(vec![m.clone()]);
-        }
-        for m in move_straight_z(bounding.min_z) {
-            state.update_time_step(&vec![m.clone()]).expect("failed to move z");
-            commands.push(vec![m.clone()]);
+        // (min_x, max_y + 1, min_z) へ、モデルの Void のところだけを通って行く
+        {
+            let goal = Position::new(bounding.min_x, bounding.max_y + 1, bounding.min_z);
+            let moves = {
+                let is_free = |p: &Position| state.is_valid_coordinate(p) && state.get_voxel(*p) == Voxel::Void;
+                find_path(&is_free, &Position::zero(), &goal).expect("no path to the top")
+            };
+            for m in moves {
+                state.update_time_step(&vec![m.clone()]).expect("failed to move to the top");
+                commands.push(vec![m.clone()]);
+            }
         }
 
         // x-z plane fission
@@ -66,39 +66,29 @@ impl DisassembleAI for Gvoid2dAI {
             let z_size = grid_index_z[z_index + 1] - grid_index_z[z_index] + 1;
             for step in generate_2d_gvoid_commands(&grid_index_x, y_size, z_size) {
                 if dry_run {
-                    let mut cloned = state.clone();
                     if !harmonics_high {
-                        match cloned.update_time_step(&step[..]) {
-                            Ok(_) => {
-                                state = cloned;
-                            }
-                            Err(_) => {
-                                harmonics_high = true;
-                                let mut high = vec![Command::Flip];
-                                high.extend(repeat(Command::Wait).take(step.len() - 1));
-                                state.update_time_step(&high[..]).unwrap();
-                                state.update_time_step(&step[..]).unwrap();
-                                commands.push(high);
-                            }
+                        if state.try_time_step(&step[..]).is_err() {
+                            harmonics_high = true;
+                            let mut high = vec![Command::Flip];
+                            high.extend(repeat(Command::Wait).take(step.len() - 1));
+                            state.update_time_step(&high[..]).unwrap();
+                            state.update_time_step(&step[..]).unwrap();
+                            commands.push(high);
                         }
                     } else {
                         let mut low = vec![Command::Flip];
                         low.extend(repeat(Command::Wait).take(step.len() - 1));
 
-                        match cloned.update_time_step(&low[..]) {
-                            Ok(_) => match cloned.update_time_step(&step[..]) {
-                                Ok(_) => {
-                                    harmonics_high = false;
-                                    state = cloned;
-                                    commands.push(low);
-                                }
-                                Err(_) => {
-                                    state.update_time_step(&step[..]).unwrap();
-                                }
-                            },
-                            Err(_) => {
-                                state.update_time_step(&step[..]).unwrap();
-                            }
+                        state.checkpoint();
+                        if state.update_time_step(&low[..]).is_ok()
+                            && state.update_time_step(&step[..]).is_ok()
+                        {
+                            state.commit();
+                            harmonics_high = false;
+                            commands.push(low);
+                        } else {
+                            state.rollback();
+                            state.update_time_step(&step[..]).unwrap();
                         }
                     }
                 } else {
@@ -167,15 +157,16 @@ impl DisassembleAI for Gvoid2dAI {
             commands.push(ms.clone());
         }
 
-        // move to (0,0,0)
-        for m in move_straight_x(-bounding.min_x) {
-            commands.push(vec![m.clone()]);
-        }
-        for m in move_straight_y(-bounding.min_y) {
-            commands.push(vec![m.clone()]);
-        }
-        for m in move_straight_z(-bounding.max_z) {
-            commands.push(vec![m.clone()]);
+        // move to (0,0,0)。全部消えているので、どこを通ってもよい
+        {
+            let from = Position::new(bounding.min_x, bounding.min_y, bounding.max_z);
+            let moves = {
+                let is_free = |p: &Position| state.is_valid_coordinate(p);
+                find_path(&is_free, &from, &Position::zero()).expect("no path to the origin")
+            };
+            for m in moves {
+                commands.push(vec![m.clone()]);
+            }
         }
 
         if harmonics_high {
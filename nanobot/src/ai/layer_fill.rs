@@ -0,0 +1,195 @@
+use ai::config::*;
+use ai::dflt::dflt_trace;
+use ai::planner::*;
+use ai::utils::*;
+use ai::AssembleAI;
+use common::*;
+use geometry::*;
+use model::*;
+use std::cmp::{max, min};
+
+// 下の層から順に、各層の断面を長方形に分けて 2D の GFill で埋めていく AI。
+//
+// bot はバウンディングボックスの底の面に Fission の木で格子状に広げてから (fission_tree.rs)、
+// 埋める層のすぐ上の面で長方形の角に集める。
+// 長方形は床か埋めた voxel に接しているものから埋めるので、普段は harmonics は Low のまま。
+// 層に残っている長方形がどれも接していないとき (上の層からぶら下がる部分があるとき) だけ High にして埋め、
+// High の間に埋めた voxel が全部床につながった時点で Low に戻す。Flip は bot 0 が GFill と同じ step で出す。
+// 最後は近い bot を 2 体ずつ Fusion して 1 体にまとめ、原点に戻る。
+
+const MAX_BOTS: usize = 40;
+
+pub struct LayerFillAI {
+    // 1 つの層の長方形の数がこれより多くても、同時に埋めるのはこの数まで
+    max_groups: usize,
+}
+
+impl LayerFillAI {
+    pub fn params() -> Vec<Param> {
+        vec![MAX_GROUPS]
+    }
+
+    pub fn new(config: &Config) -> Self {
+        LayerFillAI {
+            max_groups: config.max_groups,
+        }
+    }
+}
+
+impl AssembleAI for LayerFillAI {
+    fn assemble(&mut self, model: &Model) -> Vec<Command> {
+        let bounding = match calc_bounding_box(model) {
+            Some(b) => b,
+            None => {
+                return vec![Command::Halt];
+            }
+        };
+        let r = model.matrix.len();
+        let mut layers = vec![vec![]; r];
+        for region in decompose_layers(model, Cover::Exact) {
+            layers[region.0.y as usize].push(region);
+        }
+
+        // 1 つの層の長方形を一度に埋められるだけの bot と、Flip を出す bot 0 を用意する
+        let groups = min(layers.iter().map(|l| l.len()).max().unwrap(), self.max_groups);
+        let split = grid_split(4 * groups + 1, r);
+        let size = (
+            max((bounding.max_x - bounding.min_x + 1) as usize, split.0),
+            max((bounding.max_z - bounding.min_z + 1) as usize, split.1),
+        );
+        // 格子がはみ出すときは内側にずらす
+        let anchor = Position::new(
+            min(bounding.min_x, (r - size.0) as i32),
+            bounding.min_y + 1,
+            min(bounding.min_z, (r - size.1) as i32),
+        );
+
+        let mut planner = Planner::new(&Model::initial(r));
+        let xs = grid_offsets(size.0, split.0);
+        let zs = grid_offsets(size.1, split.1);
+        let targets = xs
+            .iter()
+            .flat_map(|&x| zs.iter().map(move |&z| anchor + &Position::new(x, 0, z)))
+            .collect::<Vec<_>>();
+        let planned = planner
+            .spread(&targets)
+            .and_then(|_| planner.fill_layers(layers))
+            .and_then(|_| planner.fuse_home());
+        match planned {
+            Some(()) => planner.trace,
+            None => dflt_trace(&Model::initial(r), model),
+        }
+    }
+}
+
+// 長さ len を split 個に分けたときの、各区間の始まりの位置
+fn grid_offsets(len: usize, split: usize) -> Vec<i32> {
+    (0..split)
+        .map(|i| (i * (len / split) + min(i, len % split)) as i32)
+        .collect()
+}
+
+// なるべく n 体に近い格子の、x 方向と z 方向の bot の数。全部で MAX_BOTS 体、各方向 r 体を超えない。
+fn grid_split(n: usize, r: usize) -> (usize, usize) {
+    let mut a = 1;
+    while a * a < n {
+        a += 1;
+    }
+    let a = min(a, r);
+    let b = min(min((n + a - 1) / a, MAX_BOTS / a), r);
+    (a, b)
+}
+
+impl Planner {
+    // bot を動かせなくなれば None
+    fn fill_layers(&mut self, layers: Vec<Vec<Region>>) -> Option<()> {
+        let capacity = self.bots.len() - 1;
+        // High にしてから埋めた voxel。これが全部床につながれば Low に戻せる。
+        let mut suspects = vec![];
+        for (y, mut rest) in layers.into_iter().enumerate() {
+            let y = y as i32;
+            while !rest.is_empty() {
+                let high = self.state.get_harmonics() == Harmonics::High;
+                let mut batch = self.choose_rectangles(&rest, capacity, true);
+                let floating = batch.is_empty();
+                if floating {
+                    // どの長方形も接していないので、High にして埋める
+                    batch = self.choose_rectangles(&rest, capacity, false);
+                }
+
+                let regions = batch.iter().map(|&i| rest[i]).collect::<Vec<_>>();
+                let assignment = self.gather_above(&regions, |p| p.y > y)?;
+                for region in regions.iter() {
+                    self.set_region(region, Voxel::Full);
+                }
+                let flip = if high {
+                    suspects.extend(regions.iter().flat_map(|region| region.iter()));
+                    is_connected_to_ground(&self.current, &suspects)
+                } else {
+                    suspects = regions.iter().flat_map(|region| region.iter()).collect();
+                    floating
+                };
+                if high && flip {
+                    suspects.clear();
+                }
+
+                let mut commands = vec![Command::Wait; self.bots.len()];
+                if flip {
+                    commands[0] = Command::Flip;
+                }
+                let down = NCD::new(0, -1, 0);
+                for &(bi, c) in assignment.iter() {
+                    let region = regions.iter().find(|region| region.contains(c)).unwrap();
+                    commands[bi] = if region.0 == region.1 {
+                        Command::Fill(down)
+                    } else {
+                        let fcd = opposite(region, &c) - &c;
+                        Command::GFill(down, FCD::new(fcd.x, fcd.y, fcd.z))
+                    };
+                }
+                self.step(commands)?;
+
+                for &i in batch.iter().rev() {
+                    rest.remove(i);
+                }
+            }
+        }
+        Some(())
+    }
+
+    // rest の長方形を、bot が足りる限り前から選ぶ。grounded なら床か Full の voxel に接しているものだけ。
+    fn choose_rectangles(&self, rest: &[Region], capacity: usize, grounded: bool) -> Vec<usize> {
+        let mut batch = vec![];
+        let mut used = 0;
+        for (i, region) in rest.iter().enumerate() {
+            let n = corners(region).len();
+            if used + n > capacity || (grounded && !is_grounded(&self.current, region)) {
+                continue;
+            }
+            batch.push(i);
+            used += n;
+        }
+        batch
+    }
+}
+
+#[test]
+fn test_grid_split() {
+    assert_eq!(grid_split(5, 10), (3, 2));
+    assert_eq!(grid_split(37, 10), (7, 5));
+    assert_eq!(grid_split(37, 5), (5, 5));
+}
+
+#[test]
+fn test_layer_fill_assemble() {
+    use generator::{generate, Shape};
+    use state::State;
+    let config = Config::new();
+    for &shape in [Shape::Tower, Shape::Arch, Shape::HollowShell, Shape::Pillars].iter() {
+        let model = generate(shape, 10, 0);
+        let trace = LayerFillAI::new(&config).assemble(&model);
+        let mut state = State::initial(10);
+        state.execute_trace(&trace).unwrap();
+        state.end_check(&model).unwrap();
+    }
+}
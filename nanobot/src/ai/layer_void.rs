@@ -0,0 +1,332 @@
+use ai::config::*;
+use ai::dflt::dflt_trace;
+use ai::planner::*;
+use ai::DisassembleAI;
+use common::*;
+use geometry::*;
+use model::*;
+use std::cmp::{max, min};
+
+// 上から順に、水平な長方形を 2D の GVoid で消していく AI。
+//
+// 各層を厚さ 1 の長方形で覆い、角の上の列が一番上まで空いている長方形だけを消す。
+// bot は長方形のすぐ上にいるので、bot の移動が残りの voxel に邪魔されない。
+// 消した後も残りが全部床につながっている長方形だけをまとめて消すので、普段は harmonics は Low のまま。
+// そういう長方形がなければ長方形を半分に分けて試し、それでもだめなとき
+// (張り出しの下にぶら下がっている部分があるとき) だけ High にして、浮いている部分を優先して消し、
+// 全部つながった時点で Low に戻す。
+// Flip は原点に残した bot 0 が GVoid と同じ step で出すので、High の step は最小限で済む。
+
+// 覆わなくてよい voxel 1 つあたりの損。Void の voxel を GVoid するのは安く、長方形が増えて step が増える方がずっと高い。
+const OVER_WEIGHT: f64 = 0.01;
+
+pub struct LayerVoidAI {
+    // 1 つの層の長方形の数がこれより多くても、同時に消すのはこの数まで。9 までなら bot 0 を入れて 40 体に収まる。
+    max_groups: usize,
+    // dflt の trace に切り替えたときは数えない
+    high_steps: Option<usize>,
+}
+
+impl LayerVoidAI {
+    pub fn params() -> Vec<Param> {
+        vec![MAX_GROUPS]
+    }
+
+    pub fn new(config: &Config) -> Self {
+        LayerVoidAI {
+            max_groups: config.max_groups,
+            high_steps: None,
+        }
+    }
+}
+
+impl DisassembleAI for LayerVoidAI {
+    fn disassemble(&mut self, model: &Model) -> Vec<Command> {
+        let r = model.matrix.len();
+        let slabs = decompose_layers(model, Cover::Over(OVER_WEIGHT))
+            .into_iter()
+            .map(|region| Slab {
+                cells: region
+                    .iter()
+                    .filter(|&p| model.voxel_at(p) == Voxel::Full)
+                    .collect(),
+                region: region.canonical(),
+            })
+            .collect::<Vec<_>>();
+        if slabs.is_empty() {
+            self.high_steps = Some(0);
+            return vec![Command::Halt];
+        }
+
+        // 1 つの層の長方形を一度に消せるだけの bot を用意する
+        let mut per_layer = vec![0; r];
+        for s in slabs.iter() {
+            per_layer[s.region.0.y as usize] += 1;
+        }
+        let groups = max(1, min(min(*per_layer.iter().max().unwrap(), self.max_groups), r - 2));
+
+        let mut planner = Planner::new(model);
+        let planned = planner
+            .spawn(4 * groups + 1)
+            .and_then(|_| planner.void_slabs(slabs))
+            .and_then(|_| planner.fuse_home());
+        match planned {
+            Some(()) => {
+                self.high_steps = Some(planner.high_steps);
+                planner.trace
+            }
+            None => {
+                self.high_steps = None;
+                dflt_trace(model, &Model::initial(r))
+            }
+        }
+    }
+
+    fn high_steps(&self) -> Option<usize> {
+        self.high_steps
+    }
+}
+
+// 消す長方形と、その中の Full の voxel
+struct Slab {
+    region: Region,
+    cells: Vec<Position>,
+}
+
+// 長方形の角のすぐ上の、GVoid を出す bot の位置
+fn hover_cells(slab: &Slab) -> Vec<(Position, Position)> {
+    corners(&slab.region)
+        .into_iter()
+        .map(|c| (c + &Position::new(0, 1, 0), c))
+        .collect()
+}
+
+// slabs[i] を batch と同時に消すとき、他の長方形の中に bot を置くことになるなら true
+fn conflicts(slabs: &[Slab], batch: &[usize], i: usize) -> bool {
+    let hovers = hover_cells(&slabs[i]);
+    batch.iter().any(|&j| {
+        hovers.iter().any(|h| slabs[j].region.contains(h.0))
+            || hover_cells(&slabs[j])
+                .iter()
+                .any(|h| slabs[i].region.contains(h.0))
+    })
+}
+
+impl Planner {
+    fn set_cells(&mut self, cells: &[Position], voxel: Voxel) {
+        for p in cells.iter() {
+            self.current.set_voxel_at(*p, voxel);
+        }
+    }
+
+    // 角の上の列が一番上まで空いていて、上から bot が降りて行ける長方形。
+    // bot に近いもの、同じなら上にあるものから並べる。
+    fn exposed_slabs(&self, slabs: &[Slab]) -> Vec<usize> {
+        let r = self.r as i32;
+        let mut ret = vec![];
+        for (i, slab) in slabs.iter().enumerate() {
+            let hovers = hover_cells(slab);
+            let exposed = hovers.iter().all(|&(h, _)| {
+                (h.y..r).all(|y| self.is_free(&Position::new(h.x, y, h.z)))
+            });
+            if !exposed {
+                continue;
+            }
+            let dist = hovers
+                .iter()
+                .flat_map(|&(h, _)| self.bots.iter().skip(1).map(move |b| (*b - &h).manhattan_length()))
+                .min()
+                .unwrap_or(0);
+            ret.push((dist, -slab.region.0.y, i));
+        }
+        ret.sort();
+        ret.into_iter().map(|(_, _, i)| i).collect()
+    }
+
+    // candidates のうち、batch と一緒に消しても残りが全部床につながっているものを、bot が足りる限り選ぶ
+    fn grounded_slabs(&mut self, slabs: &[Slab], candidates: &[usize], capacity: usize) -> Vec<usize> {
+        let mut batch: Vec<usize> = vec![];
+        let mut cells = vec![];
+        let mut used = 0;
+        for &i in candidates.iter() {
+            let n = corners(&slabs[i].region).len();
+            if used + n > capacity || conflicts(slabs, &batch, i) {
+                continue;
+            }
+            self.set_cells(&slabs[i].cells, Voxel::Void);
+            let mut next = cells.clone();
+            next.extend(slabs[i].cells.iter().cloned());
+            if is_connected_to_ground(&self.current, &full_neighbors(&self.current, &next)) {
+                batch.push(i);
+                cells = next;
+                used += n;
+            } else {
+                self.set_cells(&slabs[i].cells, Voxel::Full);
+            }
+        }
+        self.set_cells(&cells, Voxel::Full);
+        batch
+    }
+
+    // candidates のうち、suspects からつながっていて浮いているものを、bot が足りる限り選ぶ
+    fn floating_slabs(
+        &self,
+        slabs: &[Slab],
+        candidates: &[usize],
+        suspects: &[Position],
+        capacity: usize,
+    ) -> Vec<usize> {
+        let floating = floating_cells(&self.current, suspects);
+        let mut batch: Vec<usize> = vec![];
+        let mut used = 0;
+        for &i in candidates.iter() {
+            let n = corners(&slabs[i].region).len();
+            if used + n > capacity
+                || !slabs[i].cells.iter().any(|p| floating.contains(p))
+                || conflicts(slabs, &batch, i)
+            {
+                continue;
+            }
+            batch.push(i);
+            used += n;
+        }
+        batch
+    }
+
+    // bot を動かせなくなれば None
+    fn void_slabs(&mut self, mut slabs: Vec<Slab>) -> Option<()> {
+        let capacity = self.bots.len() - 1;
+        // High にしてから消した voxel のまわりの voxel。これが全部床につながれば Low に戻せる。
+        let mut suspects = vec![];
+        while !slabs.is_empty() {
+            let high = self.state.get_harmonics() == Harmonics::High;
+            let mut candidates = self.exposed_slabs(&slabs);
+            let mut batch = if high {
+                self.floating_slabs(&slabs, &candidates, &suspects, capacity)
+            } else {
+                vec![]
+            };
+            if batch.is_empty() {
+                batch = self.grounded_slabs(&slabs, &candidates, capacity);
+            }
+            // どれを消しても浮くなら、一番大きい長方形を半分に分けて試し直す
+            while batch.is_empty() {
+                let largest = candidates
+                    .iter()
+                    .cloned()
+                    .filter(|&i| slabs[i].cells.len() > 1)
+                    .max_by_key(|&i| slabs[i].cells.len());
+                let i = match largest {
+                    Some(i) => i,
+                    None => break,
+                };
+                let slab = slabs.remove(i);
+                let (a, b) = split(&slab.region);
+                for region in [a, b].iter() {
+                    let cells = slab
+                        .cells
+                        .iter()
+                        .cloned()
+                        .filter(|&p| region.contains(p))
+                        .collect::<Vec<_>>();
+                    if !cells.is_empty() {
+                        slabs.push(Slab {
+                            region: *region,
+                            cells,
+                        });
+                    }
+                }
+                candidates = self.exposed_slabs(&slabs);
+                batch = self.grounded_slabs(&slabs, &candidates, capacity);
+            }
+            if batch.is_empty() {
+                // どれを消しても浮くので、High にして 1 つずつ消す。一番上の層の長方形はいつも候補に入る。
+                batch = vec![candidates[0]];
+            }
+
+            let regions = batch.iter().map(|&i| slabs[i].region).collect::<Vec<_>>();
+            let assignment = self.gather_above(&regions, |_| true)?;
+            let cells = batch
+                .iter()
+                .flat_map(|&i| slabs[i].cells.iter().cloned())
+                .collect::<Vec<_>>();
+            let neighbors = full_neighbors(&self.current, &cells);
+            self.set_cells(&cells, Voxel::Void);
+            let flip = if high {
+                suspects.extend(neighbors);
+                suspects.retain(|p| self.current.voxel_at(*p) == Voxel::Full);
+                is_connected_to_ground(&self.current, &suspects)
+            } else {
+                suspects = neighbors;
+                !is_connected_to_ground(&self.current, &suspects)
+            };
+            if high && flip {
+                suspects.clear();
+            }
+
+            let mut commands = vec![Command::Wait; self.bots.len()];
+            if flip {
+                commands[0] = Command::Flip;
+            }
+            let down = NCD::new(0, -1, 0);
+            for &(bi, c) in assignment.iter() {
+                let slab = batch
+                    .iter()
+                    .map(|&i| &slabs[i])
+                    .find(|s| s.region.contains(c))
+                    .unwrap();
+                commands[bi] = if slab.region.0 == slab.region.1 {
+                    Command::Void(down)
+                } else {
+                    let fcd = opposite(&slab.region, &c) - &c;
+                    Command::GVoid(down, FCD::new(fcd.x, fcd.y, fcd.z))
+                };
+            }
+            self.step(commands)?;
+
+            batch.sort();
+            for &i in batch.iter().rev() {
+                slabs.remove(i);
+            }
+        }
+        Some(())
+    }
+}
+
+#[test]
+fn test_layer_void_disassemble() {
+    use generator::{generate, Shape};
+    use state::State;
+    let config = Config::new();
+    for &shape in [Shape::Tower, Shape::Arch, Shape::HollowShell, Shape::Overhang].iter() {
+        let model = generate(shape, 10, 0);
+        let mut ai = LayerVoidAI::new(&config);
+        let trace = ai.disassemble(&model);
+        let mut state = State::initial_with_model(&model);
+        state.execute_trace(&trace).unwrap();
+        state.end_check(&Model::initial(10)).unwrap();
+        assert_eq!(ai.high_steps(), Some(0));
+    }
+}
+
+#[test]
+fn test_layer_void_hanging() {
+    use state::State;
+    // 腕の先にぶら下がっている部分は、腕を消すと浮く
+    let mut model = Model::initial(10);
+    for p in Region(Position::new(2, 0, 2), Position::new(2, 5, 2)).iter() {
+        model.set_voxel_at(p, Voxel::Full);
+    }
+    for p in Region(Position::new(3, 5, 2), Position::new(6, 5, 2)).iter() {
+        model.set_voxel_at(p, Voxel::Full);
+    }
+    for p in Region(Position::new(6, 3, 2), Position::new(6, 4, 2)).iter() {
+        model.set_voxel_at(p, Voxel::Full);
+    }
+    let mut ai = LayerVoidAI::new(&Config::new());
+    let trace = ai.disassemble(&model);
+    let mut state = State::initial_with_model(&model);
+    state.execute_trace(&trace).unwrap();
+    state.end_check(&Model::initial(10)).unwrap();
+    assert!(ai.high_steps().unwrap() > 0);
+}
@@ -4,9 +4,21 @@ use super::model::*;
 pub mod bfs;
 pub mod builder;
 pub mod config;
+pub mod cuboid;
+pub mod dflt;
+pub mod diff_reassemble;
+pub mod finish;
+pub mod fission_tree;
 pub mod grid_fission;
+pub mod layer_fill;
+pub mod layer_void;
 pub mod naive_reassemble;
+pub mod partition;
+pub mod planner;
 pub mod reassemble_brute_force;
+pub mod reservation;
+pub mod reverse;
+pub mod symmetric;
 pub mod utils;
 pub mod gvoid;
 pub mod gvoid_2d;
@@ -14,14 +26,27 @@ pub mod void;
 pub mod void_assemble;
 
 
+// high_steps は最後に作った trace のうち、harmonics が High だった step の数。数えない AI は None
 pub trait AssembleAI {
     fn assemble(&mut self, model: &Model) -> Vec<Command>;
+
+    fn high_steps(&self) -> Option<usize> {
+        None
+    }
 }
 
 pub trait DisassembleAI {
     fn disassemble(&mut self, model: &Model) -> Vec<Command>;
+
+    fn high_steps(&self) -> Option<usize> {
+        None
+    }
 }
 
 pub trait ReassembleAI {
     fn reassemble(&mut self, source: &Model, target: &Model) -> Vec<Command>;
+
+    fn high_steps(&self) -> Option<usize> {
+        None
+    }
 }
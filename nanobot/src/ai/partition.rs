@@ -0,0 +1,221 @@
+use ai::utils::*;
+use common::*;
+use model::*;
+use std::cmp::{max, min};
+
+// モデルの X-Z 平面での足場を、bot 1 体ずつが受け持つ長方形に分ける道具。
+//
+// 長方形は 1 本の線で 2 つに分けることを繰り返して作り (kd 木)、分けた両側に bot の数をおよそ半分ずつ割り振る。
+// 線の位置は、両側の見積もりの大きい方が一番小さくなる所にする。
+// 1 つの長方形にかかる step 数は、原点から長方形の角までの移動と、長方形の上を層ごとに蛇行しながら
+// Fill (Void) する分の和で見積もる (grid_fission.rs, void.rs の generate_region_commands)。
+// bot を増やすと Fission の回数と bot ごとのエネルギーが増えるので、見積もったエネルギーが一番小さい数で分ける。
+// できた長方形は Full の voxel がある柱だけを囲むように縮め、y は 0 からその中の一番高い voxel までにする。
+
+pub const MAX_BOTS: usize = 40;
+// SMove で 1 step に進める距離
+const MOVE_PER_STEP: i64 = 15;
+// Fission の木の 1 段で、Fission と子の移動にかかる step 数の見積もり
+const FISSION_ROUND_STEPS: i64 = 3;
+
+// X-Z 平面での柱の数
+fn area(rect: &Region) -> usize {
+    ((rect.1.x - rect.0.x + 1) * (rect.1.z - rect.0.z + 1)) as usize
+}
+
+// 柱ごとの Full の voxel の数の 2 次元累積和と、蛇行する層の数
+struct Footprint {
+    r: usize,
+    sums: Vec<Vec<i64>>,
+    layers: i64,
+}
+
+impl Footprint {
+    fn new(model: &Model, bounding: &Bounding) -> Self {
+        let r = model.matrix.len();
+        let mut sums = vec![vec![0; r + 1]; r + 1];
+        for x in 0..r {
+            for z in 0..r {
+                let column = (0..r)
+                    .filter(|&y| model.matrix[x][y][z] == Voxel::Full)
+                    .count() as i64;
+                sums[x + 1][z + 1] = sums[x][z + 1] + sums[x + 1][z] - sums[x][z] + column;
+            }
+        }
+        Footprint {
+            r,
+            sums,
+            layers: bounding.max_y as i64 + 2,
+        }
+    }
+
+    fn count(&self, rect: &Region) -> i64 {
+        let (x0, z0) = (rect.0.x as usize, rect.0.z as usize);
+        let (x1, z1) = (rect.1.x as usize + 1, rect.1.z as usize + 1);
+        self.sums[x1][z1] - self.sums[x0][z1] - self.sums[x1][z0] + self.sums[x0][z0]
+    }
+
+    // rect を k 体で分けたときの、1 体あたりの step 数の見積もり
+    fn cost(&self, rect: &Region, k: usize) -> i64 {
+        let work = self.layers * area(rect) as i64 + self.count(rect);
+        let travel = (rect.0.x + rect.0.z) as i64 + self.layers;
+        (travel + MOVE_PER_STEP - 1) / MOVE_PER_STEP + (work + k as i64 - 1) / k as i64
+    }
+
+    // rect を k 個以下の長方形に分ける
+    fn split(&self, rect: Region, k: usize, leaves: &mut Vec<Region>) {
+        let k = min(k, area(&rect));
+        if k <= 1 {
+            leaves.push(rect);
+            return;
+        }
+        let mut best: Option<(i64, Region, Region, usize)> = None;
+        let cuts = (rect.0.x..rect.1.x)
+            .map(|x| {
+                (
+                    Position::new(x, 0, rect.1.z),
+                    Position::new(x + 1, 0, rect.0.z),
+                )
+            })
+            .chain((rect.0.z..rect.1.z).map(|z| {
+                (
+                    Position::new(rect.1.x, 0, z),
+                    Position::new(rect.0.x, 0, z + 1),
+                )
+            }));
+        for (end, start) in cuts {
+            let (a, b) = (Region(rect.0, end), Region(start, rect.1));
+            // 見積もりが大きい方に bot を多く割り振る。ただし柱の数より多くは割り振らない。
+            let half = if self.cost(&a, 1) >= self.cost(&b, 1) {
+                k - k / 2
+            } else {
+                k / 2
+            };
+            let ka = min(max(half, k.saturating_sub(area(&b))), area(&a));
+            let score = max(self.cost(&a, ka), self.cost(&b, k - ka));
+            if best.as_ref().map_or(true, |&(s, _, _, _)| score < s) {
+                best = Some((score, a, b, ka));
+            }
+        }
+        let (_, a, b, ka) = best.unwrap();
+        self.split(a, ka, leaves);
+        self.split(b, k - ka, leaves);
+    }
+
+    // Full の voxel がある柱だけを囲むように縮めて、y を 0 から一番高い voxel までにする。
+    // Full の voxel がなければ None。
+    fn shrink(&self, model: &Model, rect: &Region) -> Option<Region> {
+        if self.count(rect) == 0 {
+            return None;
+        }
+        let mut rect = *rect;
+        let line = |x0: i32, z0: i32, x1: i32, z1: i32| {
+            self.count(&Region(Position::new(x0, 0, z0), Position::new(x1, 0, z1)))
+        };
+        while line(rect.0.x, rect.0.z, rect.0.x, rect.1.z) == 0 {
+            rect.0.x += 1;
+        }
+        while line(rect.1.x, rect.0.z, rect.1.x, rect.1.z) == 0 {
+            rect.1.x -= 1;
+        }
+        while line(rect.0.x, rect.0.z, rect.1.x, rect.0.z) == 0 {
+            rect.0.z += 1;
+        }
+        while line(rect.0.x, rect.1.z, rect.1.x, rect.1.z) == 0 {
+            rect.1.z -= 1;
+        }
+        let mut top = 0;
+        for x in rect.0.x..=rect.1.x {
+            for z in rect.0.z..=rect.1.z {
+                for y in (top..self.r).rev() {
+                    if model.matrix[x as usize][y][z as usize] == Voxel::Full {
+                        top = y;
+                        break;
+                    }
+                }
+            }
+        }
+        rect.1.y = top as i32;
+        Some(rect)
+    }
+}
+
+// model の足場を max_bots 個以下の長方形に分ける。どの長方形にも Full の voxel があり、
+// 長方形どうしは X-Z 平面で重ならない。model が空なら空の Vec を返す。
+pub fn partition_footprint(model: &Model, max_bots: usize) -> Vec<Region> {
+    let bounding = match calc_bounding_box(model) {
+        Some(b) => b,
+        None => return vec![],
+    };
+    let footprint = Footprint::new(model, &bounding);
+    let whole = Region(
+        Position::new(bounding.min_x, 0, bounding.min_z),
+        Position::new(bounding.max_x, 0, bounding.max_z),
+    );
+    let r = model.matrix.len() as i64;
+    let mut best: Option<(i64, Vec<Region>)> = None;
+    for n in 1..=min(max_bots, MAX_BOTS) {
+        let mut leaves = vec![];
+        footprint.split(whole, n, &mut leaves);
+        let mut rounds = 0;
+        while (1 << rounds) < leaves.len() {
+            rounds += 1;
+        }
+        let steps = FISSION_ROUND_STEPS * rounds
+            + leaves
+                .iter()
+                .map(|rect| footprint.cost(rect, 1))
+                .max()
+                .unwrap();
+        let energy = steps * (3 * r * r * r + 20 * leaves.len() as i64);
+        if best.as_ref().map_or(true, |&(e, _)| energy < e) {
+            best = Some((energy, leaves));
+        }
+    }
+    best.unwrap()
+        .1
+        .iter()
+        .filter_map(|rect| footprint.shrink(model, rect))
+        .collect()
+}
+
+#[test]
+fn test_partition_footprint() {
+    use generator::{generate, Shape};
+    for &shape in [Shape::Tower, Shape::Blob, Shape::Pillars].iter() {
+        let model = generate(shape, 20, 0);
+        let rects = partition_footprint(&model, MAX_BOTS);
+        assert!(!rects.is_empty() && rects.len() <= MAX_BOTS);
+        // どの Full の voxel もちょうど 1 つの長方形に入る
+        let r = model.matrix.len();
+        for x in 0..r {
+            for y in 0..r {
+                for z in 0..r {
+                    let p = Position::new(x as i32, y as i32, z as i32);
+                    if model.voxel_at(p) == Voxel::Full {
+                        assert_eq!(rects.iter().filter(|rect| rect.contains(p)).count(), 1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_partition_footprint_balance() {
+    // 半分だけが高い床では、高い側を細かく分ける
+    let r = 20;
+    let mut model = Model::initial(r);
+    for x in 0..r {
+        for z in 0..r {
+            let height = if x < r / 2 { 1 } else { 15 };
+            for y in 0..height {
+                model.set_voxel_at(Position::new(x as i32, y, z as i32), Voxel::Full);
+            }
+        }
+    }
+    let rects = partition_footprint(&model, 8);
+    assert_eq!(rects.len(), 8);
+    let low = rects.iter().filter(|rect| rect.1.x < r as i32 / 2).count();
+    assert!(low < 4, "{:?}", rects);
+}
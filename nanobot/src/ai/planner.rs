@@ -0,0 +1,638 @@
+use common::*;
+use geometry::corners;
+use model::*;
+use state::State;
+use std::cmp::{max, Reverse};
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+// 複数の bot を、Full の voxel と他の bot を避けて動かすための道具。
+// State で 1 step ずつ確かめながら trace を作る。
+// bot は床の縁に並べてから使い、最後は 2 体ずつ Fusion して原点に戻す (fission_tree.rs)。
+
+// 曲がるとコマンドが増えるので、経路探索で曲がるときに足すコスト
+const TURN_COST: i32 = 2;
+// plan_moves で、移動距離あたりこれだけの状態を調べても経路が見つからなければ後回しにする
+const SEARCH_LIMIT_FACTOR: usize = 64;
+
+// 同時に動かす bot とそのコマンド列の組の列
+pub type Waves = Vec<Vec<(usize, Vec<Command>)>>;
+
+// 床の縁を原点から一周する順に並べた、i 番目の bot を置く位置。
+// x = 0, R-1 や z = 0, R-1 には Full の voxel がないので、4 * (R - 1) 体まで置ける。
+pub fn home_position(i: usize, r: usize) -> Position {
+    let side = r as i32 - 1;
+    let i = i as i32;
+    assert!(i < 4 * side);
+    if i <= side {
+        Position::new(i, 0, 0)
+    } else if i <= 2 * side {
+        Position::new(side, 0, i - side)
+    } else if i <= 3 * side {
+        Position::new(3 * side - i, 0, side)
+    } else {
+        Position::new(0, 0, 4 * side - i)
+    }
+}
+
+// 単位長さの移動の列を SMove と LMove にまとめる
+pub fn path_commands(path: &[Position]) -> Vec<Command> {
+    let mut runs: Vec<(Position, i32)> = vec![];
+    for w in path.windows(2) {
+        let d = w[1] - &w[0];
+        match runs.last_mut() {
+            Some(ref mut last) if last.0 == d && last.1 < 15 => {
+                last.1 += 1;
+                continue;
+            }
+            _ => {}
+        }
+        runs.push((d, 1));
+    }
+    let mut commands = vec![];
+    let mut i = 0;
+    while i < runs.len() {
+        let (d1, l1) = runs[i];
+        if i + 1 < runs.len() && l1 <= 5 && runs[i + 1].1 <= 5 && runs[i + 1].0 != d1 {
+            let (d2, l2) = runs[i + 1];
+            commands.push(Command::LMove(
+                SLCD::new(d1.x * l1, d1.y * l1, d1.z * l1),
+                SLCD::new(d2.x * l2, d2.y * l2, d2.z * l2),
+            ));
+            i += 2;
+        } else {
+            commands.push(Command::SMove(LLCD::new(d1.x * l1, d1.y * l1, d1.z * l1)));
+            i += 1;
+        }
+    }
+    commands
+}
+
+// p から goal までの移動距離と、曲がる回数の下限から見積もったコスト
+fn turn_heuristic(p: &Position, goal: &Position) -> i32 {
+    let d = *goal - p;
+    let axes = [d.x, d.y, d.z].iter().filter(|&&v| v != 0).count() as i32;
+    d.manhattan_length() + TURN_COST * max(axes - 1, 0)
+}
+
+// stands の (立てる位置の候補, 角) の角ごとに、他の角と重ならないように立てる位置を 1 つずつ選ぶ。
+// 候補の前にあるものから試して、取り合いになったら先に選んだ角を他の候補に移す (二部マッチング)。
+pub fn match_stands(stands: &[(Vec<Position>, Position)]) -> Option<Vec<Position>> {
+    let mut owner = HashMap::new();
+    for i in 0..stands.len() {
+        if !augment(stands, i, &mut owner, &mut HashSet::new()) {
+            return None;
+        }
+    }
+    let mut ret = vec![Position::zero(); stands.len()];
+    for (h, &i) in owner.iter() {
+        ret[i] = *h;
+    }
+    Some(ret)
+}
+
+fn augment(
+    stands: &[(Vec<Position>, Position)],
+    i: usize,
+    owner: &mut HashMap<Position, usize>,
+    visited: &mut HashSet<Position>,
+) -> bool {
+    for h in stands[i].0.iter() {
+        if !visited.insert(*h) {
+            continue;
+        }
+        let prev = owner.get(h).cloned();
+        if prev.map_or(true, |j| augment(stands, j, owner, visited)) {
+            owner.insert(*h, i);
+            return true;
+        }
+    }
+    false
+}
+
+pub struct Planner {
+    pub r: usize,
+    pub state: State,
+    // 今の (step を実行した後の) モデル
+    pub current: Model,
+    // bid 順の bot の位置
+    pub bots: Vec<Position>,
+    pub trace: Vec<Command>,
+    // harmonics が High のまま実行した step の数
+    pub high_steps: usize,
+}
+
+impl Planner {
+    pub fn new(model: &Model) -> Self {
+        let r = model.matrix.len();
+        Planner {
+            r,
+            state: State::initial_with_model(model),
+            current: model.clone(),
+            bots: vec![Position::zero()],
+            trace: vec![],
+            high_steps: 0,
+        }
+    }
+
+    // 途中まで進めた state から続きを作る。trace はこの後に足すコマンドだけになる。
+    pub fn from_state(state: &State) -> Self {
+        let current = state.get_model();
+        Planner {
+            r: current.matrix.len(),
+            state: state.clone(),
+            current,
+            bots: state.get_bots().iter().map(|b| b.pos).collect(),
+            trace: vec![],
+            high_steps: 0,
+        }
+    }
+
+    pub fn is_valid_coordinate(&self, p: &Position) -> bool {
+        let r = self.r as i32;
+        0 <= p.x && p.x < r && 0 <= p.y && p.y < r && 0 <= p.z && p.z < r
+    }
+
+    pub fn is_free(&self, p: &Position) -> bool {
+        self.is_valid_coordinate(p) && self.current.voxel_at(*p) == Voxel::Void
+    }
+
+    // State で実行できないコマンドなら何もせずに None を返す。呼び出し側はそこで計画をあきらめる。
+    pub fn step(&mut self, commands: Vec<Command>) -> Option<()> {
+        let high = self.state.get_harmonics() == Harmonics::High;
+        self.state.update_time_step(&commands).ok()?;
+        if high {
+            self.high_steps += 1;
+        }
+        self.trace.extend(commands);
+        Some(())
+    }
+
+    // 原点の bot から count 体を床の縁に並べる
+    pub fn spawn(&mut self, count: usize) -> Option<()> {
+        for i in 0..count - 1 {
+            let d = home_position(i + 1, self.r) - &home_position(i, self.r);
+            let mut commands = vec![Command::Wait; self.bots.len()];
+            commands[i] = Command::Fission(NCD::new(d.x, d.y, d.z), count - 2 - i);
+            self.step(commands)?;
+            self.bots.push(home_position(i + 1, self.r));
+        }
+        Some(())
+    }
+
+    // from から is_goal を満たす位置までの、obstacles を通らない経路を探す。
+    // 状態は (位置, 最後に動いた向き) で、曲がるときは TURN_COST を足す。
+    pub fn find_path<G, H>(
+        &self,
+        from: &Position,
+        obstacles: &HashSet<Position>,
+        is_goal: G,
+        heuristic: H,
+    ) -> Option<Vec<Position>>
+    where
+        G: Fn(&Position) -> bool,
+        H: Fn(&Position) -> i32,
+    {
+        self.find_path_within(from, obstacles, is_goal, heuristic, usize::max_value())
+    }
+
+    // find_path と同じだが、limit 個の状態を調べても見つからなければ諦める
+    fn find_path_within<G, H>(
+        &self,
+        from: &Position,
+        obstacles: &HashSet<Position>,
+        is_goal: G,
+        heuristic: H,
+        limit: usize,
+    ) -> Option<Vec<Position>>
+    where
+        G: Fn(&Position) -> bool,
+        H: Fn(&Position) -> i32,
+    {
+        let dirs = adjacent(Position::zero());
+        let start = (*from, dirs.len());
+        let mut dist = HashMap::new();
+        let mut parent = HashMap::<(Position, usize), (Position, usize)>::new();
+        let mut que = BinaryHeap::new();
+        dist.insert(start, 0);
+        que.push(Reverse((heuristic(from), 0, start)));
+        let mut expanded = 0;
+        while let Some(Reverse((_, cost, (p, dir)))) = que.pop() {
+            if dist[&(p, dir)] < cost {
+                continue;
+            }
+            expanded += 1;
+            if expanded > limit {
+                return None;
+            }
+            if is_goal(&p) {
+                let mut path = vec![p];
+                let mut s = (p, dir);
+                while let Some(&prev) = parent.get(&s) {
+                    path.push(prev.0);
+                    s = prev;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (ndir, d) in dirs.iter().enumerate() {
+                let next = p + d;
+                if !self.is_free(&next) || obstacles.contains(&next) {
+                    continue;
+                }
+                let ncost = cost + 1 + if dir != ndir { TURN_COST } else { 0 };
+                let s = (next, ndir);
+                if dist.get(&s).map_or(false, |&c| c <= ncost) {
+                    continue;
+                }
+                dist.insert(s, ncost);
+                parent.insert(s, (p, dir));
+                que.push(Reverse((ncost + heuristic(&next), ncost, s)));
+            }
+        }
+        None
+    }
+
+    // bot を goals に動かす計画を立てる。
+    // 経路が他の bot の経路や位置と重ならない bot を同じ wave にまとめて、同時に動かす。
+    // wave に分けられなければ、予約表で全部の bot を時刻をずらして同時に動かす (reservation.rs)。
+    // 狭い通路の手前の行き先に先に入って奥へ行く bot をふさぐときは、奥へ行く bot を先に動かして立て直す。
+    pub fn plan_moves(&self, goals: &[(usize, Position)]) -> Option<Waves> {
+        self.plan_moves_with(goals, false)
+            .or_else(|| self.plan_timed_moves(goals))
+            .or_else(|| self.plan_moves_with(goals, true))
+    }
+
+    // yielding なら、まだ動いていない他の bot の通り道にある行き先には、その bot が通るまで入らない
+    fn plan_moves_with(&self, goals: &[(usize, Position)], yielding: bool) -> Option<Waves> {
+        let mut positions = self.bots.clone();
+        let mut rest = goals
+            .iter()
+            .filter(|&&(i, goal)| positions[i] != goal)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut waves = vec![];
+        while !rest.is_empty() {
+            let mut passing = if yielding {
+                self.passing_cells(&positions, &rest)
+            } else {
+                HashSet::new()
+            };
+            let (mut wave, next_rest) = loop {
+                let (wave, next_rest) = self.plan_wave(&mut positions, &rest, &passing);
+                // 譲り合って誰も動けないなら、譲らずに動かす
+                if wave.is_empty() && !passing.is_empty() {
+                    passing.clear();
+                    continue;
+                }
+                break (wave, next_rest);
+            };
+            if wave.is_empty() {
+                // 行き先に他の bot がいて動けないときは、その bot を行き先でない位置によける
+                let targets = rest.iter().map(|g| g.1).collect::<HashSet<_>>();
+                let j = rest
+                    .iter()
+                    .filter_map(|g| positions.iter().position(|p| *p == g.1))
+                    .next()?;
+                let mut obstacles = positions.iter().cloned().collect::<HashSet<_>>();
+                obstacles.remove(&positions[j]);
+                let path = self.find_path(
+                    &positions[j],
+                    &obstacles,
+                    |p| !targets.contains(p),
+                    |_| 0,
+                )?;
+                positions[j] = *path.last().unwrap();
+                wave.push((j, path_commands(&path)));
+            }
+            waves.push(wave);
+            rest = next_rest;
+        }
+        Some(waves)
+    }
+
+    // rest の bot を、他の bot の経路や位置と重ならない限り動かす。動かした bot の位置は positions に反映する。
+    // 返り値は (動かす bot と経路, 動かせなかった bot と行き先)
+    fn plan_wave(
+        &self,
+        positions: &mut Vec<Position>,
+        rest: &[(usize, Position)],
+        passing: &HashSet<Position>,
+    ) -> (Vec<(usize, Vec<Command>)>, Vec<(usize, Position)>) {
+        let mut obstacles = positions.iter().cloned().collect::<HashSet<_>>();
+        let mut wave = vec![];
+        let mut next_rest = vec![];
+        for &(i, goal) in rest.iter() {
+            obstacles.remove(&positions[i]);
+            // 出発点か行き先がふさがっていれば探すまでもない
+            if obstacles.contains(&goal)
+                || passing.contains(&goal)
+                || self.is_enclosed(&goal, &obstacles)
+                || self.is_enclosed(&positions[i], &obstacles)
+            {
+                obstacles.insert(positions[i]);
+                next_rest.push((i, goal));
+                continue;
+            }
+            // wave の最初の bot 以外は、遠回りになるなら次の wave に回す
+            let limit = if wave.is_empty() {
+                usize::max_value()
+            } else {
+                SEARCH_LIMIT_FACTOR * ((positions[i] - &goal).manhattan_length() as usize + 1)
+            };
+            let path = self.find_path_within(
+                &positions[i],
+                &obstacles,
+                |p| *p == goal,
+                |p| turn_heuristic(p, &goal),
+                limit,
+            );
+            match path {
+                Some(path) => {
+                    obstacles.extend(path.iter().cloned());
+                    wave.push((i, path_commands(&path)));
+                    positions[i] = goal;
+                }
+                None => {
+                    obstacles.insert(positions[i]);
+                    next_rest.push((i, goal));
+                }
+            }
+        }
+        (wave, next_rest)
+    }
+
+    // rest の bot が他の bot を無視して行き先まで動くときに通る位置。行き先は含めない。
+    fn passing_cells(&self, positions: &[Position], rest: &[(usize, Position)]) -> HashSet<Position> {
+        let mut ret = HashSet::new();
+        for &(i, goal) in rest.iter() {
+            let path = self.find_path(&positions[i], &HashSet::new(), |p| *p == goal, |p| {
+                turn_heuristic(p, &goal)
+            });
+            if let Some(path) = path {
+                ret.extend(path[..path.len() - 1].iter().cloned());
+            }
+        }
+        ret
+    }
+
+    pub fn execute_moves(&mut self, waves: Waves) -> Option<()> {
+        for wave in waves.into_iter() {
+            let len = wave.iter().map(|w| w.1.len()).max().unwrap_or(0);
+            for t in 0..len {
+                let mut commands = vec![Command::Wait; self.bots.len()];
+                for &(i, ref path) in wave.iter() {
+                    if t < path.len() {
+                        commands[i] = path[t];
+                    }
+                }
+                for (i, command) in commands.iter().enumerate() {
+                    match *command {
+                        Command::SMove(llcd) => self.bots[i] = self.bots[i] + &llcd,
+                        Command::LMove(slcd1, slcd2) => {
+                            self.bots[i] = self.bots[i] + &slcd1 + &slcd2
+                        }
+                        _ => {}
+                    }
+                }
+                self.step(commands)?;
+            }
+        }
+        Some(())
+    }
+
+    fn is_enclosed(&self, p: &Position, obstacles: &HashSet<Position>) -> bool {
+        adjacent(*p)
+            .iter()
+            .all(|q| !self.is_free(q) || obstacles.contains(q))
+    }
+
+    // p から blocked を通らずに一番上の面まで行けるなら true。一番上の面には Full の voxel がない。
+    pub fn can_escape(&self, p: &Position, blocked: &HashSet<Position>) -> bool {
+        let top = self.r as i32 - 1;
+        self.find_path(p, blocked, |q| q.y == top, |q| top - q.y)
+            .is_some()
+    }
+
+    pub fn set_region(&mut self, region: &Region, voxel: Voxel) {
+        for p in region.iter() {
+            self.current.set_voxel_at(p, voxel);
+        }
+    }
+
+    // 他の bot を動かさずに bot i が行ける位置
+    pub fn reachable(&self, i: usize) -> HashSet<Position> {
+        let mut visited = HashSet::new();
+        let mut que = VecDeque::new();
+        visited.insert(self.bots[i]);
+        que.push_back(self.bots[i]);
+        while let Some(p) = que.pop_front() {
+            for q in adjacent(p) {
+                if !self.is_free(&q) || visited.contains(&q) || self.bots.contains(&q) {
+                    continue;
+                }
+                visited.insert(q);
+                que.push_back(q);
+            }
+        }
+        visited
+    }
+
+    // bot i を is_goal を満たす位置まで動かす経路の行き先。
+    // movers に入っている bot は先に動くので障害物にせず、代わりに blocked を通らない。
+    pub fn find_goal<G>(
+        &self,
+        i: usize,
+        movers: &[usize],
+        blocked: &HashSet<Position>,
+        is_goal: G,
+    ) -> Option<Position>
+    where
+        G: Fn(&Position) -> bool,
+    {
+        let mut obstacles = blocked.clone();
+        obstacles.extend(
+            self.bots
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i && !movers.contains(&j))
+                .map(|(_, b)| *b),
+        );
+        let path = self.find_path(&self.bots[i], &obstacles, is_goal, |_| 0)?;
+        path.last().cloned()
+    }
+
+    // regions の各角のすぐ上に bot を 1 体ずつ動かして、GFill や GVoid を下向きに出せるようにする。
+    // 返り値は (bot, 角)。bot を動かせなければ None
+    pub fn gather_above<F>(
+        &mut self,
+        regions: &[Region],
+        can_wait: F,
+    ) -> Option<Vec<(usize, Position)>>
+    where
+        F: Fn(&Position) -> bool,
+    {
+        let stands = regions
+            .iter()
+            .flat_map(corners)
+            .map(|c| (vec![c + &Position::new(0, 1, 0)], c))
+            .collect::<Vec<_>>();
+        self.gather_at(regions, &stands, can_wait)
+    }
+
+    // stands の (立てる位置の候補, 角) の角ごとに、候補のどれかに bot を 1 体ずつ動かす。
+    // 候補は前にあるものほど優先する。
+    // bot 0 は Flip のために使わない。行き先や regions の中、can_wait を満たさない位置にいる他の bot は、
+    // can_wait を満たす位置にどかす。
+    // 返り値は (bot, 角)。立てる位置や bot が足りなかったり、bot が互いにふさいで動かせなかったりすれば None
+    pub fn gather_at<F>(
+        &mut self,
+        regions: &[Region],
+        stands: &[(Vec<Position>, Position)],
+        can_wait: F,
+    ) -> Option<Vec<(usize, Position)>>
+    where
+        F: Fn(&Position) -> bool,
+    {
+        self.gather_with(regions, stands, &can_wait, true)
+            .or_else(|| self.gather_with(regions, stands, &can_wait, false))
+    }
+
+    // keep_places なら、行き先に立っている bot にはその行き先を使わせる。
+    // そうでなければ、その bot の奥に閉じ込められた bot が出られるように、別の bot に使わせる。
+    fn gather_with<F>(
+        &mut self,
+        regions: &[Region],
+        stands: &[(Vec<Position>, Position)],
+        can_wait: &F,
+        keep_places: bool,
+    ) -> Option<Vec<(usize, Position)>>
+    where
+        F: Fn(&Position) -> bool,
+    {
+        let cells = match_stands(stands)?;
+        let mut candidates = vec![];
+        for (si, h) in cells.iter().enumerate() {
+            for (bi, b) in self.bots.iter().enumerate().skip(1) {
+                candidates.push(((*b - h).manhattan_length(), bi, si));
+            }
+        }
+        candidates.sort();
+        let mut assigned: Vec<Option<usize>> = vec![None; stands.len()];
+        let mut used_bots = HashSet::new();
+        for &(_, bi, si) in candidates.iter() {
+            if assigned[si].is_some() || used_bots.contains(&bi) {
+                continue;
+            }
+            // 他の bot が立っている位置はその bot しか使えない。bot 0 はどかす。
+            let occupied = self.bots
+                .iter()
+                .enumerate()
+                .any(|(j, b)| j != bi && j != 0 && *b == cells[si]);
+            if (keep_places && occupied) || (!keep_places && self.bots[bi] == cells[si]) {
+                continue;
+            }
+            assigned[si] = Some(bi);
+            used_bots.insert(bi);
+        }
+        let mut goals = vec![];
+        let mut ret = vec![];
+        for (si, a) in assigned.into_iter().enumerate() {
+            let bi = a?;
+            goals.push((bi, cells[si]));
+            ret.push((bi, stands[si].1));
+        }
+
+        let mut reserved = goals.iter().map(|g| g.1).collect::<HashSet<_>>();
+        let in_regions = |p: &Position| regions.iter().any(|region| region.contains(*p));
+        let evacuees = (0..self.bots.len())
+            .filter(|&i| !used_bots.contains(&i))
+            .filter(|&i| {
+                let p = &self.bots[i];
+                reserved.contains(p) || in_regions(p) || !can_wait(p)
+            })
+            .collect::<Vec<_>>();
+        let movers = goals
+            .iter()
+            .map(|g| g.0)
+            .chain(evacuees.iter().cloned())
+            .collect::<Vec<_>>();
+        for &i in evacuees.iter() {
+            let goal = self.find_goal(i, &movers, &HashSet::new(), |p| {
+                !reserved.contains(p) && !in_regions(p) && can_wait(p)
+            })?;
+            reserved.insert(goal);
+            goals.push((i, goal));
+        }
+
+        let waves = self.plan_moves(&goals)?;
+        self.execute_moves(waves)?;
+        Some(ret)
+    }
+
+    // 一番上の面から、Full でない voxel を通って行ける位置
+    pub fn open_cells(&self) -> HashSet<Position> {
+        let top = self.r as i32 - 1;
+        let mut visited = HashSet::new();
+        let mut que = VecDeque::new();
+        for x in 0..self.r as i32 {
+            for z in 0..self.r as i32 {
+                let p = Position::new(x, top, z);
+                visited.insert(p);
+                que.push_back(p);
+            }
+        }
+        while let Some(p) = que.pop_front() {
+            for q in adjacent(p) {
+                if !self.is_free(&q) || visited.contains(&q) {
+                    continue;
+                }
+                visited.insert(q);
+                que.push_back(q);
+            }
+        }
+        visited
+    }
+}
+
+#[test]
+fn test_path_commands() {
+    let path = (0..20)
+        .map(|x| Position::new(x, 0, 0))
+        .chain((1..3).map(|z| Position::new(19, 0, z)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        path_commands(&path),
+        vec![
+            Command::SMove(LLCD::new(15, 0, 0)),
+            Command::LMove(SLCD::new(4, 0, 0), SLCD::new(0, 0, 2)),
+        ]
+    );
+}
+
+#[test]
+fn test_home_position() {
+    let r = 5;
+    let homes = (0..16).map(|i| home_position(i, r)).collect::<Vec<_>>();
+    assert_eq!(homes[0], Position::zero());
+    assert_eq!(homes[4], Position::new(4, 0, 0));
+    assert_eq!(homes[8], Position::new(4, 0, 4));
+    assert_eq!(homes[12], Position::new(0, 0, 4));
+    // 隣同士は接していて、重ならない
+    for i in 0..16 {
+        let d = homes[(i + 1) % 16] - &homes[i];
+        assert_eq!(d.manhattan_length(), 1);
+    }
+}
+
+#[test]
+fn test_step_invalid() {
+    let mut planner = Planner::new(&Model::initial(5));
+    // 床の下には動けないので、trace を変えずに None を返す
+    assert!(planner.step(vec![Command::SMove(LLCD::new(0, -1, 0))]).is_none());
+    assert!(planner.trace.is_empty());
+    assert!(planner.step(vec![Command::Flip]).is_some());
+    assert_eq!(planner.trace, vec![Command::Flip]);
+}
@@ -1,9 +1,9 @@
 use ai::builder::*;
 use ai::config::Config;
 use ai::ReassembleAI;
+use anytime::verify;
 use common::Command;
 use model::Model;
-use state::State;
 
 pub struct ReassembleBruteForceAI {
     best_assembler_commands: Vec<Command>,
@@ -31,9 +31,7 @@ impl ReassembleBruteForceAI {
             let mut disassembler = build_disassembler(&disassembler_name.to_string(), &config, source);
             let commands = disassembler.disassemble(source);
 
-            let mut state = State::initial_with_model(source);
-
-            let score = simulate(&mut state, &commands);
+            let score = simulate(source, &Model::initial(source.matrix.len()), &commands);
             println!("{:?}  : {:?}", disassembler_name, score);
 
             if score < min_energy {
@@ -52,10 +50,8 @@ impl ReassembleBruteForceAI {
         for assembler_name in assemblers {
             let mut assembler = build_assembler(&assembler_name.to_string(), &config, target);
             let commands = assembler.assemble(target);
-            ;
-            let mut state = State::initial(target.matrix.len());
 
-            let score = simulate(&mut state, &commands);
+            let score = simulate(&Model::initial(target.matrix.len()), target, &commands);
 
             println!("{:?}  : {:?}", assembler_name, score);
 
@@ -76,19 +72,9 @@ impl ReassembleBruteForceAI {
 }
 
 
-fn simulate(state: &mut State, trace: &[Command]) -> i64 {
-    let mut offset = 0;
-    while offset < trace.len() {
-        let bot_cnt = state.get_bot_count();
-        match state.update_time_step(&trace[offset..offset + bot_cnt]) {
-            Ok(_) => {}
-            Err(err) => {
-                panic!(err.to_string());
-            }
-        }
-        offset += bot_cnt;
-    }
-    state.get_energy()
+// trace が target まで通らなければ (bfs が詰んだときなど) 選ばれないように、energy を最大にする
+fn simulate(source: &Model, target: &Model, trace: &[Command]) -> i64 {
+    verify(source, target, trace).unwrap_or(<i64>::max_value())
 }
 
 impl ReassembleAI for ReassembleBruteForceAI {
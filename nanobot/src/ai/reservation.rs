@@ -0,0 +1,410 @@
+use ai::planner::*;
+use common::*;
+use std::cmp::{max, Reverse};
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// 時刻つきの予約表を使って、複数の bot を互いにぶつからないように同時に動かす計画を立てる。
+//
+// bot を 1 体ずつ、(位置, 時刻) を状態にした A* で SMove, LMove, Wait の列を探し、
+// 各 step で volatile になる位置 (動く bot が通る位置と、止まっている bot の位置) を予約表に書く。
+// 後から探す bot は、予約された位置を同じ step に使わない。着いた bot はその位置にずっといることにする。
+// まだ探していない bot も最初はその位置にずっといることにして、探せない bot があればその bot を先に探すようにして
+// 最初からやり直す。それでもだめなら、まだ探していない bot は最初の何 step かだけその位置にいることにして
+// (後で探すときに、先に探した bot をよけて動く) やり直す。
+// 地形は is_free で渡すので Planner なしでも呼べる。使っているのは次のところ。
+// - Planner::plan_moves で wave に分けられなかったとき (plan_paths)
+// - BfsAI の移動 (find_timed_path_any)。他の bot の通り道は BfsAI の volatiles で is_free から外すので、予約表は空でよい。
+// - void_assemble で、掘らずに Void の voxel だけを通って行けるとき (find_path)
+// - gvoid, gvoid_2d の最初にモデルの上へ行くときと、最後に原点に戻るとき (find_path)
+// 予約表が空なら同じ位置に後の時刻で着いても意味がないので、位置だけで探す。
+// bot をそろえて同じ向きに動かす gvoid の格子の中や grid_fission の移動は、自分の受け持ちの中が空だとわかっているので
+// move_straight_* のままにしている。
+
+// 移動距離あたりこれだけの状態を調べても経路が見つからなければ諦める
+const SEARCH_LIMIT_FACTOR: usize = 32;
+// 近くへ行く bot も、他の bot をよけて回り道するのでこれだけは調べる
+const MIN_SEARCH_LIMIT: usize = 2000;
+// 探す順を変えてやり直す回数
+const MAX_RETRIES: usize = 4;
+
+// step ごとに予約された位置
+#[derive(Default)]
+pub struct Reservations {
+    // volatile[t] は step t に使われる位置
+    volatile: Vec<HashSet<Position>>,
+    // その時刻以降ずっと bot がいる位置
+    parked: HashMap<Position, usize>,
+    // まだ探していない bot の位置と、そこをふさぐ step の数
+    held: HashMap<Position, usize>,
+    // 位置ごとの、予約されている最後の step
+    last: HashMap<Position, usize>,
+}
+
+impl Reservations {
+    pub fn new() -> Self {
+        Reservations {
+            volatile: vec![],
+            parked: HashMap::new(),
+            held: HashMap::new(),
+            last: HashMap::new(),
+        }
+    }
+
+    // 何も予約されていなければ true
+    pub fn is_empty(&self) -> bool {
+        self.parked.is_empty() && self.held.is_empty() && self.last.is_empty()
+    }
+
+    // step t に p を使えるなら true
+    pub fn is_free(&self, p: &Position, t: usize) -> bool {
+        self.parked.get(p).map_or(true, |&s| t < s)
+            && self.held.get(p).map_or(true, |&n| t >= n)
+            && self.volatile.get(t).map_or(true, |cells| !cells.contains(p))
+    }
+
+    // step t から後ずっと p にいられるなら true
+    pub fn can_stay(&self, p: &Position, t: usize) -> bool {
+        !self.parked.contains_key(p)
+            && self.held.get(p).map_or(true, |&n| n <= t)
+            && self.last.get(p).map_or(true, |&s| s < t)
+    }
+
+    pub fn reserve(&mut self, p: Position, t: usize) {
+        while self.volatile.len() <= t {
+            self.volatile.push(HashSet::new());
+        }
+        self.volatile[t].insert(p);
+        let last = self.last.entry(p).or_insert(t);
+        *last = max(*last, t);
+    }
+
+    pub fn park(&mut self, p: Position, t: usize) {
+        self.parked.insert(p, t);
+    }
+
+    // 最初の steps 個の step の間 p をふさぐ
+    pub fn hold(&mut self, p: Position, steps: usize) {
+        self.held.insert(p, steps);
+    }
+
+    pub fn release(&mut self, p: &Position) {
+        self.held.remove(p);
+    }
+
+    // step 0 に start から動き始める commands の通る位置を予約して、着いた位置に停める
+    pub fn reserve_commands(&mut self, start: Position, commands: &[Command]) {
+        let mut p = start;
+        for (t, command) in commands.iter().enumerate() {
+            for q in move_cells(&p, command) {
+                self.reserve(q, t);
+            }
+            p = destination(&p, command);
+        }
+        self.park(p, commands.len());
+    }
+}
+
+// p にいる bot が command を出した step に volatile になる位置
+pub fn move_cells(p: &Position, command: &Command) -> Vec<Position> {
+    match *command {
+        Command::SMove(llcd) => segment(p, &Position::new(llcd.x(), llcd.y(), llcd.z())),
+        Command::LMove(slcd1, slcd2) => {
+            let d1 = Position::new(slcd1.x(), slcd1.y(), slcd1.z());
+            let d2 = Position::new(slcd2.x(), slcd2.y(), slcd2.z());
+            let mut cells = segment(p, &d1);
+            cells.extend(segment(&(*p + &d1), &d2).into_iter().skip(1));
+            cells
+        }
+        _ => vec![*p],
+    }
+}
+
+// p にいる bot が command を出した後の位置
+fn destination(p: &Position, command: &Command) -> Position {
+    match *command {
+        Command::SMove(llcd) => *p + &llcd,
+        Command::LMove(slcd1, slcd2) => *p + &slcd1 + &slcd2,
+        _ => *p,
+    }
+}
+
+// p から p + d までの位置。d は 1 つの軸に沿っている。
+fn segment(p: &Position, d: &Position) -> Vec<Position> {
+    let len = d.manhattan_length();
+    let unit = if len == 0 {
+        Position::zero()
+    } else {
+        Position::new(d.x / len, d.y / len, d.z / len)
+    };
+    (0..len + 1)
+        .map(|k| *p + &Position::new(unit.x * k, unit.y * k, unit.z * k))
+        .collect()
+}
+
+// from から step t に 1 回で動ける (行き先, コマンド)。通る位置は is_free で、予約されていない。
+fn moves<F>(is_free: &F, table: &Reservations, from: &Position, t: usize) -> Vec<(Position, Command)>
+where
+    F: Fn(&Position) -> bool,
+{
+    let free = |p: &Position| is_free(p) && table.is_free(p, t);
+    let dirs = adjacent(Position::zero());
+    let mut ret = vec![];
+    for d1 in dirs.iter() {
+        for l1 in 1..16 {
+            let p1 = *from + &Position::new(d1.x * l1, d1.y * l1, d1.z * l1);
+            if !free(&p1) {
+                break;
+            }
+            ret.push((p1, Command::SMove(LLCD::new(d1.x * l1, d1.y * l1, d1.z * l1))));
+            if l1 > 5 {
+                continue;
+            }
+            for d2 in dirs.iter().filter(|d2| d2.x * d1.x + d2.y * d1.y + d2.z * d1.z == 0) {
+                for l2 in 1..6 {
+                    let p2 = p1 + &Position::new(d2.x * l2, d2.y * l2, d2.z * l2);
+                    if !free(&p2) {
+                        break;
+                    }
+                    ret.push((
+                        p2,
+                        Command::LMove(
+                            SLCD::new(d1.x * l1, d1.y * l1, d1.z * l1),
+                            SLCD::new(d2.x * l2, d2.y * l2, d2.z * l2),
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+    ret
+}
+
+// from から goal まで、table の予約を避けて動くコマンドの列を探す。Wait は避けるために必要なときだけ入る。
+// 着いた後もずっと goal にいられなければ、着いたことにしない。
+pub fn find_timed_path<F>(is_free: &F, table: &Reservations, from: &Position, goal: &Position) -> Option<Vec<Command>>
+where
+    F: Fn(&Position) -> bool,
+{
+    find_timed_path_any(is_free, table, from, &[*goal]).map(|(_, commands)| commands)
+}
+
+// 他の bot がいないときに、from から goal まで is_free の位置だけを通って動くコマンドの列
+pub fn find_path<F>(is_free: &F, from: &Position, goal: &Position) -> Option<Vec<Command>>
+where
+    F: Fn(&Position) -> bool,
+{
+    find_timed_path(is_free, &Reservations::new(), from, goal)
+}
+
+// goals のどれかまで動くコマンドの列と、着いた位置。step 数が同じなら goals の前にあるものへ行く。
+pub fn find_timed_path_any<F>(
+    is_free: &F,
+    table: &Reservations,
+    from: &Position,
+    goals: &[Position],
+) -> Option<(Position, Vec<Command>)>
+where
+    F: Fn(&Position) -> bool,
+{
+    let distance = |p: &Position| goals.iter().map(|g| (*g - p).manhattan_length()).min().unwrap_or(0);
+    let limit = max(
+        SEARCH_LIMIT_FACTOR * (distance(from) as usize + 1),
+        MIN_SEARCH_LIMIT,
+    );
+    // 1 step で動けるのは最長 15 なので、残りの step 数の下限
+    let lower_bound = |p: &Position| (distance(p) as usize + 14) / 15;
+    // 予約がなければ、同じ位置に後から着いても先に着いたときより良くならない
+    let timeless = table.is_empty();
+    let key = |p: Position, t: usize| (p, if timeless { 0 } else { t });
+    let mut parent = HashMap::<(Position, usize), ((Position, usize), Command)>::new();
+    // 見つけた中で一番早く着く時刻
+    let mut arrival = HashMap::new();
+    let mut closed = HashSet::new();
+    let mut que = BinaryHeap::new();
+    que.push(Reverse((lower_bound(from), distance(from), 0, *from)));
+    while let Some(Reverse((_, _, t, p))) = que.pop() {
+        if !closed.insert(key(p, t)) {
+            continue;
+        }
+        if closed.len() > limit {
+            return None;
+        }
+        if goals.contains(&p) && table.can_stay(&p, t) {
+            let mut commands = vec![];
+            let mut s = key(p, t);
+            while let Some(&(prev, command)) = parent.get(&s) {
+                commands.push(command);
+                s = prev;
+            }
+            commands.reverse();
+            return Some((p, commands));
+        }
+        if !table.is_free(&p, t) {
+            continue;
+        }
+        let wait = if timeless { None } else { Some((p, Command::Wait)) };
+        let next = moves(is_free, table, &p, t).into_iter().chain(wait);
+        for (q, command) in next {
+            let s = key(q, t + 1);
+            if closed.contains(&s) || arrival.get(&s).map_or(false, |&a| a <= t + 1) {
+                continue;
+            }
+            arrival.insert(s, t + 1);
+            parent.insert(s, (key(p, t), command));
+            que.push(Reverse((t + 1 + lower_bound(&q), distance(&q), t + 1, q)));
+        }
+    }
+    None
+}
+
+// bots[i] にいる bot を goals の位置に同時に動かす、bot ごとのコマンド列。goals にない bot は動かない。
+// 遠くへ行く bot から順に探すが、他の bot がいる位置へ行く bot はその bot の後に探す。
+pub fn plan_paths<F>(is_free: &F, bots: &[Position], goals: &[(usize, Position)]) -> Option<Vec<(usize, Vec<Command>)>>
+where
+    F: Fn(&Position) -> bool,
+{
+    let mut by_distance = goals
+        .iter()
+        .filter(|&&(i, goal)| bots[i] != goal)
+        .cloned()
+        .collect::<Vec<_>>();
+    by_distance.sort_by_key(|&(i, goal)| Reverse((goal - &bots[i]).manhattan_length()));
+    let mut order = vec![];
+    let mut visited = HashSet::new();
+    for &g in by_distance.iter() {
+        visit_after_leaving(g, &by_distance, bots, &mut visited, &mut order);
+    }
+    // 後で探す bot は、最初にふさぐ step が多いほど先に探した bot をよけやすいが、先に探す bot が待たされる
+    [usize::max_value(), 1, 4]
+        .iter()
+        .filter_map(|&n| plan_in_order(is_free, bots, order.clone(), n))
+        .next()
+}
+
+// order の順に探す。まだ探していない bot は最初の hold_steps 個の step の間その位置をふさぐ。
+fn plan_in_order<F>(
+    is_free: &F,
+    bots: &[Position],
+    mut order: Vec<(usize, Position)>,
+    hold_steps: usize,
+) -> Option<Vec<(usize, Vec<Command>)>>
+where
+    F: Fn(&Position) -> bool,
+{
+    let moving = order.iter().map(|g| g.0).collect::<HashSet<_>>();
+    // 探せなかった bot を先頭に回してやり直すのは、MAX_RETRIES 回までにする
+    for _ in 0..MAX_RETRIES + 1 {
+        let mut table = Reservations::new();
+        for (i, p) in bots.iter().enumerate() {
+            if moving.contains(&i) {
+                table.hold(*p, hold_steps);
+            } else {
+                table.park(*p, 0);
+            }
+        }
+        let mut ret = vec![];
+        let mut failed = None;
+        for (k, &(i, goal)) in order.iter().enumerate() {
+            table.release(&bots[i]);
+            match find_timed_path(is_free, &table, &bots[i], &goal) {
+                Some(commands) => {
+                    table.reserve_commands(bots[i], &commands);
+                    ret.push((i, commands));
+                }
+                None => {
+                    failed = Some(k);
+                    break;
+                }
+            }
+        }
+        match failed {
+            None => return Some(ret),
+            Some(0) => return None,
+            Some(k) => {
+                let g = order.remove(k);
+                order.insert(0, g);
+            }
+        }
+    }
+    None
+}
+
+// g の行き先にいる bot を先に order に入れてから、g を入れる
+fn visit_after_leaving(
+    g: (usize, Position),
+    goals: &[(usize, Position)],
+    bots: &[Position],
+    visited: &mut HashSet<usize>,
+    order: &mut Vec<(usize, Position)>,
+) {
+    if !visited.insert(g.0) {
+        return;
+    }
+    if let Some(&h) = goals.iter().find(|&&(j, _)| bots[j] == g.1) {
+        visit_after_leaving(h, goals, bots, visited, order);
+    }
+    order.push(g);
+}
+
+impl Planner {
+    // plan_moves と同じ行き先に、予約表を使って全部の bot を同時に動かす計画を立てる
+    pub fn plan_timed_moves(&self, goals: &[(usize, Position)]) -> Option<Waves> {
+        plan_paths(&|p: &Position| self.is_free(p), &self.bots, goals).map(|paths| vec![paths])
+    }
+}
+
+#[test]
+fn test_move_cells() {
+    let p = Position::new(1, 1, 1);
+    assert_eq!(move_cells(&p, &Command::Wait), vec![p]);
+    assert_eq!(
+        move_cells(&p, &Command::LMove(SLCD::new(2, 0, 0), SLCD::new(0, 0, -1))),
+        vec![
+            p,
+            Position::new(2, 1, 1),
+            Position::new(3, 1, 1),
+            Position::new(3, 1, 0),
+        ]
+    );
+}
+
+#[test]
+fn test_plan_timed_moves() {
+    use model::*;
+    // 床の縁に並べた bot を、縁に沿って 8 つ先に同時に動かす。前の 4 体は後ろの 4 体がいた位置へ行く。
+    // 縁は真ん中の箱との間が狭く、すれ違うには箱の上を通る。
+    let r = 6;
+    let mut model = Model::initial(r);
+    for p in Region(Position::new(1, 0, 1), Position::new(4, 3, 4)).iter() {
+        model.set_voxel_at(p, Voxel::Full);
+    }
+    let mut planner = Planner::new(&model);
+    planner.spawn(12).unwrap();
+    let goals = (0..12)
+        .map(|i| (i, home_position(i + 8, r)))
+        .collect::<Vec<_>>();
+    let waves = planner.plan_timed_moves(&goals).unwrap();
+    planner.execute_moves(waves).unwrap();
+    for &(i, goal) in goals.iter() {
+        assert_eq!(planner.bots[i], goal);
+    }
+}
+
+#[test]
+fn test_plan_timed_moves_cycle() {
+    use model::Model;
+    // 行き先が輪になっていても、先に動く bot をよけて動けば入れ替われる
+    let r = 6;
+    let mut planner = Planner::new(&Model::initial(r));
+    planner.spawn(12).unwrap();
+    let goals = (0..12)
+        .map(|i| (i, home_position((i + 5) % 12, r)))
+        .collect::<Vec<_>>();
+    let waves = planner.plan_timed_moves(&goals).unwrap();
+    planner.execute_moves(waves).unwrap();
+    for &(i, goal) in goals.iter() {
+        assert_eq!(planner.bots[i], goal);
+    }
+}
@@ -0,0 +1,466 @@
+use ai::builder::*;
+use ai::config::Config;
+use ai::planner::Planner;
+use ai::AssembleAI;
+use ai::DisassembleAI;
+use common::*;
+use geometry::*;
+use model::Model;
+use state::State;
+use std::collections::HashMap;
+use std::error::*;
+use std::fmt;
+
+// trace を時間方向に反転して、source -> target の trace から target -> source の trace を作る。
+// disassemble の trace を反転すると assemble の trace になる (逆も同じ)。
+//
+// Fill <-> Void, GFill <-> GVoid, Fission <-> FusionP/FusionS を入れ替え、移動は逆向きにする。
+// 反転後の bot の bid は元の trace と一致しないことがあるので、コマンドは bot の位置で対応付ける。
+// 各 bot の seeds の数は元の trace と一致するので、Fission の m はそのまま使える。
+// Flip は捨てて、浮いた voxel ができる間だけ harmonics を High にするように入れ直す。
+// 一部だけ Full の領域の GVoid は、GVoid した bot を待たせておき、その step の後に Full だった voxel だけを
+// 直方体に分けて GFill し (cuboid.rs の fill_all)、bot を元の位置に戻す step を足す。
+// 一部だけ Full の領域の GFill は反転できない。
+
+#[derive(Debug)]
+pub struct ReverseError {
+    message: String,
+}
+
+impl ReverseError {
+    pub fn new(message: String) -> ReverseError {
+        ReverseError { message }
+    }
+}
+
+impl fmt::Display for ReverseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ReverseError: {}", self.message)
+    }
+}
+
+impl Error for ReverseError {
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+fn reverse_error(message: String) -> Box<Error> {
+    Box::new(ReverseError::new(message))
+}
+
+// 元の trace の 1 step を反転したもの
+struct ReversedStep {
+    // 元の step を実行した後の bot の位置 -> 反転したコマンド
+    commands: HashMap<Position, Command>,
+    // 元の step を実行する前に浮いた voxel があったか
+    floating: bool,
+    // 反転したコマンドの後に埋める voxel (一部だけ Full の領域の GVoid で消えた voxel)
+    fills: Vec<Position>,
+}
+
+// region の voxel がすべて voxel なら true, すべて voxel でなければ false
+fn region_is(state: &State, region: &Region, voxel: Voxel) -> Result<Option<bool>, Box<Error>> {
+    let mut count = 0;
+    let mut total = 0;
+    for p in region.iter() {
+        if !state.is_valid_coordinate(&p) {
+            return Err(reverse_error(format!("region is out of matrix: {:?}", region)));
+        }
+        total += 1;
+        if state.get_voxel(p) == voxel {
+            count += 1;
+        }
+    }
+    Ok(if count == total {
+        Some(true)
+    } else if count == 0 {
+        Some(false)
+    } else {
+        None
+    })
+}
+
+// bot の位置ごとの反転したコマンドと、その後に埋める voxel
+type Reversed = (HashMap<Position, Command>, Vec<Position>);
+
+// state で commands を実行する前に、反転したコマンドと、その後に埋める voxel を作る
+fn reverse_step(state: &State, commands: &[Command]) -> Result<Reversed, Box<Error>> {
+    let bots = state.get_bots();
+    let mut reversed = HashMap::new();
+    let mut fills = vec![];
+    for (bot, command) in bots.iter().zip(commands.iter()) {
+        let c = bot.pos;
+        match *command {
+            Command::Halt => {
+                return Err(reverse_error(format!(
+                    "Halt must be the last command: bid={:?}",
+                    bot.bid
+                )));
+            }
+            Command::Wait | Command::Flip => {
+                reversed.insert(c, Command::Wait);
+            }
+            Command::SMove(llcd) => {
+                let d = LLCD::new(-llcd.x(), -llcd.y(), -llcd.z());
+                reversed.insert(c + &llcd, Command::SMove(d));
+            }
+            Command::LMove(slcd1, slcd2) => {
+                let d1 = SLCD::new(-slcd2.x(), -slcd2.y(), -slcd2.z());
+                let d2 = SLCD::new(-slcd1.x(), -slcd1.y(), -slcd1.z());
+                reversed.insert(c + &slcd1 + &slcd2, Command::LMove(d1, d2));
+            }
+            Command::Fill(ncd) | Command::Void(ncd) => {
+                let p = c + &ncd;
+                if !state.is_valid_coordinate(&p) {
+                    return Err(reverse_error(format!("voxel is out of matrix: {}", p)));
+                }
+                // すでに Full の voxel への Fill などは何もしていないのと同じ
+                let reversed_command = match (*command, state.get_voxel(p)) {
+                    (Command::Fill(_), Voxel::Void) => Command::Void(ncd),
+                    (Command::Void(_), Voxel::Full) => Command::Fill(ncd),
+                    _ => Command::Wait,
+                };
+                reversed.insert(c, reversed_command);
+            }
+            Command::GFill(ncd, fcd) | Command::GVoid(ncd, fcd) => {
+                let region = Region(c + &ncd, c + &ncd + &fcd);
+                let (voxel, reversed_command) = match *command {
+                    Command::GFill(_, _) => (Voxel::Void, Command::GVoid(ncd, fcd)),
+                    _ => (Voxel::Full, Command::GFill(ncd, fcd)),
+                };
+                let reversed_command = match (region_is(state, &region, voxel)?, *command) {
+                    (Some(true), _) => reversed_command,
+                    (Some(false), _) => Command::Wait,
+                    (None, Command::GVoid(_, _)) => {
+                        // 同じグループの他の bot が先に入れていることがある
+                        for p in region.iter() {
+                            if state.get_voxel(p) == Voxel::Full && !fills.contains(&p) {
+                                fills.push(p);
+                            }
+                        }
+                        Command::Wait
+                    }
+                    (None, _) => {
+                        return Err(reverse_error(format!(
+                            "cannot reverse {:?} on partially filled region: {:?}",
+                            command, region
+                        )));
+                    }
+                };
+                reversed.insert(c, reversed_command);
+            }
+            Command::Fission(ncd, _) => {
+                let d = NCD::new(-ncd.x(), -ncd.y(), -ncd.z());
+                reversed.insert(c, Command::FusionP(ncd));
+                reversed.insert(c + &ncd, Command::FusionS(d));
+            }
+            Command::FusionP(ncd) => {
+                let p = c + &ncd;
+                let secondary = match bots.iter().find(|b| b.pos == p) {
+                    Some(b) => b,
+                    None => {
+                        return Err(reverse_error(format!("no secondary nanobot at {}", p)));
+                    }
+                };
+                reversed.insert(c, Command::Fission(ncd, secondary.seeds.len()));
+            }
+            Command::FusionS(_) => {}
+        }
+    }
+    Ok((reversed, fills))
+}
+
+// state から cells を直方体に分けて GFill し、bot を今の位置に戻す trace を作って state で実行する
+fn fill_cells(state: &mut State, cells: &[Position]) -> Result<Vec<Command>, Box<Error>> {
+    let mut planner = Planner::from_state(state);
+    let mut model = Model::initial(planner.r);
+    for p in cells.iter() {
+        model.set_voxel_at(*p, Voxel::Full);
+    }
+    let boxes = order_grounded(&planner.current, &decompose(&model, Cover::Exact));
+    let homes = planner.bots.iter().cloned().enumerate().collect::<Vec<_>>();
+    let planned = planner.fill_all(boxes).and_then(|_| {
+        let waves = planner.plan_moves(&homes)?;
+        planner.execute_moves(waves)
+    });
+    if planned.is_none() {
+        return Err(reverse_error(format!(
+            "cannot fill {} voxels of a partially filled region",
+            cells.len()
+        )));
+    }
+    *state = planner.state;
+    Ok(planner.trace)
+}
+
+// 1 体だけ Flip して残りは Wait する step
+fn flip_step(n: usize) -> Vec<Command> {
+    let mut commands = vec![Command::Wait; n];
+    commands[0] = Command::Flip;
+    commands
+}
+
+// source から trace を実行して得られるモデルから source に戻す trace を返す
+pub fn reverse_trace(source: &Model, trace: &[Command]) -> Result<Vec<Command>, Box<Error>> {
+    let mut state = State::initial_with_model(source);
+    let mut steps = vec![];
+    let mut offset = 0;
+    loop {
+        let bot_cnt = state.get_bot_count();
+        if bot_cnt == 0 {
+            break;
+        }
+        if offset + bot_cnt > trace.len() {
+            return Err(reverse_error(String::from("trace does not end with Halt")));
+        }
+        let commands = &trace[offset..offset + bot_cnt];
+        offset += bot_cnt;
+        if commands == [Command::Halt] {
+            state.update_time_step(commands)?;
+            break;
+        }
+        let floating = state.has_floating_voxel();
+        let (reversed, fills) = reverse_step(&state, commands)?;
+        state.update_time_step(commands)?;
+        steps.push(ReversedStep {
+            commands: reversed,
+            // 埋めるまでは Full だった voxel が足りないので、浮いた voxel があるかもしれない
+            floating: floating || !fills.is_empty(),
+            fills,
+        });
+    }
+    if offset != trace.len() {
+        return Err(reverse_error(format!(
+            "commands remain after halt: offset={}",
+            offset
+        )));
+    }
+
+    let mut state = State::initial_with_model(&state.get_model());
+    let mut ret = vec![];
+    for (i, step) in steps.iter().enumerate().rev() {
+        let mut commands = vec![];
+        for bot in state.get_bots() {
+            match step.commands.get(&bot.pos) {
+                Some(&command) => commands.push(command),
+                None => {
+                    return Err(reverse_error(format!(
+                        "no reversed command for nanobot at {}: step={}",
+                        bot.pos, i
+                    )));
+                }
+            }
+        }
+        if commands.len() != step.commands.len() {
+            return Err(reverse_error(format!(
+                "nanobots do not match: step={}, expected={}, actual={}",
+                i,
+                step.commands.len(),
+                commands.len()
+            )));
+        }
+
+        // この step の後に浮いた voxel があるなら High, なければ Low にする。
+        // 暇な bot がいればその bot に Flip させ、いなければ Flip だけの step を足す。
+        // High にするのは step の前、Low に戻すのは step の後でないといけない。
+        let high = state.get_harmonics() == Harmonics::High;
+        let mut flip_after = false;
+        if step.floating != high {
+            match commands.iter().position(|c| *c == Command::Wait) {
+                Some(j) => commands[j] = Command::Flip,
+                None if step.floating => {
+                    let flip = flip_step(commands.len());
+                    state.update_time_step(&flip)?;
+                    ret.extend(flip);
+                }
+                None => flip_after = true,
+            }
+        }
+        state.update_time_step(&commands)?;
+        ret.extend(commands);
+        if flip_after {
+            let flip = flip_step(state.get_bot_count());
+            state.update_time_step(&flip)?;
+            ret.extend(flip);
+        }
+        if !step.fills.is_empty() {
+            ret.extend(fill_cells(&mut state, &step.fills)?);
+        }
+    }
+    state.update_time_step(&[Command::Halt])?;
+    ret.push(Command::Halt);
+    state.end_check(source)?;
+    Ok(ret)
+}
+
+// disassembler の trace を反転して assemble する
+// 反転できなかったときは空の trace を返すので、State の検査で失敗する。dflt の trace に切り替えるのは
+// 呼び出し側 (anytime.rs) で、失敗したことも報告される。
+pub struct ReverseAssembleAI {
+    disassembler: Box<DisassembleAI>,
+}
+
+impl ReverseAssembleAI {
+    pub fn new(name: &String, config: &Config, target: &Model) -> Self {
+        let disassembler = build_disassembler(name, config, target);
+        ReverseAssembleAI { disassembler }
+    }
+}
+
+impl AssembleAI for ReverseAssembleAI {
+    fn assemble(&mut self, model: &Model) -> Vec<Command> {
+        let trace = self.disassembler.disassemble(model);
+        match reverse_trace(model, &trace) {
+            Ok(trace) => trace,
+            Err(err) => {
+                eprintln!("failed to reverse the disassembly trace: {}", err);
+                vec![]
+            }
+        }
+    }
+}
+
+// assembler の trace を反転して disassemble する
+// 反転できなかったときは ReverseAssembleAI と同じく空の trace を返す。
+pub struct ReverseDisassembleAI {
+    assembler: Box<AssembleAI>,
+}
+
+impl ReverseDisassembleAI {
+    pub fn new(name: &String, config: &Config, source: &Model) -> Self {
+        let assembler = build_assembler(name, config, source);
+        ReverseDisassembleAI { assembler }
+    }
+}
+
+impl DisassembleAI for ReverseDisassembleAI {
+    fn disassemble(&mut self, model: &Model) -> Vec<Command> {
+        let r = model.matrix.len();
+        let trace = self.assembler.assemble(model);
+        match reverse_trace(&Model::initial(r), &trace) {
+            Ok(trace) => trace,
+            Err(err) => {
+                eprintln!("failed to reverse the assembly trace: {}", err);
+                vec![]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn run_trace(source: &Model, target: &Model, trace: &[Command]) {
+    let mut state = State::initial_with_model(source);
+    state.execute_trace(trace).unwrap();
+    state.end_check(target).unwrap();
+}
+
+#[test]
+fn test_reverse_trace() {
+    let source = Model::initial(5);
+    let mut target = Model::initial(5);
+    target.matrix[2][0][2] = Voxel::Full;
+    target.matrix[1][1][3] = Voxel::Full;
+    target.matrix[1][0][3] = Voxel::Full;
+    let trace = vec![
+        Command::Fission(NCD::new(1, 0, 0), 5),
+        Command::Wait,
+        Command::SMove(LLCD::new(0, 0, 2)),
+        Command::Wait,
+        Command::Fill(NCD::new(1, 0, 0)),
+        // (1, 1, 3) は浮いている
+        Command::Flip,
+        Command::Fill(NCD::new(0, 1, 1)),
+        Command::Wait,
+        Command::Fill(NCD::new(0, 0, 1)),
+        Command::Flip,
+        Command::LMove(SLCD::new(0, 0, -1), SLCD::new(1, 0, 0)),
+        Command::Wait,
+        Command::LMove(SLCD::new(-1, 0, 0), SLCD::new(0, 0, -1)),
+        Command::FusionP(NCD::new(1, 0, 0)),
+        Command::FusionS(NCD::new(-1, 0, 0)),
+        Command::Halt,
+    ];
+    run_trace(&source, &target, &trace);
+
+    let reversed = reverse_trace(&source, &trace).unwrap();
+    run_trace(&target, &source, &reversed);
+    assert_eq!(reversed.len(), trace.len());
+    assert_eq!(reversed[0], Command::Fission(NCD::new(1, 0, 0), 5));
+    assert!(reversed.contains(&Command::Void(NCD::new(0, 1, 1))));
+    assert_eq!(reversed.iter().filter(|c| **c == Command::Flip).count(), 2);
+
+    // 2 回反転すると元の問題の trace に戻る
+    let twice = reverse_trace(&target, &reversed).unwrap();
+    run_trace(&source, &target, &twice);
+}
+
+#[test]
+fn test_reverse_trace_errors() {
+    let source = Model::initial(5);
+    assert!(reverse_trace(&source, &[]).is_err());
+    assert!(reverse_trace(&source, &[Command::Wait]).is_err());
+    assert!(reverse_trace(&source, &[Command::Halt, Command::Halt]).is_err());
+    // 一部だけ Full の領域への GFill は反転できない
+    let mut source = Model::initial(5);
+    source.matrix[1][0][1] = Voxel::Full;
+    let trace = vec![
+        Command::Fission(NCD::new(0, 0, 1), 0),
+        Command::GFill(NCD::new(1, 0, 0), FCD::new(0, 0, 1)),
+        Command::GFill(NCD::new(1, 0, 0), FCD::new(0, 0, -1)),
+        Command::FusionP(NCD::new(0, 0, 1)),
+        Command::FusionS(NCD::new(0, 0, -1)),
+        Command::Halt,
+    ];
+    let err = reverse_trace(&source, &trace).unwrap_err();
+    assert!(err.to_string().contains("partially filled"));
+}
+
+#[test]
+fn test_reverse_ais() {
+    use generator::{generate, Shape};
+    let config = Config::new();
+    let empty = Model::initial(8);
+    let model = generate(Shape::Arch, 8, 0);
+    let name = String::from("default");
+    let trace = ReverseAssembleAI::new(&name, &config, &model).assemble(&model);
+    run_trace(&empty, &model, &trace);
+    let trace = ReverseDisassembleAI::new(&name, &config, &model).disassemble(&model);
+    run_trace(&model, &empty, &trace);
+}
+
+#[test]
+fn test_reverse_partial_gvoid() {
+    // (1, 0, 2) だけ Void の領域を GVoid する。反転すると消えた 2 つの voxel を埋め直す
+    let mut source = Model::initial(5);
+    source.matrix[1][0][1] = Voxel::Full;
+    source.matrix[1][0][3] = Voxel::Full;
+    let trace = vec![
+        Command::Fission(NCD::new(0, 0, 1), 0),
+        Command::Wait,
+        Command::SMove(LLCD::new(0, 0, 3)),
+        Command::GVoid(NCD::new(1, 0, 1), FCD::new(0, 0, 2)),
+        Command::GVoid(NCD::new(1, 0, -1), FCD::new(0, 0, -2)),
+        Command::Wait,
+        Command::SMove(LLCD::new(0, 0, -3)),
+        Command::FusionP(NCD::new(0, 0, 1)),
+        Command::FusionS(NCD::new(0, 0, -1)),
+        Command::Halt,
+    ];
+    let target = Model::initial(5);
+    run_trace(&source, &target, &trace);
+    let reversed = reverse_trace(&source, &trace).unwrap();
+    run_trace(&target, &source, &reversed);
+}
+
+#[test]
+fn test_reverse_gvoid_ais() {
+    use generator::{generate, Shape};
+    let config = Config::new();
+    let empty = Model::initial(8);
+    let model = generate(Shape::Arch, 8, 0);
+    for name in ["gvoid", "gvoid_2d"].iter() {
+        let trace = ReverseAssembleAI::new(&name.to_string(), &config, &model).assemble(&model);
+        run_trace(&empty, &model, &trace);
+    }
+}
@@ -0,0 +1,155 @@
+use ai::builder::*;
+use ai::config::Config;
+use ai::dflt::dflt_trace;
+use ai::AssembleAI;
+use ai::DisassembleAI;
+use ai::ReassembleAI;
+use common::*;
+use model::Model;
+use state::State;
+use std::panic::{self, AssertUnwindSafe};
+use symmetry::Symmetry;
+
+// 問題を 8 通りに対称変換してそれぞれ name の AI で解き、元の問題に戻した trace のうち
+// energy が一番小さいものを使う。AI によって得意な向きがあるので、それを補う。
+
+// solve は変換後の問題を解いた trace を返す。
+// panic したり検査を通らなかったりした向きは使わず、どの向きもだめなら dflt の trace を返す。
+fn best_trace<F>(source: &Model, target: &Model, solve: F) -> Vec<Command>
+where
+    F: Fn(&Symmetry) -> Vec<Command>,
+{
+    let r = source.matrix.len();
+    let mut best: Option<(i64, Vec<Command>)> = None;
+    for s in Symmetry::all() {
+        let trace = match panic::catch_unwind(AssertUnwindSafe(|| solve(&s))) {
+            Ok(trace) => s.inverse().transform_trace(&trace, r),
+            Err(_) => continue,
+        };
+        let mut state = State::initial_with_model(source);
+        if state.execute_trace(&trace).is_err() || state.end_check(target).is_err() {
+            continue;
+        }
+        let energy = state.get_energy();
+        if best.as_ref().map_or(true, |b| energy < b.0) {
+            best = Some((energy, trace));
+        }
+    }
+    match best {
+        Some((_, trace)) => trace,
+        None => dflt_trace(source, target),
+    }
+}
+
+pub struct SymmetricAssembleAI {
+    name: String,
+    config: Config,
+}
+
+impl SymmetricAssembleAI {
+    pub fn new(name: &str, config: &Config) -> Self {
+        SymmetricAssembleAI {
+            name: name.to_string(),
+            config: config.clone(),
+        }
+    }
+}
+
+impl AssembleAI for SymmetricAssembleAI {
+    fn assemble(&mut self, model: &Model) -> Vec<Command> {
+        let source = Model::initial(model.matrix.len());
+        best_trace(&source, model, |s| {
+            let model = s.transform_model(model);
+            build_assembler(&self.name, &self.config, &model).assemble(&model)
+        })
+    }
+}
+
+pub struct SymmetricDisassembleAI {
+    name: String,
+    config: Config,
+}
+
+impl SymmetricDisassembleAI {
+    pub fn new(name: &str, config: &Config) -> Self {
+        SymmetricDisassembleAI {
+            name: name.to_string(),
+            config: config.clone(),
+        }
+    }
+}
+
+impl DisassembleAI for SymmetricDisassembleAI {
+    fn disassemble(&mut self, model: &Model) -> Vec<Command> {
+        let target = Model::initial(model.matrix.len());
+        best_trace(model, &target, |s| {
+            let model = s.transform_model(model);
+            build_disassembler(&self.name, &self.config, &model).disassemble(&model)
+        })
+    }
+}
+
+pub struct SymmetricReassembleAI {
+    name: String,
+    config: Config,
+}
+
+impl SymmetricReassembleAI {
+    pub fn new(name: &str, config: &Config) -> Self {
+        SymmetricReassembleAI {
+            name: name.to_string(),
+            config: config.clone(),
+        }
+    }
+}
+
+impl ReassembleAI for SymmetricReassembleAI {
+    fn reassemble(&mut self, source: &Model, target: &Model) -> Vec<Command> {
+        best_trace(source, target, |s| {
+            let source = s.transform_model(source);
+            let target = s.transform_model(target);
+            build_reassembler(&self.name, &self.config, &source, &target)
+                .reassemble(&source, &target)
+        })
+    }
+}
+
+#[test]
+fn test_symmetric_assemble() {
+    use generator::{generate, Shape};
+    let config = Config::new();
+    let model = generate(Shape::Overhang, 8, 0);
+    let energy = |trace: &[Command]| {
+        let mut state = State::initial_with_model(&Model::initial(8));
+        state.execute_trace(trace).unwrap();
+        state.end_check(&model).unwrap();
+        state.get_energy()
+    };
+    let trace = SymmetricAssembleAI::new("default", &config).assemble(&model);
+    let identity = build_assembler(&String::from("default"), &config, &model).assemble(&model);
+    // 変換しない場合より悪くはならない
+    assert!(energy(&trace) <= energy(&identity));
+}
+
+#[test]
+fn test_best_trace_fallback() {
+    use generator::{generate, Shape};
+    let target = generate(Shape::Tower, 8, 0);
+    let source = Model::initial(8);
+    let check = |trace: &[Command]| {
+        let mut state = State::initial_with_model(&source);
+        state.execute_trace(trace).unwrap();
+        state.end_check(&target).unwrap();
+    };
+    // 一部の向きが panic しても残りの向きから選ぶ
+    let trace = best_trace(&source, &target, |s| {
+        if s.name() == Symmetry::all()[0].name() {
+            panic!("broken symmetry");
+        }
+        dflt_trace(&s.transform_model(&source), &s.transform_model(&target))
+    });
+    check(&trace);
+    // どの向きも通らなければ dflt の trace になる
+    let trace = best_trace(&source, &target, |_| vec![Command::Halt]);
+    check(&trace);
+}
@@ -98,144 +98,6 @@ pub fn move_straight_z(len: i32) -> Vec<Command> {
     commands
 }
 
-pub fn generate_devide_commands(size: (usize, usize), split: (usize, usize)) -> Vec<Vec<Command>> {
-    let mut commands = vec![];
-
-    let ncd_x1 = NCD::new(1, 0, 0);
-    for i in 0..(split.0 - 1) {
-        let rest = split.1 * (split.0 - i - 1);
-
-        let mut step = vec![];
-        step.extend(repeat(Command::Wait).take(i));
-        step.push(Command::Fission(ncd_x1.clone(), rest - 1));
-        commands.push(step);
-
-        let width = (size.0 / split.0) as i32 + if i < size.0 % split.0 { 1 } else { 0 };
-        let x_moves = move_straight_x(width - 1);
-        for m in x_moves.into_iter() {
-            let mut step = vec![];
-            step.extend(repeat(Command::Wait).take(i + 1));
-            step.push(m);
-            commands.push(step);
-        }
-    }
-
-    let ncd_z1 = NCD::new(0, 0, 1);
-    for i in 0..(split.1 - 1) {
-        let fussion = Command::Fission(ncd_z1.clone(), split.1 - i - 2);
-        let width = (size.1 / split.1) as i32 + if i < size.1 % split.1 { 1 } else { 0 };
-        let z_moves = move_straight_z(width - 1);
-
-        if i == 0 {
-            commands.push(repeat(fussion).take(split.0).collect());
-
-            for m in z_moves.iter() {
-                let mut step = vec![];
-                step.extend(repeat(Command::Wait).take(split.0));
-                step.extend(repeat(m.clone()).take(split.0));
-                commands.push(step);
-            }
-        } else {
-            let mut step = vec![];
-            step.extend(repeat(Command::Wait).take(split.0));
-            for _ in 0..split.0 {
-                step.extend(repeat(Command::Wait).take(i - 1));
-                step.push(fussion.clone());
-            }
-            commands.push(step);
-
-            for m in z_moves.iter() {
-                let mut step = vec![];
-                step.extend(repeat(Command::Wait).take(split.0));
-                for _ in 0..split.0 {
-                    step.extend(repeat(Command::Wait).take(i));
-                    step.push(m.clone());
-                }
-                commands.push(step);
-            }
-        }
-    }
-
-    commands
-}
-
-pub fn generate_concur_commands(size: (usize, usize), split: (usize, usize)) -> Vec<Vec<Command>> {
-    let mut commands = vec![];
-
-    // concur z axis
-    let ncd_z1 = NCD::new(0, 0, 1);
-    let ncd_z_1 = NCD::new(0, 0, -1);
-    for i in 0..(split.1 - 1) {
-        let width = (size.1 / split.1) as i32 + if (split.1 - i - 2) < size.1 % split.1 {
-            1
-        } else {
-            0
-        };
-        let z_moves = move_straight_z(-(width - 1));
-
-        if i == split.1 - 2 {
-            // last
-            for m in z_moves.iter() {
-                let mut step = vec![];
-                step.extend(repeat(Command::Wait).take(split.0));
-                step.extend(repeat(m.clone()).take(split.0));
-                commands.push(step);
-            }
-            let mut step = vec![];
-            step.extend(repeat(Command::FusionP(ncd_z1.clone())).take(split.0));
-            step.extend(repeat(Command::FusionS(ncd_z_1.clone())).take(split.0));
-            commands.push(step);
-        } else {
-            let z_rest = split.1 - i - 1; // ignore first line
-
-            for m in z_moves.iter() {
-                let mut step = vec![];
-                step.extend(repeat(Command::Wait).take(split.0));
-                for _ in 0..split.0 {
-                    step.extend(repeat(Command::Wait).take(z_rest - 1));
-                    step.push(m.clone());
-                }
-                commands.push(step);
-            }
-
-            let mut step = vec![];
-            step.extend(repeat(Command::Wait).take(split.0));
-            for _ in 0..split.0 {
-                step.extend(repeat(Command::Wait).take(z_rest - 2));
-                step.push(Command::FusionP(ncd_z1.clone()));
-                step.push(Command::FusionS(ncd_z_1.clone()));
-            }
-            commands.push(step);
-        }
-    }
-
-    // concur x axis
-    let ncd_x1 = NCD::new(1, 0, 0);
-    let ncd_x_1 = NCD::new(-1, 0, 0);
-    for i in 0..(split.0 - 1) {
-        let width = (size.0 / split.0) as i32 + if (split.0 - i - 2) < size.0 % split.0 {
-            1
-        } else {
-            0
-        };
-        let x_moves = move_straight_x(-(width - 1));
-        for m in x_moves.into_iter() {
-            let mut step = vec![];
-            step.extend(repeat(Command::Wait).take(split.0 - i - 1));
-            step.push(m);
-            commands.push(step);
-        }
-        // fusion
-        let mut step = vec![];
-        step.extend(repeat(Command::Wait).take(split.0 - i - 2));
-        step.push(Command::FusionP(ncd_x1.clone()));
-        step.push(Command::FusionS(ncd_x_1.clone()));
-        commands.push(step);
-    }
-
-    commands
-}
-
 #[test]
 fn test_bounding_box() {
     let mut matrix = vec![vec![vec![Voxel::Void; 4]; 4]; 4];
@@ -254,186 +116,3 @@ fn test_bounding_box() {
     assert_eq!(1, bounding.min_z);
     assert_eq!(2, bounding.max_z);
 }
-
-#[test]
-fn test_generate_devide_commands_with_1x1() {
-    let commands = generate_devide_commands((5, 4), (1, 1));
-    let expect: Vec<Vec<Command>> = vec![];
-    assert_eq!(expect, commands);
-}
-
-#[test]
-fn test_generate_devide_commands_with_2x3() {
-    let ncd_x1 = NCD::new(1, 0, 0);
-    let ncd_z1 = NCD::new(0, 0, 1);
-
-    let llcd_x1 = LLCD::new(1, 0, 0);
-    let llcd_z1 = LLCD::new(0, 0, 1);
-
-    let commands = generate_devide_commands((4, 6), (2, 3));
-    let expect = vec![
-        vec![Command::Fission(ncd_x1.clone(), 2)],
-        vec![Command::Wait, Command::SMove(llcd_x1.clone())],
-        vec![
-            Command::Fission(ncd_z1.clone(), 1),
-            Command::Fission(ncd_z1.clone(), 1),
-        ],
-        vec![
-            Command::Wait,
-            Command::Wait,
-            Command::SMove(llcd_z1.clone()),
-            Command::SMove(llcd_z1.clone()),
-        ],
-        vec![
-            Command::Wait,
-            Command::Wait,
-            Command::Fission(ncd_z1.clone(), 0),
-            Command::Fission(ncd_z1.clone(), 0),
-        ],
-        vec![
-            Command::Wait,
-            Command::Wait,
-            Command::Wait,
-            Command::SMove(llcd_z1.clone()),
-            Command::Wait,
-            Command::SMove(llcd_z1.clone()),
-        ],
-    ];
-
-    assert_eq!(expect, commands);
-}
-
-#[test]
-fn test_generate_devide_commands_with_2x2() {
-    let ncd_x1 = NCD::new(1, 0, 0);
-    let ncd_z1 = NCD::new(0, 0, 1);
-
-    let llcd_x1 = LLCD::new(1, 0, 0);
-    let llcd_z2 = LLCD::new(0, 0, 2);
-
-    let commands = generate_devide_commands((3, 5), (2, 2));
-    let expect = vec![
-        vec![Command::Fission(ncd_x1.clone(), 1)],
-        vec![Command::Wait, Command::SMove(llcd_x1.clone())],
-        vec![
-            Command::Fission(ncd_z1.clone(), 0),
-            Command::Fission(ncd_z1.clone(), 0),
-        ],
-        vec![
-            Command::Wait,
-            Command::Wait,
-            Command::SMove(llcd_z2.clone()),
-            Command::SMove(llcd_z2.clone()),
-        ],
-    ];
-
-    assert_eq!(expect, commands);
-}
-
-#[test]
-fn test_generate_devide_commands_with_3x2() {
-    let ncd_x1 = NCD::new(1, 0, 0);
-    let ncd_z1 = NCD::new(0, 0, 1);
-
-    let commands = generate_devide_commands((3, 3), (3, 3));
-    let expect = vec![
-        vec![Command::Fission(ncd_x1.clone(), 5)],
-        vec![Command::Wait, Command::Fission(ncd_x1.clone(), 2)],
-        vec![
-            Command::Fission(ncd_z1.clone(), 1),
-            Command::Fission(ncd_z1.clone(), 1),
-            Command::Fission(ncd_z1.clone(), 1),
-        ],
-        vec![
-            Command::Wait,
-            Command::Wait,
-            Command::Wait,
-            Command::Fission(ncd_z1.clone(), 0),
-            Command::Fission(ncd_z1.clone(), 0),
-            Command::Fission(ncd_z1.clone(), 0),
-        ],
-    ];
-
-    assert_eq!(expect, commands);
-}
-
-#[test]
-fn test_generate_concur_commands_with_1x1() {
-    let commands = generate_concur_commands((5, 4), (1, 1));
-    let expect: Vec<Vec<Command>> = vec![];
-    assert_eq!(expect, commands);
-}
-
-#[test]
-fn test_generate_concur_commands_with_2x2() {
-    let ncd_x1 = NCD::new(1, 0, 0);
-    let ncd_x_1 = NCD::new(-1, 0, 0);
-    let ncd_z1 = NCD::new(0, 0, 1);
-    let ncd_z_1 = NCD::new(0, 0, -1);
-
-    let llcd_x_1 = LLCD::new(-1, 0, 0);
-    let llcd_z_2 = LLCD::new(0, 0, -2);
-
-    let commands = generate_concur_commands((3, 5), (2, 2));
-    let expect = vec![
-        vec![
-            Command::Wait,
-            Command::Wait,
-            Command::SMove(llcd_z_2.clone()),
-            Command::SMove(llcd_z_2.clone()),
-        ],
-        vec![
-            Command::FusionP(ncd_z1.clone()),
-            Command::FusionP(ncd_z1.clone()),
-            Command::FusionS(ncd_z_1.clone()),
-            Command::FusionS(ncd_z_1.clone()),
-        ],
-        vec![Command::Wait, Command::SMove(llcd_x_1.clone())],
-        vec![
-            Command::FusionP(ncd_x1.clone()),
-            Command::FusionS(ncd_x_1.clone()),
-        ],
-    ];
-    assert_eq!(expect, commands);
-}
-
-#[test]
-fn test_generate_concur_commands_with_3x3() {
-    let ncd_x1 = NCD::new(1, 0, 0);
-    let ncd_x_1 = NCD::new(-1, 0, 0);
-    let ncd_z1 = NCD::new(0, 0, 1);
-    let ncd_z_1 = NCD::new(0, 0, -1);
-
-    let commands = generate_concur_commands((3, 3), (3, 3));
-    let expect = vec![
-        vec![
-            Command::Wait,
-            Command::Wait,
-            Command::Wait,
-            Command::FusionP(ncd_z1.clone()),
-            Command::FusionS(ncd_z_1.clone()),
-            Command::FusionP(ncd_z1.clone()),
-            Command::FusionS(ncd_z_1.clone()),
-            Command::FusionP(ncd_z1.clone()),
-            Command::FusionS(ncd_z_1.clone()),
-        ],
-        vec![
-            Command::FusionP(ncd_z1.clone()),
-            Command::FusionP(ncd_z1.clone()),
-            Command::FusionP(ncd_z1.clone()),
-            Command::FusionS(ncd_z_1.clone()),
-            Command::FusionS(ncd_z_1.clone()),
-            Command::FusionS(ncd_z_1.clone()),
-        ],
-        vec![
-            Command::Wait,
-            Command::FusionP(ncd_x1.clone()),
-            Command::FusionS(ncd_x_1.clone()),
-        ],
-        vec![
-            Command::FusionP(ncd_x1.clone()),
-            Command::FusionS(ncd_x_1.clone()),
-        ],
-    ];
-    assert_eq!(expect, commands);
-}
@@ -1,4 +1,7 @@
-use ai::config::Config;
+use ai::config::*;
+use ai::dflt::dflt_trace;
+use ai::finish::finish;
+use ai::reservation::find_path;
 use ai::utils::*;
 use ai::AssembleAI;
 use common::*;
@@ -17,6 +20,10 @@ pub struct VoidAssembleAI {
 }
 
 impl VoidAssembleAI {
+    pub fn params() -> Vec<Param> {
+        vec![DRY_RUN_MAX_RESOLUTION]
+    }
+
     pub fn new(config: &Config) -> Self {
         VoidAssembleAI {
             dry_run_max_resolution: config.dry_run_max_resolution,
@@ -120,41 +127,31 @@ impl AssembleAI for VoidAssembleAI {
                     break;
                 }
                 if dry_run {
-                    let mut cloned = state.clone();
                     if !harmonity_high {
-                        match cloned.update_time_step(&step[..]) {
-                            Ok(_) => {
-                                state = cloned;
-                            }
-                            Err(_) => {
-                                harmonity_high = true;
-                                let mut high = vec![Command::Flip];
-                                high.extend(
-                                    repeat(Command::Wait).take(void_commands_list.len() - 1),
-                                );
-                                state.update_time_step(&high[..]).unwrap();
-                                state.update_time_step(&step[..]).unwrap();
-                                commands.extend(high);
-                            }
+                        if state.try_time_step(&step[..]).is_err() {
+                            harmonity_high = true;
+                            let mut high = vec![Command::Flip];
+                            high.extend(
+                                repeat(Command::Wait).take(void_commands_list.len() - 1),
+                            );
+                            state.update_time_step(&high[..]).unwrap();
+                            state.update_time_step(&step[..]).unwrap();
+                            commands.extend(high);
                         }
                     } else {
                         let mut low = vec![Command::Flip];
                         low.extend(repeat(Command::Wait).take(void_commands_list.len() - 1));
 
-                        match cloned.update_time_step(&low[..]) {
-                            Ok(_) => match cloned.update_time_step(&step[..]) {
-                                Ok(_) => {
-                                    harmonity_high = false;
-                                    state = cloned;
-                                    commands.extend(low);
-                                }
-                                Err(_) => {
-                                    state.update_time_step(&step[..]).unwrap();
-                                }
-                            },
-                            Err(_) => {
-                                state.update_time_step(&step[..]).unwrap();
-                            }
+                        state.checkpoint();
+                        if state.update_time_step(&low[..]).is_ok()
+                            && state.update_time_step(&step[..]).is_ok()
+                        {
+                            state.commit();
+                            harmonity_high = false;
+                            commands.extend(low);
+                        } else {
+                            state.rollback();
+                            state.update_time_step(&step[..]).unwrap();
                         }
                     }
                 } else {
@@ -186,12 +183,18 @@ impl AssembleAI for VoidAssembleAI {
                 .iter()
                 .flat_map(|v| v.iter()),
         );
-        // back to origin
-        commands.extend(move_straight_x(-bounding.min_x));
-        commands.extend(move_straight_z(-(bounding.min_z - 1)));
-        commands.push(Command::Halt);
-        // finish
-        commands
+        // back to origin, avoiding full voxels
+        let mut state = State::initial(r);
+        state
+            .execute_trace(&commands)
+            .expect("void_assemble: invalid trace");
+        match finish(&state) {
+            Some(rest) => {
+                commands.extend(rest);
+                commands
+            }
+            None => dflt_trace(&Model::initial(r), target),
+        }
     }
 }
 
@@ -289,6 +292,21 @@ fn generate_fill_and_void_commands(
 
         for next in path.into_iter() {
             let path = shortest_path(&source, &expanded_region, &cur, &next);
+            // 今いる位置を埋め直さなくてよくて、掘らずに Void だけを通って同じ step 数以下で行けるなら、そのまま動く
+            if target.voxel_at(cur) == Voxel::Void {
+                let is_free = |p: &Position| {
+                    expanded_region.contains(*p)
+                        && source.voxel_at(*p) == Voxel::Void
+                        && target.voxel_at(*p) == Voxel::Void
+                };
+                if let Some(moves) = find_path(&is_free, &cur, &next) {
+                    if moves.len() <= path.len() {
+                        void_commands.extend(moves);
+                        cur = next;
+                        continue;
+                    }
+                }
+            }
             for cd in path.iter() {
                 let prev = cur;
                 let next = cur + cd;
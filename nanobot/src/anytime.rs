@@ -0,0 +1,159 @@
+use ai::dflt::dflt_trace;
+use common::*;
+use model::Model;
+use state::State;
+use std::error::Error;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// AI を別のスレッドで動かして、締め切りまでに State で検査を通った trace が出ればそれを、
+// 出なければ (時間切れ、panic、検査で失敗) dflt_trace を返す。
+//
+// AI の panic はスレッドの中で止まり、送り手が落ちたことで分かる。時間切れのスレッドは止められないので
+// そのまま放っておき、呼び出し側が終了するときに一緒に終わる。
+// dflt_trace も同じように検査してから返すので、ここから返る trace はいつも State で最後まで通る。
+
+pub enum Outcome {
+    // AI の trace が通った
+    Solved,
+    // 締め切りまでに AI が終わらなかった
+    TimedOut,
+    // AI が panic したか、trace が検査で失敗した
+    Failed(String),
+}
+
+pub struct Solution {
+    pub trace: Vec<Command>,
+    pub energy: i64,
+    pub outcome: Outcome,
+}
+
+// trace を source から最後まで実行して、target になれば energy を返す
+pub fn verify(source: &Model, target: &Model, trace: &[Command]) -> Result<i64, Box<Error>> {
+    let mut state = State::initial_with_model(source);
+    state.execute_trace(trace)?;
+    state.end_check(target)?;
+    Ok(state.get_energy())
+}
+
+// run を別のスレッドで動かして、締め切りまでに検査を通った trace と energy を返す。
+// 通らなければ dflt_trace には切り替えずに、Solved 以外の Outcome を返す。
+pub fn run_with_deadline<F>(
+    source: &Model,
+    target: &Model,
+    deadline: Option<Duration>,
+    run: F,
+) -> Result<(Vec<Command>, i64), Outcome>
+where
+    F: FnOnce() -> Vec<Command> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    let (s, t) = (source.clone(), target.clone());
+    thread::spawn(move || {
+        let trace = run();
+        // 検査も panic するかもしれないので、スレッドの中で行う
+        let result = verify(&s, &t, &trace).map_err(|e| e.to_string());
+        let _ = sender.send((trace, result));
+    });
+    let received = match deadline {
+        Some(d) => receiver.recv_timeout(d).map_err(|e| match e {
+            mpsc::RecvTimeoutError::Timeout => Outcome::TimedOut,
+            mpsc::RecvTimeoutError::Disconnected => Outcome::Failed(String::from("AI panicked")),
+        }),
+        None => receiver
+            .recv()
+            .map_err(|_| Outcome::Failed(String::from("AI panicked"))),
+    };
+    match received {
+        Ok((trace, Ok(energy))) => Ok((trace, energy)),
+        Ok((_, Err(message))) => Err(Outcome::Failed(message)),
+        Err(outcome) => Err(outcome),
+    }
+}
+
+// run が作る trace で source を target にする。deadline が None なら AI が終わるまで待つ。
+// dflt_trace まで検査で失敗したら (source か target が正しいモデルでなければ) Err を返す。
+pub fn solve<F>(
+    source: &Model,
+    target: &Model,
+    deadline: Option<Duration>,
+    run: F,
+) -> Result<Solution, Box<Error>>
+where
+    F: FnOnce() -> Vec<Command> + Send + 'static,
+{
+    let outcome = match run_with_deadline(source, target, deadline, run) {
+        Ok((trace, energy)) => {
+            return Ok(Solution {
+                trace,
+                energy,
+                outcome: Outcome::Solved,
+            })
+        }
+        Err(outcome) => outcome,
+    };
+
+    let trace = dflt_trace(source, target);
+    let energy = verify(source, target, &trace)?;
+    Ok(Solution {
+        trace,
+        energy,
+        outcome,
+    })
+}
+
+#[test]
+fn test_solve_fallback() {
+    use generator::{generate, Shape};
+    let empty = Model::initial(8);
+    let model = generate(Shape::Tower, 8, 0);
+
+    let solution = solve(&empty, &model, None, || unimplemented!()).unwrap();
+    assert!(match solution.outcome {
+        Outcome::Failed(_) => true,
+        _ => false,
+    });
+    assert!(verify(&empty, &model, &solution.trace).is_ok());
+
+    // Halt だけでは target にならない
+    let solution = solve(&empty, &model, None, || vec![Command::Halt]).unwrap();
+    assert!(match solution.outcome {
+        Outcome::Failed(_) => true,
+        _ => false,
+    });
+
+    let solution = solve(&empty, &model, Some(Duration::from_millis(10)), || {
+        thread::sleep(Duration::from_secs(10));
+        vec![]
+    })
+    .unwrap();
+    assert!(match solution.outcome {
+        Outcome::TimedOut => true,
+        _ => false,
+    });
+    assert_eq!(
+        solution.energy,
+        verify(&empty, &model, &solution.trace).unwrap()
+    );
+}
+
+#[test]
+fn test_solve() {
+    use ai::builder::build_assembler;
+    use ai::config::Config;
+    use generator::{generate, Shape};
+    let empty = Model::initial(8);
+    let model = generate(Shape::Tower, 8, 0);
+    let target = model.clone();
+    let solution = solve(&empty, &model, Some(Duration::from_secs(60)), move || {
+        build_assembler(&String::from("default"), &Config::new(), &target).assemble(&target)
+    })
+    .unwrap();
+    assert!(match solution.outcome {
+        Outcome::Solved => true,
+        _ => false,
+    });
+    let dflt = verify(&empty, &model, &dflt_trace(&empty, &model)).unwrap();
+    assert!(solution.energy < dflt);
+}
@@ -0,0 +1,105 @@
+extern crate getopts;
+extern crate nanobot_lib;
+
+use getopts::Options;
+use nanobot_lib::common::read_trace_file;
+use nanobot_lib::export::*;
+use nanobot_lib::import::*;
+use nanobot_lib::model::*;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::process;
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!(
+        "Usage: {} [options]
+
+Input:  .mdl, .vox, .binvox
+Output: .mdl, .obj, .ply, .vox (or .obj with --trace)",
+        program
+    );
+    print!("{}", opts.usage(&brief));
+}
+
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.reqopt("i", "input", "set input model", "FILE");
+    opts.reqopt("o", "output", "set output file", "FILE");
+    opts.optopt("r", "resolution", "set resolution of imported model", "R");
+    opts.optopt("t", "trace", "export bot paths of the trace instead of the model", "FILE");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            print_usage(&program, opts);
+            eprintln!("{}", f);
+            process::exit(1);
+        }
+    };
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        return;
+    }
+    let input = matches.opt_str("input").unwrap();
+    let output = matches.opt_str("output").unwrap();
+    let resolution = matches
+        .opt_str("resolution")
+        .map(|r| r.parse::<usize>().expect("invalid resolution"));
+
+    // Load
+    let model = match &extension(&input)[..] {
+        "mdl" => {
+            let f = File::open(Path::new(&input)).expect("file not found");
+            let mut f = BufReader::new(f);
+            Model::new(&mut f).expect("failed to open model")
+        }
+        "vox" | "binvox" => {
+            let path = Path::new(&input);
+            let (model, report) = if extension(&input) == "vox" {
+                read_vox_file(path, resolution)
+            } else {
+                read_binvox_file(path, resolution)
+            }.expect("failed to import model");
+            eprintln!(
+                "R={} imported={} clipped={} floating={}",
+                report.resolution, report.imported, report.clipped, report.floating
+            );
+            model
+        }
+        e => {
+            eprintln!("unknown input format: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // Save
+    let path = Path::new(&output);
+    if let Some(trace) = matches.opt_str("trace") {
+        let trace = read_trace_file(Path::new(&trace)).expect("failed to open trace");
+        write_trace_obj_file(path, model.matrix.len(), &trace).expect("failed to write");
+        return;
+    }
+    let result = match &extension(&output)[..] {
+        "mdl" => write_model_file(path, &model),
+        "obj" => write_obj_file(path, &model),
+        "ply" => write_ply_file(path, &model),
+        "vox" => write_vox_file(path, &model),
+        e => {
+            eprintln!("unknown output format: {}", e);
+            process::exit(1);
+        }
+    };
+    result.expect("failed to write");
+}
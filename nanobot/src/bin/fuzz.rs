@@ -0,0 +1,74 @@
+extern crate getopts;
+extern crate nanobot_lib;
+
+use getopts::Options;
+use nanobot_lib::fuzz::*;
+use std::env;
+use std::fs;
+use std::process;
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!(
+        "Usage: {} [options]
+
+Targets: decode, simulate",
+        program
+    );
+    print!("{}", opts.usage(&brief));
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.reqopt("t", "target", "set fuzz target", "TARGET");
+    opts.optopt("n", "iterations", "set the number of inputs (default: 100000)", "N");
+    opts.optopt("s", "seed", "set random seed (default: 0)", "SEED");
+    opts.optopt("o", "output", "set crasher output file (default: crash-TARGET-SEED)", "FILE");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            print_usage(&program, opts);
+            eprintln!("{}", f);
+            process::exit(1);
+        }
+    };
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        return;
+    }
+    let target_name = matches.opt_str("target").unwrap();
+    let iterations = matches
+        .opt_str("iterations")
+        .map(|n| n.parse::<usize>().expect("invalid iterations"))
+        .unwrap_or(100000);
+    let seed = matches
+        .opt_str("seed")
+        .map(|s| s.parse::<u64>().expect("invalid seed"))
+        .unwrap_or(0);
+
+    let target: fn(&[u8]) = match &target_name[..] {
+        "decode" => fuzz_decode,
+        "simulate" => fuzz_simulate,
+        t => {
+            eprintln!("unknown target: {}", t);
+            process::exit(1);
+        }
+    };
+
+    match run_random(target, seed, iterations) {
+        None => println!("no crash in {} inputs", iterations),
+        Some((data, message)) => {
+            let output = matches
+                .opt_str("output")
+                .unwrap_or(format!("crash-{}-{}", target_name, seed));
+            fs::write(&output, &data).expect("failed to write crasher");
+            println!("crash: {}", message);
+            println!("input: {:?}", data);
+            println!("saved to {}", output);
+            process::exit(1);
+        }
+    }
+}
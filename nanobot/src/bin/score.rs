@@ -2,12 +2,16 @@ extern crate getopts;
 extern crate nanobot_lib;
 
 use getopts::Options;
+use nanobot_lib::ai::dflt::dflt_trace;
+use nanobot_lib::anytime::verify;
 use nanobot_lib::common::read_trace_file;
 use nanobot_lib::model::Model;
-use nanobot_lib::state::State;
+use nanobot_lib::report::Report;
+use nanobot_lib::state::{Observer, State};
+use nanobot_lib::timeline::Timeline;
 use std::env;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Write};
 use std::path::Path;
 
 enum OutputFormat {
@@ -30,6 +34,21 @@ fn main() {
     opts.reqopt("", "trace", "set trace", "FILE");
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("j", "json", "print in JSON format");
+    opts.optflag("b", "baseline", "also print the energy of the default trace and the ratio to it");
+    opts.optopt(
+        "",
+        "report",
+        "write which bot filled or voided each voxel and the energy by region as JSON",
+        "FILE",
+    );
+    opts.optopt("", "heatmap", "write energy heatmaps of each layer as PNG", "DIR");
+    opts.optopt("", "block", "set region size of the report (default: 8)", "N");
+    opts.optopt(
+        "",
+        "timeline",
+        "write what each bot did at each step as an SVG Gantt chart and print idle time",
+        "FILE",
+    );
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => {
@@ -84,18 +103,33 @@ fn main() {
         panic!("source_model and target_model size are not same")
     }
     let mut state = State::initial_with_model(&source_model);
+    let mut report = if matches.opt_present("report") || matches.opt_present("heatmap") {
+        Some(Report::new(r))
+    } else {
+        None
+    };
+    let mut timeline = if matches.opt_present("timeline") {
+        Some(Timeline::new())
+    } else {
+        None
+    };
 
     // Simulate
-    let mut offset = 0;
-    while offset < trace.len() {
-        let bot_cnt = state.get_bot_count();
-        match state.update_time_step(&trace[offset..offset + bot_cnt]) {
-            Ok(_) => {}
-            Err(err) => {
-                panic!(err.to_string());
-            }
+    let result = {
+        let mut observers: Vec<&mut Observer> = vec![];
+        if let Some(ref mut report) = report {
+            observers.push(report);
+        }
+        if let Some(ref mut timeline) = timeline {
+            observers.push(timeline);
+        }
+        state.execute_trace_observed(&trace, &mut observers)
+    };
+    match result {
+        Ok(_) => {}
+        Err(err) => {
+            panic!(err.to_string());
         }
-        offset += bot_cnt;
     }
     match state.end_check(&target_model) {
         Ok(_) => {}
@@ -105,6 +139,37 @@ fn main() {
         }
     }
 
+    if let Some(report) = report {
+        if let Some(path) = matches.opt_str("report") {
+            let block = matches
+                .opt_str("block")
+                .map(|b| b.parse::<usize>().expect("invalid block"))
+                .unwrap_or(8);
+            let mut f = File::create(Path::new(&path)).expect("failed to create report");
+            f.write_all(report.to_json(block).as_bytes())
+                .expect("failed to write report");
+        }
+        if let Some(dir) = matches.opt_str("heatmap") {
+            report
+                .write_heatmaps(Path::new(&dir))
+                .expect("failed to write heatmaps");
+        }
+    }
+
+    let summary = timeline.map(|timeline| {
+        timeline
+            .write_svg(Path::new(&matches.opt_str("timeline").unwrap()))
+            .expect("failed to write timeline");
+        timeline.summary()
+    });
+
+    let baseline = if matches.opt_present("b") {
+        // dflt_trace の energy。AI の energy をこれで割ったものが相対スコアの基準になる。
+        let trace = dflt_trace(&source_model, &target_model);
+        Some(verify(&source_model, &target_model, &trace).expect("default trace failed"))
+    } else {
+        None
+    };
     match output_format {
         OutputFormat::Text => {
             println!("Success:: ");
@@ -112,9 +177,39 @@ fn main() {
             println!("Commands:  {}", trace.len());
             println!("Energy:    {}", state.get_energy());
             println!("ClockTime: ?ms");
+            if let Some(baseline) = baseline {
+                println!("Baseline:  {}", baseline);
+                println!("Ratio:     {:.4}", state.get_energy() as f64 / baseline as f64);
+            }
+            if let Some(ref summary) = summary {
+                println!("Idle:      {:.4}", summary.idle_ratio);
+                if let Some(bid) = summary.critical_bot {
+                    println!("Critical:  bot {} (busy {} steps)", bid.0, summary.critical_busy);
+                }
+                println!("Wasted:    {}", summary.wasted_energy);
+            }
         }
         OutputFormat::Json => {
-            println!("{{\"commands\":\"{}\",\"energy\":\"{}\"}}", trace.len(), state.get_energy());
+            let mut fields = vec![
+                ("commands", trace.len().to_string()),
+                ("energy", state.get_energy().to_string()),
+            ];
+            if let Some(baseline) = baseline {
+                fields.push(("baseline", baseline.to_string()));
+            }
+            if let Some(ref summary) = summary {
+                fields.push(("idle_ratio", format!("{:.4}", summary.idle_ratio)));
+                if let Some(bid) = summary.critical_bot {
+                    fields.push(("critical_bot", bid.0.to_string()));
+                    fields.push(("critical_busy", summary.critical_busy.to_string()));
+                }
+                fields.push(("wasted_energy", summary.wasted_energy.to_string()));
+            }
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|&(k, ref v)| format!("\"{}\":\"{}\"", k, v))
+                .collect();
+            println!("{{{}}}", fields.join(","));
         }
     }
 }
@@ -0,0 +1,150 @@
+extern crate getopts;
+extern crate nanobot_lib;
+
+use getopts::Options;
+use nanobot_lib::generator::*;
+use nanobot_lib::harness::Task;
+use nanobot_lib::model::Model;
+use nanobot_lib::tuning::*;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!(
+        "Usage: {} [options]
+
+Tasks: assemble, disassemble, reassemble
+Searches: grid, random, halving
+Give --source/--target files, or --shape and -r to generate a problem.",
+        program
+    );
+    print!("{}", opts.usage(&brief));
+}
+
+fn read_model(path: &str) -> Model {
+    let f = File::open(Path::new(path)).expect("file not found");
+    Model::new(&mut BufReader::new(f)).expect("failed to open model")
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.reqopt("t", "task", "set task", "TASK");
+    opts.reqopt("a", "ai", "set AI name", "AI");
+    opts.optopt("", "source", "set source model", "FILE");
+    opts.optopt("", "target", "set target model", "FILE");
+    opts.optopt("", "shape", "set generated shape", "SHAPE");
+    opts.optopt(
+        "r",
+        "resolution",
+        "set generated resolution (default: 20)",
+        "R",
+    );
+    opts.optopt("", "search", "set search (default: random)", "SEARCH");
+    opts.optopt(
+        "b",
+        "budget",
+        "set time budget in seconds (default: 60)",
+        "SECONDS",
+    );
+    opts.optopt("s", "seed", "set random seed (default: 0)", "SEED");
+    opts.optopt(
+        "o",
+        "output",
+        "append the result to FILE (default: tuning.tsv)",
+        "FILE",
+    );
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            print_usage(&program, opts);
+            eprintln!("{}", f);
+            process::exit(1);
+        }
+    };
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        return;
+    }
+    let task = Task::from_name(&matches.opt_str("task").unwrap()).unwrap_or_else(|| {
+        eprintln!("unknown task");
+        process::exit(1);
+    });
+    let ai = matches.opt_str("ai").unwrap();
+    let search = Search::from_name(&matches.opt_str("search").unwrap_or(String::from("random")))
+        .unwrap_or_else(|| {
+            eprintln!("unknown search");
+            process::exit(1);
+        });
+    let budget = matches
+        .opt_str("budget")
+        .map(|b| b.parse::<u64>().expect("invalid budget"))
+        .unwrap_or(60);
+    let seed = matches
+        .opt_str("seed")
+        .map(|s| s.parse::<u64>().expect("invalid seed"))
+        .unwrap_or(0);
+
+    let problem = if let Some(shape_name) = matches.opt_str("shape") {
+        let shape = Shape::from_name(&shape_name).unwrap_or_else(|| {
+            eprintln!("unknown shape: {}", shape_name);
+            process::exit(1);
+        });
+        let r = matches
+            .opt_str("resolution")
+            .map(|r| r.parse::<usize>().expect("invalid resolution"))
+            .unwrap_or(20);
+        let empty = Model::initial(r);
+        let (source, target) = match task {
+            Task::Assemble => (empty.clone(), generate(shape, r, seed)),
+            Task::Disassemble => (generate(shape, r, seed), empty),
+            Task::Reassemble => generate_pair(shape, shape, r, seed, 0.5),
+        };
+        Problem {
+            name: format!("{}_{}_{}", shape.name(), r, seed),
+            task,
+            source,
+            target,
+        }
+    } else {
+        let source = matches.opt_str("source").map(|p| read_model(&p));
+        let target = matches.opt_str("target").map(|p| read_model(&p));
+        let r = source
+            .as_ref()
+            .or_else(|| target.as_ref())
+            .map(|m| m.matrix.len())
+            .unwrap_or_else(|| {
+                print_usage(&program, opts);
+                eprintln!("source, target or shape should be selected");
+                process::exit(1);
+            });
+        let name = matches
+            .opt_str("target")
+            .or_else(|| matches.opt_str("source"))
+            .unwrap();
+        Problem {
+            name,
+            task,
+            source: source.unwrap_or_else(|| Model::initial(r)),
+            target: target.unwrap_or_else(|| Model::initial(r)),
+        }
+    };
+
+    let tuned = tune(&problem, &ai, search, Duration::from_secs(budget), seed);
+    let energy = |e: Option<i64>| e.map_or(String::from("failed"), |e| e.to_string());
+    println!("trials: {}", tuned.trials);
+    println!("default: {}", energy(tuned.default_energy));
+    println!("best: {}", energy(tuned.best_energy));
+    println!("{}", tuned.settings());
+    let output = matches
+        .opt_str("output")
+        .unwrap_or(String::from("tuning.tsv"));
+    record(Path::new(&output), &tuned).expect("failed to write result");
+}
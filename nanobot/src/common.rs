@@ -114,7 +114,34 @@ impl Command {
             }
         }
     }
+    // 1 byte 目から、このコマンドが何 byte からなるかを返す
+    fn encoded_len(v: u8) -> usize {
+        if v == 0b11111111 || v == 0b11111110 || v == 0b11111101 {
+            1
+        } else if (v & 0b00000111) == 0b101 || (v & 0b00000111) == 0b100 {
+            // Fission, SMove, LMove
+            2
+        } else if (v & 0b00000111) == 0b001 || (v & 0b00000111) == 0b000 {
+            // GFill, GVoid
+            4
+        } else {
+            1
+        }
+    }
     pub fn decode(input: &[u8], offset: &mut usize) -> Result<Command, Box<Error>> {
+        if *offset >= input.len() {
+            let message = format!("unexpected end of trace: offset={}", offset);
+            return Err(Box::new(CommandParseError::new(message)));
+        }
+        let len = Command::encoded_len(input[*offset]);
+        if *offset + len > input.len() {
+            let message = format!(
+                "unexpected end of trace: value={}, offset={}",
+                input[*offset], offset
+            );
+            return Err(Box::new(CommandParseError::new(message)));
+        }
+
         if input[*offset] == 0b11111111 {
             *offset += 1;
             return Ok(Command::Halt);
@@ -127,7 +154,7 @@ impl Command {
         } else if (input[*offset] & 0b00001111) == 0b0100 {
             let v1 = input[*offset] >> 4;
             let v2 = input[*offset + 1];
-            let lcd = LLCD::decode(v1, v2);
+            let lcd = LLCD::decode(v1, v2)?;
             *offset += 2;
             return Ok(Command::SMove(lcd));
         } else if (input[*offset] & 0b00001111) == 0b1100 {
@@ -135,34 +162,34 @@ impl Command {
             let v12 = (input[*offset + 1] >> 0) & 0b1111;
             let v21 = (input[*offset] >> 6) & 0b11;
             let v22 = (input[*offset + 1] >> 4) & 0b1111;
-            let slcd1 = SLCD::decode(v11, v12);
-            let slcd2 = SLCD::decode(v21, v22);
+            let slcd1 = SLCD::decode(v11, v12)?;
+            let slcd2 = SLCD::decode(v21, v22)?;
             *offset += 2;
             return Ok(Command::LMove(slcd1, slcd2));
         } else if (input[*offset] & 0b00000111) == 0b101 {
             let v1 = input[*offset] >> 3;
-            let ncd = NCD::decode(v1);
+            let ncd = NCD::decode(v1)?;
             let m = input[*offset + 1] as usize;
             *offset += 2;
             return Ok(Command::Fission(ncd, m));
         } else if (input[*offset] & 0b00000111) == 0b011 {
             let v1 = input[*offset] >> 3;
-            let ncd = NCD::decode(v1);
+            let ncd = NCD::decode(v1)?;
             *offset += 1;
             return Ok(Command::Fill(ncd));
         } else if (input[*offset] & 0b00000111) == 0b010 {
             let v1 = input[*offset] >> 3;
-            let ncd = NCD::decode(v1);
+            let ncd = NCD::decode(v1)?;
             *offset += 1;
             return Ok(Command::Void(ncd));
         } else if (input[*offset] & 0b00000111) == 0b111 {
             let v1 = input[*offset] >> 3;
-            let ncd = NCD::decode(v1);
+            let ncd = NCD::decode(v1)?;
             *offset += 1;
             return Ok(Command::FusionP(ncd));
         } else if (input[*offset] & 0b00000111) == 0b110 {
             let v1 = input[*offset] >> 3;
-            let ncd = NCD::decode(v1);
+            let ncd = NCD::decode(v1)?;
             *offset += 1;
             return Ok(Command::FusionS(ncd));
         } else if (input[*offset] & 0b00000111) == 0b001 {
@@ -170,8 +197,8 @@ impl Command {
             let v21 = input[*offset + 1];
             let v22 = input[*offset + 2];
             let v23 = input[*offset + 3];
-            let ncd = NCD::decode(v11);
-            let fcd = FCD::decode(v21, v22, v23);
+            let ncd = NCD::decode(v11)?;
+            let fcd = FCD::decode(v21, v22, v23)?;
             *offset += 4;
             return Ok(Command::GFill(ncd, fcd));
         } else if (input[*offset] & 0b00000111) == 0b000 {
@@ -179,8 +206,8 @@ impl Command {
             let v21 = input[*offset + 1];
             let v22 = input[*offset + 2];
             let v23 = input[*offset + 3];
-            let ncd = NCD::decode(v11);
-            let fcd = FCD::decode(v21, v22, v23);
+            let ncd = NCD::decode(v11)?;
+            let fcd = FCD::decode(v21, v22, v23)?;
             *offset += 4;
             return Ok(Command::GVoid(ncd, fcd));
         } else {
@@ -305,6 +332,11 @@ fn command_encdec_test() {
     assert_eq!(offset, 4);
 }
 
+fn invalid_cd_error(name: &str, x: i32, y: i32, z: i32) -> Box<Error> {
+    let message = format!("invalid {}: ({}, {}, {})", name, x, y, z);
+    Box::new(CommandParseError::new(message))
+}
+
 pub trait CD {
     fn x(&self) -> i32;
     fn y(&self) -> i32;
@@ -336,17 +368,24 @@ pub struct NCD {
 impl NCD {
     pub fn new(x: i32, y: i32, z: i32) -> Self {
         let ncd = NCD { x, y, z };
-        assert!(ncd.manhattan_length() <= 2 && ncd.chessboard_length() == 1);
+        assert!(ncd.is_valid());
         ncd
     }
+    fn is_valid(&self) -> bool {
+        self.manhattan_length() <= 2 && self.chessboard_length() == 1
+    }
     pub fn encode(&self) -> u8 {
         ((self.x + 1) * 9 + (self.y + 1) * 3 + (self.z + 1)) as u8
     }
-    pub fn decode(v: u8) -> Self {
-        let x = v as i32 / 9 % 3 - 1;
+    pub fn decode(v: u8) -> Result<Self, Box<Error>> {
+        let x = v as i32 / 9 - 1;
         let y = v as i32 / 3 % 3 - 1;
         let z = v as i32 / 1 % 3 - 1;
-        Self::new(x, y, z)
+        let ncd = NCD { x, y, z };
+        if !ncd.is_valid() {
+            return Err(invalid_cd_error("NCD", x, y, z));
+        }
+        Ok(ncd)
     }
 }
 
@@ -366,7 +405,7 @@ impl CD for NCD {
 fn ncd_encdec_test() {
     let ncd = NCD::new(1, 0, 0);
     assert_eq!(ncd.encode(), 18 + 3 + 1);
-    let ncd2 = NCD::decode(ncd.encode());
+    let ncd2 = NCD::decode(ncd.encode()).unwrap();
     assert_eq!(ncd2, ncd);
 }
 
@@ -380,9 +419,12 @@ pub struct FCD {
 impl FCD {
     pub fn new(x: i32, y: i32, z: i32) -> Self {
         let fcd = FCD { x, y, z };
-        assert!(0 < fcd.chessboard_length() && fcd.chessboard_length() <= 30);
+        assert!(fcd.is_valid());
         fcd
     }
+    fn is_valid(&self) -> bool {
+        0 < self.chessboard_length() && self.chessboard_length() <= 30
+    }
     pub fn encode(&self) -> (u8, u8, u8) {
         (
             (self.x + 30) as u8,
@@ -390,11 +432,15 @@ impl FCD {
             (self.z + 30) as u8,
         )
     }
-    pub fn decode(v1: u8, v2: u8, v3: u8) -> Self {
+    pub fn decode(v1: u8, v2: u8, v3: u8) -> Result<Self, Box<Error>> {
         let x = v1 as i32 - 30;
         let y = v2 as i32 - 30;
         let z = v3 as i32 - 30;
-        Self::new(x, y, z)
+        let fcd = FCD { x, y, z };
+        if !fcd.is_valid() {
+            return Err(invalid_cd_error("FCD", x, y, z));
+        }
+        Ok(fcd)
     }
 }
 
@@ -417,7 +463,7 @@ fn fcd_encdec_test() {
     assert_eq!(enc.0, 50);
     assert_eq!(enc.1, 40);
     assert_eq!(enc.2, 25);
-    let fcd2 = FCD::decode(enc.0, enc.1, enc.2);
+    let fcd2 = FCD::decode(enc.0, enc.1, enc.2).unwrap();
     assert_eq!(fcd2, fcd);
 }
 
@@ -431,13 +477,14 @@ pub struct SLCD {
 impl SLCD {
     pub fn new(x: i32, y: i32, z: i32) -> Self {
         let slcd = SLCD { x, y, z };
-        assert!(
-            slcd.manhattan_length() <= 5
-                && slcd.chessboard_length() > 0
-                && slcd.manhattan_length() == slcd.chessboard_length()
-        );
+        assert!(slcd.is_valid());
         slcd
     }
+    fn is_valid(&self) -> bool {
+        self.manhattan_length() <= 5
+            && self.chessboard_length() > 0
+            && self.manhattan_length() == self.chessboard_length()
+    }
     pub fn encode(&self) -> (u8, u8) {
         let ret = if self.x != 0 {
             (0b01, self.x + 5)
@@ -448,7 +495,7 @@ impl SLCD {
         };
         (ret.0, ret.1 as u8)
     }
-    pub fn decode(v1: u8, v2: u8) -> Self {
+    pub fn decode(v1: u8, v2: u8) -> Result<Self, Box<Error>> {
         let mut x = 0;
         let mut y = 0;
         let mut z = 0;
@@ -459,9 +506,14 @@ impl SLCD {
         } else if v1 == 0b11 {
             z = v2 as i32 - 5;
         } else {
-            assert!(false);
+            let message = format!("invalid SLCD axis: {}", v1);
+            return Err(Box::new(CommandParseError::new(message)));
+        }
+        let lcd = SLCD { x, y, z };
+        if !lcd.is_valid() {
+            return Err(invalid_cd_error("SLCD", x, y, z));
         }
-        Self::new(x, y, z)
+        Ok(lcd)
     }
 }
 
@@ -483,7 +535,7 @@ fn slcd_encdec_test() {
     let enc = slcd.encode();
     assert_eq!(enc.0, 1);
     assert_eq!(enc.1, 2);
-    let slcd2 = SLCD::decode(enc.0, enc.1);
+    let slcd2 = SLCD::decode(enc.0, enc.1).unwrap();
     assert_eq!(slcd2, slcd);
 }
 
@@ -497,13 +549,14 @@ pub struct LLCD {
 impl LLCD {
     pub fn new(x: i32, y: i32, z: i32) -> Self {
         let llcd = LLCD { x, y, z };
-        assert!(
-            llcd.manhattan_length() <= 15
-                && llcd.chessboard_length() > 0
-                && llcd.manhattan_length() == llcd.chessboard_length()
-        );
+        assert!(llcd.is_valid());
         llcd
     }
+    fn is_valid(&self) -> bool {
+        self.manhattan_length() <= 15
+            && self.chessboard_length() > 0
+            && self.manhattan_length() == self.chessboard_length()
+    }
     pub fn encode(&self) -> (u8, u8) {
         let ret = if self.x != 0 {
             (0b01, self.x + 15)
@@ -514,7 +567,7 @@ impl LLCD {
         };
         (ret.0, ret.1 as u8)
     }
-    pub fn decode(v1: u8, v2: u8) -> Self {
+    pub fn decode(v1: u8, v2: u8) -> Result<Self, Box<Error>> {
         let mut x = 0;
         let mut y = 0;
         let mut z = 0;
@@ -525,9 +578,14 @@ impl LLCD {
         } else if v1 == 0b11 {
             z = v2 as i32 - 15;
         } else {
-            assert!(false);
+            let message = format!("invalid LLCD axis: {}", v1);
+            return Err(Box::new(CommandParseError::new(message)));
+        }
+        let lcd = LLCD { x, y, z };
+        if !lcd.is_valid() {
+            return Err(invalid_cd_error("LLCD", x, y, z));
         }
-        Self::new(x, y, z)
+        Ok(lcd)
     }
 }
 
@@ -549,7 +607,7 @@ fn llcd_encdec_test() {
     let enc = llcd.encode();
     assert_eq!(enc.0, 2);
     assert_eq!(enc.1, 25);
-    let llcd2 = LLCD::decode(enc.0, enc.1);
+    let llcd2 = LLCD::decode(enc.0, enc.1).unwrap();
     assert_eq!(llcd2, llcd);
 }
 
@@ -752,13 +810,13 @@ pub fn encode_trace(trace: &[Command]) -> Vec<u8> {
     ret
 }
 
-pub fn decode_trace(input: &[u8]) -> Vec<Command> {
+pub fn decode_trace(input: &[u8]) -> Result<Vec<Command>, Box<Error>> {
     let mut ret = Vec::with_capacity(input.len() / 4);
     let mut offset = 0;
     while offset < input.len() {
-        ret.push(Command::decode(input, &mut offset).unwrap());
+        ret.push(Command::decode(input, &mut offset)?);
     }
-    ret
+    Ok(ret)
 }
 
 #[test]
@@ -771,7 +829,7 @@ fn encode_trace_test() {
     assert_eq!(result[0], 0b10011110);
     assert_eq!(result[1], 0b01110101);
     assert_eq!(result[2], 0b00000101);
-    let trace2 = decode_trace(&result[..]);
+    let trace2 = decode_trace(&result[..]).unwrap();
     assert_eq!(trace2, trace);
 }
 
@@ -785,6 +843,5 @@ pub fn read_trace_file(path: &Path) -> Result<Vec<Command>, Box<Error>> {
     let mut f = fs::File::open(path)?;
     let mut buffer = vec![];
     f.read_to_end(&mut buffer)?;
-    let ret = decode_trace(&buffer[..]);
-    Ok(ret)
+    decode_trace(&buffer[..])
 }
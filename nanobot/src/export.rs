@@ -0,0 +1,583 @@
+#![allow(dead_code)]
+
+use common::*;
+use model::Model;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::error::*;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+// 外側から見える面だけを出力するために、外部につながっている Void を調べる。
+// 座標を 1 ずらした (r+2)^3 の空間で、角から Void を塗りつぶす。
+fn calc_exterior(model: &Model) -> Vec<Vec<Vec<bool>>> {
+    let r = model.matrix.len();
+    let n = r + 2;
+    let mut exterior = vec![vec![vec![false; n]; n]; n];
+    let mut que = VecDeque::new();
+    exterior[0][0][0] = true;
+    que.push_back((0, 0, 0));
+
+    while let Some((x, y, z)) = que.pop_front() {
+        let ds: [(i32, i32, i32); 6] = [
+            (1, 0, 0),
+            (-1, 0, 0),
+            (0, 1, 0),
+            (0, -1, 0),
+            (0, 0, 1),
+            (0, 0, -1),
+        ];
+        for &(dx, dy, dz) in ds.iter() {
+            let nx = x as i32 + dx;
+            let ny = y as i32 + dy;
+            let nz = z as i32 + dz;
+            if nx < 0 || ny < 0 || nz < 0 || nx >= n as i32 || ny >= n as i32 || nz >= n as i32 {
+                continue;
+            }
+            let (nx, ny, nz) = (nx as usize, ny as usize, nz as usize);
+            if exterior[nx][ny][nz] {
+                continue;
+            }
+            let inside = 1 <= nx && nx <= r && 1 <= ny && ny <= r && 1 <= nz && nz <= r;
+            if inside && model.matrix[nx - 1][ny - 1][nz - 1] == Voxel::Full {
+                continue;
+            }
+            exterior[nx][ny][nz] = true;
+            que.push_back((nx, ny, nz));
+        }
+    }
+    exterior
+}
+
+// 面の向き。axis 方向 (0: x, 1: y, 2: z) の正負どちらを向いているか。
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct Face {
+    pub axis: usize,
+    pub positive: bool,
+    // 面の 4 頂点 (反時計回り、外側から見て)
+    pub vertices: [Position; 4],
+}
+
+impl Face {
+    pub fn normal(&self) -> Position {
+        let d = if self.positive { 1 } else { -1 };
+        match self.axis {
+            0 => Position::new(d, 0, 0),
+            1 => Position::new(0, d, 0),
+            _ => Position::new(0, 0, d),
+        }
+    }
+}
+
+fn axis_position(axis: usize, layer: i32, u: i32, v: i32) -> Position {
+    // (axis, u, v) は右手系になるように並べる: x -> (y, z), y -> (z, x), z -> (x, y)
+    match axis {
+        0 => Position::new(layer, u, v),
+        1 => Position::new(v, layer, u),
+        _ => Position::new(u, v, layer),
+    }
+}
+
+// 外側に露出している面を貪欲法で長方形にまとめて返す。
+pub fn exterior_faces(model: &Model) -> Vec<Face> {
+    let r = model.matrix.len() as i32;
+    let exterior = calc_exterior(model);
+    let is_full = |p: Position| {
+        0 <= p.x
+            && p.x < r
+            && 0 <= p.y
+            && p.y < r
+            && 0 <= p.z
+            && p.z < r
+            && model.voxel_at(p) == Voxel::Full
+    };
+    let is_exterior =
+        |p: Position| exterior[(p.x + 1) as usize][(p.y + 1) as usize][(p.z + 1) as usize];
+
+    let mut faces = vec![];
+    for axis in 0..3 {
+        for &positive in [false, true].iter() {
+            let d = if positive { 1 } else { -1 };
+            for layer in 0..r {
+                // この層で面を出すべき (u, v) のマスク
+                let mut mask = vec![vec![false; r as usize]; r as usize];
+                for u in 0..r {
+                    for v in 0..r {
+                        let p = axis_position(axis, layer, u, v);
+                        let q = axis_position(axis, layer + d, u, v);
+                        mask[u as usize][v as usize] = is_full(p) && is_exterior(q);
+                    }
+                }
+
+                for u in 0..r as usize {
+                    let mut v = 0;
+                    while v < r as usize {
+                        if !mask[u][v] {
+                            v += 1;
+                            continue;
+                        }
+                        let mut v_end = v + 1;
+                        while v_end < r as usize && mask[u][v_end] {
+                            v_end += 1;
+                        }
+                        let mut u_end = u + 1;
+                        while u_end < r as usize && (v..v_end).all(|w| mask[u_end][w]) {
+                            u_end += 1;
+                        }
+                        for row in mask.iter_mut().take(u_end).skip(u) {
+                            for cell in row[v..v_end].iter_mut() {
+                                *cell = false;
+                            }
+                        }
+
+                        let plane = if positive { layer + 1 } else { layer };
+                        let (u0, u1, v0, v1) = (u as i32, u_end as i32, v as i32, v_end as i32);
+                        let mut vertices = [
+                            axis_position(axis, plane, u0, v0),
+                            axis_position(axis, plane, u1, v0),
+                            axis_position(axis, plane, u1, v1),
+                            axis_position(axis, plane, u0, v1),
+                        ];
+                        if !positive {
+                            vertices.reverse();
+                        }
+                        faces.push(Face {
+                            axis,
+                            positive,
+                            vertices,
+                        });
+                        v = v_end;
+                    }
+                }
+            }
+        }
+    }
+    faces
+}
+
+// 面の頂点を重複なしに並べる
+fn index_vertices(faces: &[Face]) -> (Vec<Position>, Vec<[usize; 4]>) {
+    let mut vertices = vec![];
+    let mut indices = HashMap::new();
+    let mut quads = vec![];
+    for face in faces.iter() {
+        let mut quad = [0; 4];
+        for (i, v) in face.vertices.iter().enumerate() {
+            let len = vertices.len();
+            let index = *indices.entry(*v).or_insert(len);
+            if index == len {
+                vertices.push(*v);
+            }
+            quad[i] = index;
+        }
+        quads.push(quad);
+    }
+    (vertices, quads)
+}
+
+pub fn encode_obj(model: &Model) -> String {
+    let faces = exterior_faces(model);
+    let (vertices, quads) = index_vertices(&faces);
+
+    let mut s = String::new();
+    s.push_str(&format!("# R={}\n", model.matrix.len()));
+    for v in vertices.iter() {
+        s.push_str(&format!("v {} {} {}\n", v.x, v.y, v.z));
+    }
+    let normals = [
+        (0, false, "-1 0 0"),
+        (0, true, "1 0 0"),
+        (1, false, "0 -1 0"),
+        (1, true, "0 1 0"),
+        (2, false, "0 0 -1"),
+        (2, true, "0 0 1"),
+    ];
+    for &(_, _, n) in normals.iter() {
+        s.push_str(&format!("vn {}\n", n));
+    }
+    for (face, quad) in faces.iter().zip(quads.iter()) {
+        let n = normals
+            .iter()
+            .position(|&(axis, positive, _)| axis == face.axis && positive == face.positive)
+            .unwrap()
+            + 1;
+        s.push_str(&format!(
+            "f {}//{} {}//{} {}//{} {}//{}\n",
+            quad[0] + 1,
+            n,
+            quad[1] + 1,
+            n,
+            quad[2] + 1,
+            n,
+            quad[3] + 1,
+            n
+        ));
+    }
+    s
+}
+
+pub fn encode_ply(model: &Model) -> String {
+    let faces = exterior_faces(model);
+    let (vertices, quads) = index_vertices(&faces);
+
+    let mut s = String::new();
+    s.push_str("ply\n");
+    s.push_str("format ascii 1.0\n");
+    s.push_str(&format!("element vertex {}\n", vertices.len()));
+    s.push_str("property int x\n");
+    s.push_str("property int y\n");
+    s.push_str("property int z\n");
+    s.push_str(&format!("element face {}\n", quads.len()));
+    s.push_str("property list uchar int vertex_indices\n");
+    s.push_str("end_header\n");
+    for v in vertices.iter() {
+        s.push_str(&format!("{} {} {}\n", v.x, v.y, v.z));
+    }
+    for q in quads.iter() {
+        s.push_str(&format!("4 {} {} {} {}\n", q[0], q[1], q[2], q[3]));
+    }
+    s
+}
+
+fn push_u32(buffer: &mut Vec<u8>, v: u32) {
+    buffer.push((v & 0xff) as u8);
+    buffer.push(((v >> 8) & 0xff) as u8);
+    buffer.push(((v >> 16) & 0xff) as u8);
+    buffer.push(((v >> 24) & 0xff) as u8);
+}
+
+fn push_chunk(buffer: &mut Vec<u8>, id: &[u8], content: &[u8], children: &[u8]) {
+    buffer.extend_from_slice(id);
+    push_u32(buffer, content.len() as u32);
+    push_u32(buffer, children.len() as u32);
+    buffer.extend_from_slice(content);
+    buffer.extend_from_slice(children);
+}
+
+// MagicaVoxel は z が上向きなので、Model の (x, y, z) を (x, z, y) として書き出す。
+pub fn encode_vox(model: &Model) -> Vec<u8> {
+    let r = model.matrix.len();
+
+    let mut size = vec![];
+    push_u32(&mut size, r as u32);
+    push_u32(&mut size, r as u32);
+    push_u32(&mut size, r as u32);
+
+    let mut voxels = vec![];
+    for x in 0..r {
+        for y in 0..r {
+            for z in 0..r {
+                if model.matrix[x][y][z] == Voxel::Full {
+                    voxels.push([x as u8, z as u8, y as u8, 1]);
+                }
+            }
+        }
+    }
+    let mut xyzi = vec![];
+    push_u32(&mut xyzi, voxels.len() as u32);
+    for v in voxels.iter() {
+        xyzi.extend_from_slice(v);
+    }
+
+    let mut children = vec![];
+    push_chunk(&mut children, b"SIZE", &size, &[]);
+    push_chunk(&mut children, b"XYZI", &xyzi, &[]);
+
+    let mut buffer = vec![];
+    buffer.extend_from_slice(b"VOX ");
+    push_u32(&mut buffer, 150);
+    push_chunk(&mut buffer, b"MAIN", &[], &children);
+    buffer
+}
+
+fn push_u32_be(buffer: &mut Vec<u8>, v: u32) {
+    buffer.push(((v >> 24) & 0xff) as u8);
+    buffer.push(((v >> 16) & 0xff) as u8);
+    buffer.push(((v >> 8) & 0xff) as u8);
+    buffer.push((v & 0xff) as u8);
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in data.iter() {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &d in data.iter() {
+        a = (a + d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn push_png_chunk(buffer: &mut Vec<u8>, id: &[u8], content: &[u8]) {
+    push_u32_be(buffer, content.len() as u32);
+    let start = buffer.len();
+    buffer.extend_from_slice(id);
+    buffer.extend_from_slice(content);
+    let crc = crc32(&buffer[start..]);
+    push_u32_be(buffer, crc);
+}
+
+// width * height の RGB 画素 (左上から行ごと) を PNG にする。
+// 圧縮はせず、deflate の stored block に詰めるだけ。
+pub fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3);
+    // 各行の先頭にフィルタの種類 (0: なし) を置く
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    while let Some(block) = blocks.next() {
+        zlib.push(if blocks.peek().is_none() { 1 } else { 0 });
+        let len = block.len() as u16;
+        zlib.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        zlib.extend_from_slice(block);
+    }
+    push_u32_be(&mut zlib, adler32(&raw));
+
+    let mut ihdr = vec![];
+    push_u32_be(&mut ihdr, width as u32);
+    push_u32_be(&mut ihdr, height as u32);
+    // 8 bit, RGB, 圧縮・フィルタ・インターレースはすべて標準
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut buffer = b"\x89PNG\r\n\x1a\n".to_vec();
+    push_png_chunk(&mut buffer, b"IHDR", &ihdr);
+    push_png_chunk(&mut buffer, b"IDAT", &zlib);
+    push_png_chunk(&mut buffer, b"IEND", &[]);
+    buffer
+}
+
+// trace を実行したときの各 nanobot の軌跡を OBJ のポリラインとして出力する。
+pub fn encode_trace_obj(r: usize, trace: &[Command]) -> String {
+    let mut bots = vec![Nanobot::initial()];
+    // bid ごとの軌跡
+    let mut paths: HashMap<Bid, Vec<Vec<Position>>> = HashMap::new();
+    paths.insert(Bid(1), vec![vec![Position::zero()]]);
+
+    let mut offset = 0;
+    while offset < trace.len() && !bots.is_empty() {
+        let n = bots.len();
+        if offset + n > trace.len() {
+            break;
+        }
+        let step = &trace[offset..offset + n];
+        offset += n;
+
+        let mut added = vec![];
+        let mut deleted = vec![];
+        for (i, command) in step.iter().enumerate() {
+            let c = bots[i].pos;
+            match command {
+                Command::SMove(llcd) => {
+                    bots[i].pos = c + llcd;
+                }
+                Command::LMove(slcd1, slcd2) => {
+                    let p = c + slcd1;
+                    paths.get_mut(&bots[i].bid).unwrap().last_mut().unwrap().push(p);
+                    bots[i].pos = p + slcd2;
+                }
+                Command::Fission(ncd, m) if *m < bots[i].seeds.len() => {
+                    let bot = bots[i].fission(ncd, *m);
+                    paths
+                        .entry(bot.bid)
+                        .or_insert_with(Vec::new)
+                        .push(vec![c, bot.pos]);
+                    added.push(bot);
+                }
+                Command::FusionP(ncd) => {
+                    let s = c + ncd;
+                    if let Some(j) = bots.iter().position(|b| b.pos == s) {
+                        let mut secondary = bots[j].clone();
+                        bots[i].fusion(&mut secondary);
+                        deleted.push(secondary.bid);
+                    }
+                }
+                Command::FusionS(ncd) => {
+                    paths.get_mut(&bots[i].bid).unwrap().last_mut().unwrap().push(c + ncd);
+                }
+                _ => {}
+            }
+            if bots[i].pos != c {
+                paths.get_mut(&bots[i].bid).unwrap().last_mut().unwrap().push(bots[i].pos);
+            }
+        }
+        bots.retain(|b| !deleted.contains(&b.bid));
+        bots.extend(added);
+        bots.sort();
+    }
+
+    let mut s = String::new();
+    s.push_str(&format!("# R={}\n", r));
+    let mut bids: Vec<&Bid> = paths.keys().collect();
+    bids.sort();
+    let mut index = 1;
+    for bid in bids {
+        s.push_str(&format!("o bot{}\n", bid.0));
+        for path in paths[bid].iter() {
+            if path.len() < 2 {
+                continue;
+            }
+            for p in path.iter() {
+                // voxel の中心を通るようにする
+                s.push_str(&format!(
+                    "v {} {} {}\n",
+                    p.x as f64 + 0.5,
+                    p.y as f64 + 0.5,
+                    p.z as f64 + 0.5
+                ));
+            }
+            s.push('l');
+            for i in 0..path.len() {
+                s.push_str(&format!(" {}", index + i));
+            }
+            s.push('\n');
+            index += path.len();
+        }
+    }
+    s
+}
+
+pub fn write_obj_file(path: &Path, model: &Model) -> Result<(), Box<Error>> {
+    let mut buffer = fs::File::create(path)?;
+    buffer.write_all(encode_obj(model).as_bytes())?;
+    Ok(())
+}
+
+pub fn write_ply_file(path: &Path, model: &Model) -> Result<(), Box<Error>> {
+    let mut buffer = fs::File::create(path)?;
+    buffer.write_all(encode_ply(model).as_bytes())?;
+    Ok(())
+}
+
+pub fn write_vox_file(path: &Path, model: &Model) -> Result<(), Box<Error>> {
+    let mut buffer = fs::File::create(path)?;
+    buffer.write_all(&encode_vox(model))?;
+    Ok(())
+}
+
+pub fn write_trace_obj_file(path: &Path, r: usize, trace: &[Command]) -> Result<(), Box<Error>> {
+    let mut buffer = fs::File::create(path)?;
+    buffer.write_all(encode_trace_obj(r, trace).as_bytes())?;
+    Ok(())
+}
+
+#[test]
+fn test_exterior_faces_of_single_voxel() {
+    let mut model = Model::initial(3);
+    model.matrix[1][0][1] = Voxel::Full;
+    let faces = exterior_faces(&model);
+    assert_eq!(faces.len(), 6);
+}
+
+#[test]
+fn test_exterior_faces_are_merged() {
+    // 2x1x2 の板は 6 枚の長方形になる
+    let mut model = Model::initial(4);
+    model.matrix[1][0][1] = Voxel::Full;
+    model.matrix[1][0][2] = Voxel::Full;
+    model.matrix[2][0][1] = Voxel::Full;
+    model.matrix[2][0][2] = Voxel::Full;
+    let faces = exterior_faces(&model);
+    assert_eq!(faces.len(), 6);
+    let top = faces
+        .iter()
+        .find(|f| f.axis == 1 && f.positive)
+        .unwrap();
+    for v in top.vertices.iter() {
+        assert_eq!(v.y, 1);
+    }
+}
+
+#[test]
+fn test_exterior_faces_ignore_inner_cavity() {
+    // 3x3x3 の中空の箱は外側の 6 枚だけになる
+    let mut model = Model::initial(5);
+    for x in 1..4 {
+        for y in 0..3 {
+            for z in 1..4 {
+                if (x, y, z) != (2, 1, 2) {
+                    model.matrix[x][y][z] = Voxel::Full;
+                }
+            }
+        }
+    }
+    let faces = exterior_faces(&model);
+    assert_eq!(faces.len(), 6);
+}
+
+#[test]
+fn test_encode_obj_and_ply() {
+    let mut model = Model::initial(3);
+    model.matrix[1][0][1] = Voxel::Full;
+
+    let obj = encode_obj(&model);
+    assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 8);
+    assert_eq!(obj.lines().filter(|l| l.starts_with("f ")).count(), 6);
+
+    let ply = encode_ply(&model);
+    assert!(ply.contains("element vertex 8\n"));
+    assert!(ply.contains("element face 6\n"));
+}
+
+#[test]
+fn test_encode_vox() {
+    let mut model = Model::initial(3);
+    model.matrix[1][0][1] = Voxel::Full;
+    model.matrix[1][1][1] = Voxel::Full;
+    let vox = encode_vox(&model);
+    assert_eq!(&vox[0..4], b"VOX ");
+    assert_eq!(&vox[8..12], b"MAIN");
+    // header(8) + MAIN(12) + SIZE(12 + 12) + XYZI(12 + 4 + 2 * 4)
+    assert_eq!(vox.len(), 8 + 12 + 24 + 24);
+    // 2 つ目の voxel は (x, z, y) = (1, 1, 1)
+    assert_eq!(&vox[vox.len() - 4..], &[1, 1, 1, 1]);
+}
+
+#[test]
+fn test_encode_png() {
+    // 2x1 の画像: 赤と青
+    let png = encode_png(2, 1, &[255, 0, 0, 0, 0, 255]);
+    assert_eq!(&png[0..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+    // zlib: header(2) + block header(5) + 1 行 (1 + 6) + adler32(4)
+    assert_eq!(png.len(), 8 + (12 + 13) + (12 + 2 + 5 + 7 + 4) + 12);
+    // IEND の CRC は決まっている
+    assert_eq!(
+        &png[png.len() - 12..],
+        &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+    );
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+}
+
+#[test]
+fn test_encode_trace_obj() {
+    let trace = vec![
+        Command::SMove(LLCD::new(0, 1, 0)),
+        Command::Fission(NCD::new(1, 0, 0), 0),
+        Command::Wait,
+        Command::SMove(LLCD::new(0, 0, 1)),
+    ];
+    let obj = encode_trace_obj(3, &trace);
+    assert!(obj.contains("o bot1\n"));
+    assert!(obj.contains("o bot2\n"));
+    assert_eq!(obj.lines().filter(|l| l.starts_with("l ")).count(), 2);
+}
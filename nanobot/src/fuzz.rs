@@ -0,0 +1,212 @@
+#![allow(dead_code)]
+extern crate rand;
+
+// trace の decode とシミュレータ用の fuzz target。
+// cargo-fuzz (fuzz/ 以下) からも、乱数で入力を作る簡易ドライバ (src/bin/fuzz.rs) からも使う。
+// どちらの関数も、どんな入力に対しても panic せずにエラーを返すことを確かめる。
+
+use self::rand::Rng;
+use self::rand::XorShiftRng;
+use common::*;
+use generator::*;
+use harness::panic_message;
+use model::Model;
+use state::State;
+use std::cmp::min;
+use std::panic;
+
+// 任意の byte 列を trace として読む。
+// 読めた場合は encode すると元の byte 列に戻るはず。
+pub fn fuzz_decode(data: &[u8]) {
+    if let Ok(trace) = decode_trace(data) {
+        assert_eq!(encode_trace(&trace), data);
+    }
+}
+
+// 読めない byte は読み飛ばしてコマンド列にする
+fn decode_commands(data: &[u8]) -> Vec<Command> {
+    let mut commands = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        match Command::decode(data, &mut offset) {
+            Ok(command) => commands.push(command),
+            Err(_) => offset += 1,
+        }
+    }
+    commands
+}
+
+// 先頭 3 byte で解像度と初期モデルを決め、残りをコマンド列としてシミュレータに流す。
+// bot の数と合わない長さのコマンドもそのまま渡して、エラーになることを確かめる。
+pub fn fuzz_simulate(data: &[u8]) {
+    if data.len() < 3 {
+        return;
+    }
+    let r = MIN_RESOLUTION + data[0] as usize % 6;
+    let shapes = Shape::all();
+    let source = match data[1] as usize % (shapes.len() + 1) {
+        0 => Model::initial(r),
+        i => generate(shapes[i - 1], r, u64::from(data[2])),
+    };
+    let trace = decode_commands(&data[3..]);
+
+    let mut state = State::initial_with_model(&source);
+    let mut offset = 0;
+    let mut ok = true;
+    while offset < trace.len() {
+        let n = min(state.get_bot_count(), trace.len() - offset);
+        if state.update_time_step(&trace[offset..offset + n]).is_err() {
+            ok = false;
+            break;
+        }
+        offset += n;
+    }
+    let _ = state.end_check(&source);
+    let _ = state.get_energy();
+
+    // execute_trace も同じ結果になる
+    let mut state = State::initial_with_model(&source);
+    assert_eq!(state.execute_trace(&trace).is_ok(), ok);
+}
+
+fn random_llcd(rng: &mut XorShiftRng, max: i32) -> (i32, i32, i32) {
+    let mut d = rng.gen_range(1, max + 1);
+    if rng.gen::<bool>() {
+        d = -d;
+    }
+    match rng.gen_range(0, 3) {
+        0 => (d, 0, 0),
+        1 => (0, d, 0),
+        _ => (0, 0, d),
+    }
+}
+
+fn random_ncd(rng: &mut XorShiftRng) -> NCD {
+    let ncds = all_ncd();
+    ncds[rng.gen_range(0, ncds.len())]
+}
+
+fn random_fcd(rng: &mut XorShiftRng) -> FCD {
+    loop {
+        let x = rng.gen_range(-3, 4);
+        let y = rng.gen_range(-3, 4);
+        let z = rng.gen_range(-3, 4);
+        if x != 0 || y != 0 || z != 0 {
+            return FCD::new(x, y, z);
+        }
+    }
+}
+
+// 正しい形式のコマンドをランダムに作る
+pub fn random_command(rng: &mut XorShiftRng) -> Command {
+    match rng.gen_range(0, 12) {
+        0 => Command::Halt,
+        1 => Command::Wait,
+        2 => Command::Flip,
+        3 => {
+            let (x, y, z) = random_llcd(rng, 15);
+            Command::SMove(LLCD::new(x, y, z))
+        }
+        4 => {
+            let (x1, y1, z1) = random_llcd(rng, 5);
+            let (x2, y2, z2) = random_llcd(rng, 5);
+            Command::LMove(SLCD::new(x1, y1, z1), SLCD::new(x2, y2, z2))
+        }
+        5 => Command::Fission(random_ncd(rng), rng.gen_range(0, 41)),
+        6 => Command::Fill(random_ncd(rng)),
+        7 => Command::Void(random_ncd(rng)),
+        8 => Command::FusionP(random_ncd(rng)),
+        9 => Command::FusionS(random_ncd(rng)),
+        10 => Command::GFill(random_ncd(rng), random_fcd(rng)),
+        _ => Command::GVoid(random_ncd(rng), random_fcd(rng)),
+    }
+}
+
+// 壊れた byte と正しいコマンドを混ぜた入力を作る
+pub fn random_input(rng: &mut XorShiftRng, max_commands: usize) -> Vec<u8> {
+    let mut data = vec![rng.gen::<u8>(), rng.gen::<u8>(), rng.gen::<u8>()];
+    let n = rng.gen_range(0, max_commands + 1);
+    for _ in 0..n {
+        if rng.gen_range(0, 4) == 0 {
+            data.push(rng.gen::<u8>());
+        } else {
+            data.extend(random_command(rng).encode());
+        }
+    }
+    data
+}
+
+// target にランダムな入力を iterations 回与えて、panic した入力とそのメッセージを返す
+pub fn run_random<F>(target: F, seed: u64, iterations: usize) -> Option<(Vec<u8>, String)>
+where
+    F: Fn(&[u8]),
+{
+    let mut rng = seeded_rng(seed);
+    for _ in 0..iterations {
+        let data = random_input(&mut rng, 64);
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| target(&data)));
+        if let Err(payload) = result {
+            return Some((data, panic_message(payload)));
+        }
+    }
+    None
+}
+
+#[test]
+fn test_fuzz_decode_regressions() {
+    let crashers: Vec<&[u8]> = vec![
+        // 途中で切れている SMove, GVoid
+        &[0b00000100],
+        &[120, 37],
+        &[144, 248],
+        // 範囲外の NCD
+        &[0b11000111],
+        &[0b11111011],
+        // FCD が 0 や 31
+        &[81, 30, 30, 30],
+        &[81, 61, 30, 30],
+        // SLCD, LLCD の軸が 0
+        &[0b00001100, 0x55],
+        &[0b00000100, 0],
+        // LLCD が 0
+        &[0b00010100, 15],
+        // 乱数で見つかったもの
+        &[176, 190, 224, 169],
+        &[19, 92, 219, 54],
+        &[99, 99, 199],
+        &[193, 48, 156, 53, 105],
+    ];
+    for data in crashers {
+        assert!(decode_trace(data).is_err(), "{:?}", data);
+        fuzz_decode(data);
+    }
+}
+
+#[test]
+fn test_fuzz_simulate_regressions() {
+    let crashers: Vec<Vec<u8>> = vec![
+        // bot が 1 体なのにコマンドが 2 つ
+        vec![0, 0, 0, 0b11111110, 0b11111110],
+        // Halt の後にコマンドが残っている
+        vec![0, 0, 0, 0b11111111, 0b11111110],
+    ];
+    for data in crashers.iter() {
+        fuzz_simulate(data);
+    }
+
+    let mut state = State::initial(5);
+    assert!(state.update_time_step(&[]).is_err());
+    assert!(state
+        .update_time_step(&[Command::Wait, Command::Wait])
+        .is_err());
+    let mut state = State::initial(5);
+    assert!(state.update_time_step(&[Command::Halt]).is_ok());
+    assert!(state.update_time_step(&[Command::Wait]).is_err());
+}
+
+#[test]
+fn test_fuzz_random() {
+    assert_eq!(run_random(fuzz_decode, 0, 2000), None);
+    assert_eq!(run_random(fuzz_simulate, 0, 500), None);
+}
+
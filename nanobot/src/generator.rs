@@ -0,0 +1,383 @@
+#![allow(dead_code)]
+extern crate rand;
+
+use self::rand::Rng;
+use self::rand::SeedableRng;
+use self::rand::XorShiftRng;
+use common::*;
+use import::remove_floating_voxels;
+use model::Model;
+use std::cmp::{max, min};
+
+// 公式の問題にはない形のモデルを作るための生成器。
+// どの形も床につながっていて、x, z は [1, R-2]、y は [0, R-2] に収まる。
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Shape {
+    Tower,
+    Overhang,
+    Arch,
+    HollowShell,
+    Blob,
+    ThinWall,
+    Pillars,
+}
+
+impl Shape {
+    pub fn all() -> Vec<Shape> {
+        vec![
+            Shape::Tower,
+            Shape::Overhang,
+            Shape::Arch,
+            Shape::HollowShell,
+            Shape::Blob,
+            Shape::ThinWall,
+            Shape::Pillars,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Shape::Tower => "tower",
+            Shape::Overhang => "overhang",
+            Shape::Arch => "arch",
+            Shape::HollowShell => "hollow_shell",
+            Shape::Blob => "blob",
+            Shape::ThinWall => "thin_wall",
+            Shape::Pillars => "pillars",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Shape> {
+        Shape::all().into_iter().find(|s| s.name() == name)
+    }
+}
+
+// 生成できる最小の解像度 (HollowShell の中に空洞ができる大きさ)
+pub const MIN_RESOLUTION: usize = 5;
+
+// rand 0.5 には seed_from_u64 がないので、u64 から 16 byte の seed を作る。
+// XorShiftRng は seed が全部 0 だと使えないので定数を混ぜておく。
+pub fn seeded_rng(seed: u64) -> XorShiftRng {
+    let mut bytes = [0; 16];
+    let mixed = [seed, seed ^ 0x9e37_79b9_7f4a_7c15];
+    for (i, v) in mixed.iter().enumerate() {
+        for j in 0..8 {
+            bytes[i * 8 + j] = (v >> (j * 8)) as u8;
+        }
+    }
+    XorShiftRng::from_seed(bytes)
+}
+
+struct Generator {
+    rng: XorShiftRng,
+    model: Model,
+    r: i32,
+}
+
+impl Generator {
+    fn new(r: usize, seed: u64) -> Self {
+        assert!(r >= MIN_RESOLUTION && r <= 250);
+        Generator {
+            rng: seeded_rng(seed),
+            model: Model::initial(r),
+            r: r as i32,
+        }
+    }
+
+    fn is_valid(&self, p: Position) -> bool {
+        1 <= p.x && p.x <= self.r - 2 && 0 <= p.y && p.y <= self.r - 2 && 1 <= p.z
+            && p.z <= self.r - 2
+    }
+
+    fn fill(&mut self, p: Position) {
+        if self.is_valid(p) {
+            self.model.set_voxel_at(p, Voxel::Full);
+        }
+    }
+
+    // [p1, p2] の直方体を埋める。範囲外ははみ出さないように切り落とす。
+    fn fill_box(&mut self, p1: Position, p2: Position) {
+        let region = Region(p1, p2).canonical();
+        for p in region.iter() {
+            self.fill(p);
+        }
+    }
+
+    // [lo, hi] から一様に選ぶ
+    fn range(&mut self, lo: i32, hi: i32) -> i32 {
+        if lo >= hi {
+            return lo;
+        }
+        self.rng.gen_range(lo, hi + 1)
+    }
+
+    fn max_xz(&self) -> i32 {
+        self.r - 2
+    }
+
+    fn max_y(&self) -> i32 {
+        self.r - 2
+    }
+
+    fn tower(&mut self) {
+        let count = self.range(1, 4);
+        let max_width = max(1, self.r / 4);
+        for _ in 0..count {
+            let w = self.range(1, max_width);
+            let d = self.range(1, max_width);
+            let h = self.range(1, self.max_y() + 1);
+            let x = self.range(1, self.max_xz() - w + 1);
+            let z = self.range(1, self.max_xz() - d + 1);
+            self.fill_box(
+                Position::new(x, 0, z),
+                Position::new(x + w - 1, h - 1, z + d - 1),
+            );
+        }
+    }
+
+    // 柱の上から横に張り出した板
+    fn overhang(&mut self) {
+        let max_width = max(1, self.r / 5);
+        let w = self.range(1, max_width);
+        let h = self.range(2, self.max_y() + 1);
+        let x = self.range(1, self.max_xz() - w + 1);
+        let z = self.range(1, self.max_xz() - w + 1);
+        self.fill_box(
+            Position::new(x, 0, z),
+            Position::new(x + w - 1, h - 1, z + w - 1),
+        );
+
+        let thickness = self.range(1, min(2, h));
+        let y = h - thickness;
+        let (dx, dz) = match self.range(0, 3) {
+            0 => (1, 0),
+            1 => (-1, 0),
+            2 => (0, 1),
+            _ => (0, -1),
+        };
+        let length = self.range(1, self.r);
+        let (x1, z1) = (x + w - 1, z + w - 1);
+        let (x2, z2) = (x1 + dx * length, z1 + dz * length);
+        let (x3, z3) = (x + dx * length, z + dz * length);
+        self.fill_box(
+            Position::new(min(x, x3), y, min(z, z3)),
+            Position::new(max(x1, x2), h - 1, max(z1, z2)),
+        );
+    }
+
+    // 2 本の柱を梁でつないだもの
+    fn arch(&mut self) {
+        let along_x = self.rng.gen::<bool>();
+        let span = self.range(3, self.max_xz());
+        let start = self.range(1, self.max_xz() - span + 1);
+        let depth = self.range(1, max(1, self.r / 4));
+        let side = self.range(1, self.max_xz() - depth + 1);
+        let h = self.range(1, self.max_y() - 1);
+        let t = self.range(1, min(3, self.max_y() - h + 1));
+        let leg = self.range(1, max(1, (span - 1) / 2));
+
+        let boxes = [
+            (start, 0, start + leg - 1, h - 1),
+            (start + span - leg, 0, start + span - 1, h - 1),
+            (start, h, start + span - 1, h + t - 1),
+        ];
+        for &(a1, y1, a2, y2) in boxes.iter() {
+            if along_x {
+                self.fill_box(
+                    Position::new(a1, y1, side),
+                    Position::new(a2, y2, side + depth - 1),
+                );
+            } else {
+                self.fill_box(
+                    Position::new(side, y1, a1),
+                    Position::new(side + depth - 1, y2, a2),
+                );
+            }
+        }
+    }
+
+    // 中が空洞の箱。空洞は外から見えない。
+    fn hollow_shell(&mut self) {
+        let w = self.range(3, self.max_xz());
+        let d = self.range(3, self.max_xz());
+        let h = self.range(3, self.max_y() + 1);
+        let x = self.range(1, self.max_xz() - w + 1);
+        let z = self.range(1, self.max_xz() - d + 1);
+        let p1 = Position::new(x, 0, z);
+        let p2 = Position::new(x + w - 1, h - 1, z + d - 1);
+        for p in Region(p1, p2).iter() {
+            let on_surface = p.x == p1.x || p.x == p2.x || p.y == p1.y || p.y == p2.y
+                || p.z == p1.z || p.z == p2.z;
+            if on_surface {
+                self.fill(p);
+            }
+        }
+    }
+
+    // 床の 1 点から隣接する voxel をランダムに増やしていく
+    fn blob(&mut self) {
+        let volume = (self.r - 2) * (self.r - 2) * (self.r - 1);
+        let size = self.range(1, max(1, volume / 8)) as usize;
+        let seed = Position::new(
+            self.range(1, self.max_xz()),
+            0,
+            self.range(1, self.max_xz()),
+        );
+        let mut voxels = vec![seed];
+        self.fill(seed);
+        let mut trial = 0;
+        while voxels.len() < size && trial < size * 20 {
+            trial += 1;
+            let from = voxels[self.rng.gen_range(0, voxels.len())];
+            let next = adjacent(from)[self.rng.gen_range(0, 6)];
+            if !self.is_valid(next) || self.model.voxel_at(next) == Voxel::Full {
+                continue;
+            }
+            self.fill(next);
+            voxels.push(next);
+        }
+    }
+
+    // 厚さ 1 の壁
+    fn thin_wall(&mut self) {
+        let count = self.range(1, 4);
+        for _ in 0..count {
+            let along_x = self.rng.gen::<bool>();
+            let length = self.range(1, self.max_xz());
+            let start = self.range(1, self.max_xz() - length + 1);
+            let side = self.range(1, self.max_xz());
+            let h = self.range(1, self.max_y() + 1);
+            if along_x {
+                self.fill_box(
+                    Position::new(start, 0, side),
+                    Position::new(start + length - 1, h - 1, side),
+                );
+            } else {
+                self.fill_box(
+                    Position::new(side, 0, start),
+                    Position::new(side, h - 1, start + length - 1),
+                );
+            }
+        }
+    }
+
+    // 互いにつながっていない細い柱をたくさん立てる
+    fn pillars(&mut self) {
+        let mut placed = false;
+        let mut x = 1;
+        while x <= self.max_xz() {
+            let mut z = 1;
+            while z <= self.max_xz() {
+                if !placed || self.rng.gen_bool(0.7) {
+                    let h = self.range(1, self.max_y() + 1);
+                    self.fill_box(Position::new(x, 0, z), Position::new(x, h - 1, z));
+                    placed = true;
+                }
+                z += 2;
+            }
+            x += 2;
+        }
+    }
+}
+
+// shape の形のモデルを、解像度 r、seed で決まるように生成する
+pub fn generate(shape: Shape, r: usize, seed: u64) -> Model {
+    let mut g = Generator::new(r, seed);
+    match shape {
+        Shape::Tower => g.tower(),
+        Shape::Overhang => g.overhang(),
+        Shape::Arch => g.arch(),
+        Shape::HollowShell => g.hollow_shell(),
+        Shape::Blob => g.blob(),
+        Shape::ThinWall => g.thin_wall(),
+        Shape::Pillars => g.pillars(),
+    }
+    g.model
+}
+
+// reassemble 用の source と target の組を作る。
+// target は target_shape の形に、source の各 voxel を確率 overlap で残したものを重ねる。
+// 残した voxel の下や横の voxel を残さなかったときは浮くので取り除く。そのため、実際に残る割合は overlap より小さい。
+// overlap = 0.0 なら source とは独立、1.0 なら target は source を含む。
+pub fn generate_pair(
+    source_shape: Shape,
+    target_shape: Shape,
+    r: usize,
+    seed: u64,
+    overlap: f64,
+) -> (Model, Model) {
+    assert!(0.0 <= overlap && overlap <= 1.0);
+    let source = generate(source_shape, r, seed);
+    let mut target = generate(target_shape, r, seed.wrapping_add(1));
+    let mut rng = seeded_rng(seed.wrapping_add(2));
+    for x in 0..r {
+        for y in 0..r {
+            for z in 0..r {
+                if source.matrix[x][y][z] == Voxel::Full && rng.gen::<f64>() < overlap {
+                    target.matrix[x][y][z] = Voxel::Full;
+                }
+            }
+        }
+    }
+    // 支えを残さなかった voxel は浮くので取り除く
+    remove_floating_voxels(&mut target);
+    (source, target)
+}
+
+#[cfg(test)]
+fn count_full(model: &Model) -> usize {
+    model
+        .matrix
+        .iter()
+        .flat_map(|m| m.iter())
+        .flat_map(|m| m.iter())
+        .filter(|&&v| v == Voxel::Full)
+        .count()
+}
+
+#[test]
+fn test_generate_valid_models() {
+    for shape in Shape::all() {
+        for &r in [5, 8, 20].iter() {
+            for seed in 0..20 {
+                let model = generate(shape, r, seed);
+                assert!(count_full(&model) > 0, "{:?} r={} seed={}", shape, r, seed);
+
+                // Model::new は範囲外の voxel があると落ちる
+                let encoded = model.encode();
+                let decoded = Model::new(&mut &encoded[..]).unwrap();
+                assert_eq!(decoded, model);
+
+                let mut grounded = model.clone();
+                assert_eq!(remove_floating_voxels(&mut grounded), 0);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_generate_is_deterministic() {
+    for shape in Shape::all() {
+        assert_eq!(generate(shape, 20, 42), generate(shape, 20, 42));
+        assert_eq!(Shape::from_name(shape.name()), Some(shape));
+    }
+    assert_eq!(Shape::from_name("unknown"), None);
+}
+
+#[test]
+fn test_generate_pair_overlap() {
+    let (source, target) = generate_pair(Shape::Blob, Shape::Tower, 12, 3, 1.0);
+    for x in 0..12 {
+        for y in 0..12 {
+            for z in 0..12 {
+                if source.matrix[x][y][z] == Voxel::Full {
+                    assert_eq!(target.matrix[x][y][z], Voxel::Full);
+                }
+            }
+        }
+    }
+
+    let (source, target) = generate_pair(Shape::Blob, Shape::Tower, 12, 3, 0.0);
+    assert_eq!(source, generate(Shape::Blob, 12, 3));
+    assert_eq!(target, generate(Shape::Tower, 12, 4));
+}
@@ -0,0 +1,568 @@
+#![allow(dead_code)]
+
+// voxel の集合を、一辺 MAX_SIDE 以下の重ならない直方体で覆う。
+// GFill で埋める直方体や GVoid で消す直方体を決めるのに使う。
+//
+// 覆うべき voxel のうち一番下 (y, x, z の順で最小) のものから、x, y, z を伸ばす順番 6 通りを試して、
+// 一番得になる直方体を選ぶ。最小の個数になるとは限らない。
+
+use common::*;
+use model::Model;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
+
+// GFill, GVoid の FCD の制限から決まる、直方体の一辺の最大の長さ
+pub const MAX_SIDE: i32 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cover {
+    // 覆うべき voxel だけを覆う
+    Exact,
+    // 覆わなくてよい voxel も覆ってよい。そのような voxel 1 つにつき weight だけ損をするとして、
+    // 覆うべき voxel がそれより多く増えるときだけ直方体を伸ばす。
+    Over(f64),
+}
+
+const AXIS_ORDERS: [[usize; 3]; 6] = [
+    [0, 2, 1],
+    [0, 1, 2],
+    [2, 0, 1],
+    [2, 1, 0],
+    [1, 0, 2],
+    [1, 2, 0],
+];
+
+struct Grid {
+    r: usize,
+    // 覆うべき voxel
+    target: Vec<bool>,
+    // 覆ってもよい voxel (target を含む)
+    allowed: Vec<bool>,
+    covered: Vec<bool>,
+    // true なら y 方向には伸ばさず、厚さ 1 の長方形で覆う
+    flat: bool,
+}
+
+impl Grid {
+    fn new<F, G>(r: usize, flat: bool, is_target: F, is_allowed: G) -> Grid
+    where
+        F: Fn(Position) -> bool,
+        G: Fn(Position) -> bool,
+    {
+        let mut target = vec![false; r * r * r];
+        let mut allowed = vec![false; r * r * r];
+        for x in 0..r {
+            for y in 0..r {
+                for z in 0..r {
+                    let p = Position::new(x as i32, y as i32, z as i32);
+                    let i = (x * r + y) * r + z;
+                    target[i] = is_target(p);
+                    allowed[i] = target[i] || is_allowed(p);
+                }
+            }
+        }
+        Grid {
+            r,
+            target,
+            allowed,
+            covered: vec![false; r * r * r],
+            flat,
+        }
+    }
+
+    fn index(&self, p: [i32; 3]) -> usize {
+        (p[0] as usize * self.r + p[1] as usize) * self.r + p[2] as usize
+    }
+
+    // lo から hi までの直方体の、覆うべき voxel と覆わなくてよい voxel の数。
+    // 覆えない voxel があれば None。
+    fn count(&self, lo: [i32; 3], hi: [i32; 3]) -> Option<(usize, usize)> {
+        let mut t = 0;
+        let mut e = 0;
+        for x in lo[0]..hi[0] + 1 {
+            for y in lo[1]..hi[1] + 1 {
+                for z in lo[2]..hi[2] + 1 {
+                    let i = self.index([x, y, z]);
+                    if self.covered[i] || !self.allowed[i] {
+                        return None;
+                    }
+                    if self.target[i] {
+                        t += 1;
+                    } else {
+                        e += 1;
+                    }
+                }
+            }
+        }
+        Some((t, e))
+    }
+
+    // seed から order の軸の順に、正の向きへ伸ばせるだけ伸ばす。
+    // flat なら Void の voxel を飛び越えられるように、1 枚では損でも何枚かまとめて得になれば伸ばす。
+    fn grow(&self, seed: [i32; 3], order: &[usize; 3], cover: Cover) -> ([i32; 3], [i32; 3]) {
+        let lookahead = if self.flat { MAX_SIDE } else { 1 };
+        let lo = seed;
+        let mut hi = seed;
+        for &axis in order.iter() {
+            if self.flat && axis == 1 {
+                continue;
+            }
+            'grow: loop {
+                let (mut t, mut e) = (0, 0);
+                for n in 1..lookahead + 1 {
+                    if hi[axis] - lo[axis] + 1 + n > MAX_SIDE || hi[axis] + n >= self.r as i32 {
+                        break;
+                    }
+                    let mut layer_lo = lo;
+                    let mut layer_hi = hi;
+                    layer_lo[axis] = hi[axis] + n;
+                    layer_hi[axis] = hi[axis] + n;
+                    match self.count(layer_lo, layer_hi) {
+                        Some((lt, le)) => {
+                            t += lt;
+                            e += le;
+                        }
+                        None => break,
+                    }
+                    let ok = match cover {
+                        Cover::Exact => e == 0,
+                        Cover::Over(weight) => t as f64 > weight * e as f64,
+                    };
+                    if ok {
+                        hi[axis] += n;
+                        continue 'grow;
+                    }
+                }
+                break;
+            }
+        }
+        (lo, hi)
+    }
+
+    fn cover(&mut self, cover: Cover) -> Vec<Region> {
+        let r = self.r as i32;
+        let mut boxes = vec![];
+        for y in 0..r {
+            for x in 0..r {
+                for z in 0..r {
+                    let seed = [x, y, z];
+                    let i = self.index(seed);
+                    if !self.target[i] || self.covered[i] {
+                        continue;
+                    }
+                    let mut best: Option<(f64, [i32; 3], [i32; 3])> = None;
+                    for order in AXIS_ORDERS.iter() {
+                        let (lo, hi) = self.grow(seed, order, cover);
+                        let (t, e) = self.count(lo, hi).unwrap();
+                        let score = match cover {
+                            Cover::Exact => t as f64,
+                            Cover::Over(weight) => t as f64 - weight * e as f64,
+                        };
+                        let better = match best {
+                            Some(b) => b.0 < score,
+                            None => true,
+                        };
+                        if better {
+                            best = Some((score, lo, hi));
+                        }
+                    }
+                    let (_, lo, hi) = best.unwrap();
+                    let region = Region(
+                        Position::new(lo[0], lo[1], lo[2]),
+                        Position::new(hi[0], hi[1], hi[2]),
+                    );
+                    for p in region.iter() {
+                        let i = self.index([p.x, p.y, p.z]);
+                        self.covered[i] = true;
+                    }
+                    boxes.push(region);
+                }
+            }
+        }
+        boxes
+    }
+}
+
+// model の Full の voxel を覆う直方体。Cover::Over なら Void の voxel も覆ってよい。
+pub fn decompose(model: &Model, cover: Cover) -> Vec<Region> {
+    let r = model.matrix.len();
+    let mut grid = Grid::new(r, false, |p| model.voxel_at(p) == Voxel::Full, |_| true);
+    grid.cover(cover)
+}
+
+// model で Full で other で Void の voxel を覆う直方体。
+// Cover::Over でも、覆ってよいのはどちらでも Void の voxel だけ。
+pub fn decompose_difference(model: &Model, other: &Model, cover: Cover) -> Vec<Region> {
+    let r = model.matrix.len();
+    let mut grid = Grid::new(
+        r,
+        false,
+        |p| model.voxel_at(p) == Voxel::Full && other.voxel_at(p) == Voxel::Void,
+        |p| model.voxel_at(p) == Voxel::Void && other.voxel_at(p) == Voxel::Void,
+    );
+    grid.cover(cover)
+}
+
+// model の Full の voxel を覆う、厚さ 1 の水平な長方形。
+// Cover::Over で覆ってよいのは、床の縁 (x, z が 0 か R - 1) を除く Void の voxel だけ。
+pub fn decompose_layers(model: &Model, cover: Cover) -> Vec<Region> {
+    let r = model.matrix.len();
+    let inner = |v: i32| 1 <= v && v < r as i32 - 1;
+    let mut grid = Grid::new(
+        r,
+        true,
+        |p| model.voxel_at(p) == Voxel::Full,
+        |p| inner(p.x) && inner(p.z),
+    );
+    grid.cover(cover)
+}
+
+// model で Full で other で Void の voxel だけを Full にしたモデル
+pub fn difference(model: &Model, other: &Model) -> Model {
+    let r = model.matrix.len();
+    let mut ret = Model::initial(r);
+    for x in 0..r {
+        for y in 0..r {
+            for z in 0..r {
+                if model.matrix[x][y][z] == Voxel::Full && other.matrix[x][y][z] == Voxel::Void {
+                    ret.matrix[x][y][z] = Voxel::Full;
+                }
+            }
+        }
+    }
+    ret
+}
+
+// region が床か model の Full の voxel に面で接していれば true
+pub fn is_grounded(model: &Model, region: &Region) -> bool {
+    let Region(lo, hi) = region.canonical();
+    if lo.y == 0 {
+        return true;
+    }
+    let r = model.matrix.len() as i32;
+    let outer = Region(lo - &Position::new(1, 1, 1), hi + &Position::new(1, 1, 1));
+    outer.iter().any(|p| {
+        let inside_count = [
+            lo.x <= p.x && p.x <= hi.x,
+            lo.y <= p.y && p.y <= hi.y,
+            lo.z <= p.z && p.z <= hi.z,
+        ].iter()
+            .filter(|&&b| b)
+            .count();
+        // 面で接している位置だけ見る
+        inside_count == 2
+            && 0 <= p.x && p.x < r && 0 <= p.y && p.y < r && 0 <= p.z && p.z < r
+            && model.voxel_at(p) == Voxel::Full
+    })
+}
+
+// starts のうち Full の voxel がどれも、Full の voxel を通って床までつながっていれば true
+pub fn is_connected_to_ground(model: &Model, starts: &[Position]) -> bool {
+    floating_cells(model, starts).is_empty()
+}
+
+// starts の Full の voxel からつながっている voxel のうち、床までつながっていないもの。
+// 下に向かう方を先に調べるので、床につながっている部分はほとんど starts のまわりしか見ない。
+pub fn floating_cells(model: &Model, starts: &[Position]) -> HashSet<Position> {
+    let r = model.matrix.len() as i32;
+    let mut grounded = HashSet::new();
+    let mut floating = HashSet::new();
+    for s in starts.iter() {
+        if model.voxel_at(*s) == Voxel::Void || grounded.contains(s) || floating.contains(s) {
+            continue;
+        }
+        let mut visited = HashSet::new();
+        let mut que = BinaryHeap::new();
+        visited.insert(*s);
+        que.push(Reverse((s.y, *s)));
+        let mut found = false;
+        while let Some(Reverse((_, p))) = que.pop() {
+            if p.y == 0 || grounded.contains(&p) {
+                found = true;
+                break;
+            }
+            for q in adjacent(p) {
+                if q.x < 0 || q.x >= r || q.y < 0 || q.y >= r || q.z < 0 || q.z >= r {
+                    continue;
+                }
+                if model.voxel_at(q) == Voxel::Void || visited.contains(&q) {
+                    continue;
+                }
+                visited.insert(q);
+                que.push(Reverse((q.y, q)));
+            }
+        }
+        if found {
+            grounded.extend(visited);
+        } else {
+            floating.extend(visited);
+        }
+    }
+    floating
+}
+
+// cells に面で接している、cells 以外の Full の voxel
+pub fn full_neighbors(model: &Model, cells: &[Position]) -> Vec<Position> {
+    let r = model.matrix.len() as i32;
+    let set = cells.iter().cloned().collect::<HashSet<_>>();
+    let mut ret = HashSet::new();
+    for p in cells.iter() {
+        for q in adjacent(*p) {
+            if q.x < 0 || q.x >= r || q.y < 0 || q.y >= r || q.z < 0 || q.z >= r {
+                continue;
+            }
+            if !set.contains(&q) && model.voxel_at(q) == Voxel::Full {
+                ret.insert(q);
+            }
+        }
+    }
+    ret.into_iter().collect()
+}
+
+// 一番上の面から cells のそれぞれまで、model の Full の voxel をなるべく通らずに行く経路の上にある Full の voxel。
+// これを取り除けば cells の全部に外から行ける。
+pub fn blocking_cells(model: &Model, cells: &[Position]) -> HashSet<Position> {
+    let r = model.matrix.len() as i32;
+    let mut dist = HashMap::new();
+    let mut parent = HashMap::new();
+    let mut que = VecDeque::new();
+    for x in 0..r {
+        for z in 0..r {
+            let p = Position::new(x, r - 1, z);
+            dist.insert(p, 0);
+            que.push_back(p);
+        }
+    }
+    // 0-1 BFS。Full の voxel に入るときだけコストが 1 かかる。
+    while let Some(p) = que.pop_front() {
+        let d = dist[&p];
+        for q in adjacent(p) {
+            if q.x < 0 || q.x >= r || q.y < 0 || q.y >= r || q.z < 0 || q.z >= r {
+                continue;
+            }
+            let cost = if model.voxel_at(q) == Voxel::Full { 1 } else { 0 };
+            if dist.get(&q).map_or(false, |&e| e <= d + cost) {
+                continue;
+            }
+            dist.insert(q, d + cost);
+            parent.insert(q, p);
+            if cost == 0 {
+                que.push_front(q);
+            } else {
+                que.push_back(q);
+            }
+        }
+    }
+    let mut ret = HashSet::new();
+    for c in cells.iter() {
+        let mut p = *c;
+        while dist[&p] > 0 {
+            if model.voxel_at(p) == Voxel::Full {
+                ret.insert(p);
+            }
+            p = parent[&p];
+        }
+    }
+    ret
+}
+
+// 一番長い辺で半分に分ける
+pub fn split(region: &Region) -> (Region, Region) {
+    let Region(lo, hi) = region.canonical();
+    let d = hi - &lo;
+    let mut mid_hi = hi;
+    let mut mid_lo = lo;
+    if d.x >= d.y && d.x >= d.z {
+        mid_hi.x = lo.x + d.x / 2;
+        mid_lo.x = mid_hi.x + 1;
+    } else if d.y >= d.z {
+        mid_hi.y = lo.y + d.y / 2;
+        mid_lo.y = mid_hi.y + 1;
+    } else {
+        mid_hi.z = lo.z + d.z / 2;
+        mid_lo.z = mid_hi.z + 1;
+    }
+    (Region(lo, mid_hi), Region(mid_lo, hi))
+}
+
+// 直方体の角。大きさが 1 の軸では角は 1 つにまとまる。
+pub fn corners(region: &Region) -> Vec<Position> {
+    let Region(lo, hi) = region.canonical();
+    let mut ret = vec![];
+    for &x in [lo.x, hi.x].iter() {
+        for &y in [lo.y, hi.y].iter() {
+            for &z in [lo.z, hi.z].iter() {
+                let p = Position::new(x, y, z);
+                if !ret.contains(&p) {
+                    ret.push(p);
+                }
+            }
+        }
+    }
+    ret
+}
+
+// 角 c の対角にある角
+pub fn opposite(region: &Region, c: &Position) -> Position {
+    let Region(lo, hi) = region.canonical();
+    let flip = |v: i32, lo: i32, hi: i32| if v == lo { hi } else { lo };
+    Position::new(flip(c.x, lo.x, hi.x), flip(c.y, lo.y, hi.y), flip(c.z, lo.z, hi.z))
+}
+
+// base に boxes を順に埋めていくとき、埋める直方体が常に床か Full の voxel に接するように並べる。
+// 接している直方体のうち、一番下にあるもの (同じなら boxes で先にあるもの) から選ぶ。
+// どうしても接しない直方体は、元の順番のまま最後に置く。
+// GVoid で消すときは、この逆順に消せば残りはいつも接地している。
+pub fn order_grounded(base: &Model, boxes: &[Region]) -> Vec<Region> {
+    let mut current = base.clone();
+    let mut rest = boxes.to_vec();
+    let mut ret = vec![];
+    loop {
+        let next = (0..rest.len())
+            .filter(|&i| is_grounded(&current, &rest[i]))
+            .min_by_key(|&i| (rest[i].canonical().0.y, i));
+        let i = match next {
+            Some(i) => i,
+            None => break,
+        };
+        let region = rest.remove(i);
+        for p in region.iter() {
+            current.set_voxel_at(p, Voxel::Full);
+        }
+        ret.push(region);
+    }
+    ret.extend(rest);
+    ret
+}
+
+#[cfg(test)]
+fn fill(model: &mut Model, region: &Region) {
+    for p in region.iter() {
+        model.set_voxel_at(p, Voxel::Full);
+    }
+}
+
+// boxes が重ならず、一辺が MAX_SIDE 以下で、must を全部覆い、may 以外は覆わないことを確かめる
+#[cfg(test)]
+fn check_cover<F, G>(r: usize, boxes: &[Region], must: F, may: G)
+where
+    F: Fn(Position) -> bool,
+    G: Fn(Position) -> bool,
+{
+    let mut covered = Model::initial(r);
+    for b in boxes.iter() {
+        let Region(lo, hi) = b.canonical();
+        assert!(hi.x - lo.x < MAX_SIDE && hi.y - lo.y < MAX_SIDE && hi.z - lo.z < MAX_SIDE);
+        for p in b.iter() {
+            assert_eq!(covered.voxel_at(p), Voxel::Void, "{:?}", p);
+            assert!(may(p), "{:?}", p);
+            covered.set_voxel_at(p, Voxel::Full);
+        }
+    }
+    for p in Region(Position::zero(), Position::new(r as i32 - 1, r as i32 - 1, r as i32 - 1)).iter() {
+        if must(p) {
+            assert_eq!(covered.voxel_at(p), Voxel::Full, "{:?}", p);
+        }
+    }
+}
+
+#[test]
+fn test_decompose() {
+    let mut model = Model::initial(40);
+    fill(&mut model, &Region(Position::new(1, 0, 1), Position::new(35, 3, 2)));
+    model.set_voxel_at(Position::new(1, 4, 1), Voxel::Full);
+    let boxes = decompose(&model, Cover::Exact);
+    // x 方向は 30 で切れる
+    assert_eq!(
+        boxes,
+        vec![
+            Region(Position::new(1, 0, 1), Position::new(30, 3, 2)),
+            Region(Position::new(31, 0, 1), Position::new(35, 3, 2)),
+            Region(Position::new(1, 4, 1), Position::new(1, 4, 1)),
+        ]
+    );
+
+    // 真ん中に穴のある板は、Exact なら分かれるが、Over なら 1 つで覆える
+    let mut model = Model::initial(10);
+    fill(&mut model, &Region(Position::new(1, 0, 1), Position::new(5, 0, 5)));
+    model.set_voxel_at(Position::new(3, 0, 3), Voxel::Void);
+    let exact = decompose(&model, Cover::Exact);
+    assert!(exact.len() > 1);
+    check_cover(10, &exact, |p| model.voxel_at(p) == Voxel::Full, |p| {
+        model.voxel_at(p) == Voxel::Full
+    });
+    let over = decompose(&model, Cover::Over(1.0));
+    assert_eq!(over, vec![Region(Position::new(1, 0, 1), Position::new(5, 0, 5))]);
+    // 損が大きければ Exact と同じになる
+    assert_eq!(decompose(&model, Cover::Over(100.0)), exact);
+}
+
+#[test]
+fn test_decompose_generated() {
+    use generator::*;
+    for shape in Shape::all() {
+        let model = generate(shape, 20, 0);
+        let full = |p: Position| model.voxel_at(p) == Voxel::Full;
+        check_cover(20, &decompose(&model, Cover::Exact), full, full);
+        check_cover(20, &decompose(&model, Cover::Over(0.5)), full, |_| true);
+        let layers = decompose_layers(&model, Cover::Over(0.5));
+        assert!(layers.iter().all(|b| b.canonical().0.y == b.canonical().1.y));
+        check_cover(20, &layers, full, |p| 0 < p.x && p.x < 19 && 0 < p.z && p.z < 19);
+
+        let other = generate(Shape::Blob, 20, 1);
+        let must = |p: Position| full(p) && other.voxel_at(p) == Voxel::Void;
+        let may = |p: Position| other.voxel_at(p) == Voxel::Void;
+        check_cover(20, &decompose_difference(&model, &other, Cover::Over(0.5)), must, may);
+        assert_eq!(
+            decompose_difference(&model, &other, Cover::Exact),
+            decompose(&difference(&model, &other), Cover::Exact)
+        );
+    }
+}
+
+#[test]
+fn test_split() {
+    let region = Region(Position::new(1, 0, 1), Position::new(30, 3, 2));
+    let (a, b) = split(&region);
+    assert_eq!(a, Region(Position::new(1, 0, 1), Position::new(15, 3, 2)));
+    assert_eq!(b, Region(Position::new(16, 0, 1), Position::new(30, 3, 2)));
+}
+
+#[test]
+fn test_floating_cells() {
+    let mut model = Model::initial(10);
+    fill(&mut model, &Region(Position::new(1, 0, 1), Position::new(1, 3, 1)));
+    fill(&mut model, &Region(Position::new(2, 3, 1), Position::new(3, 3, 1)));
+    fill(&mut model, &Region(Position::new(5, 2, 1), Position::new(5, 3, 1)));
+    assert!(is_connected_to_ground(&model, &[Position::new(3, 3, 1)]));
+    let floating = floating_cells(&model, &[Position::new(3, 3, 1), Position::new(5, 2, 1)]);
+    assert_eq!(floating.len(), 2);
+    assert!(floating.contains(&Position::new(5, 3, 1)));
+    // Void の位置は調べない
+    assert!(is_connected_to_ground(&model, &[Position::new(4, 3, 1)]));
+}
+
+#[test]
+fn test_order_grounded() {
+    let base = Model::initial(10);
+    // 上に乗っている直方体が先にあっても、下から並べる
+    let boxes = vec![
+        Region(Position::new(1, 2, 1), Position::new(3, 2, 1)),
+        Region(Position::new(3, 1, 1), Position::new(3, 1, 1)),
+        Region(Position::new(1, 0, 1), Position::new(3, 0, 1)),
+        // 浮いている
+        Region(Position::new(6, 5, 6), Position::new(6, 5, 6)),
+    ];
+    assert_eq!(
+        order_grounded(&base, &boxes),
+        vec![boxes[2], boxes[1], boxes[0], boxes[3]]
+    );
+    let mut base = Model::initial(10);
+    base.set_voxel_at(Position::new(6, 4, 6), Voxel::Full);
+    assert_eq!(order_grounded(&base, &boxes)[0], boxes[2]);
+    assert!(is_grounded(&base, &boxes[3]));
+    assert!(!is_grounded(&base, &boxes[0]));
+}
+
@@ -0,0 +1,462 @@
+#![allow(dead_code)]
+
+// builder に登録されている AI を生成したモデルで最後まで動かし、
+// シミュレータが trace を受け入れるかを確かめるためのテストハーネス。
+// 失敗したモデルは小さくしてから、GOLD_FIXTURE_DIR があればそこに .mdl として保存する。
+// 保存したものを fixtures/ に入れてコミットしておくと、test_replay_fixtures が毎回解き直す。
+
+use ai::builder::*;
+use ai::config::{Config, Param};
+use common::*;
+use generator::*;
+use import::remove_floating_voxels;
+use model::{write_model_file, Model};
+use state::State;
+use std::env;
+use std::error::*;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+// Nanobot::initial が持っている bot の総数
+pub const MAX_BOTS: usize = 40;
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Task {
+    Assemble,
+    Disassemble,
+    Reassemble,
+}
+
+impl Task {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Task::Assemble => "assemble",
+            Task::Disassemble => "disassemble",
+            Task::Reassemble => "reassemble",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Task> {
+        [Task::Assemble, Task::Disassemble, Task::Reassemble]
+            .iter()
+            .cloned()
+            .find(|t| t.name() == name)
+    }
+
+    pub fn ai_names(&self) -> &'static [&'static str] {
+        match *self {
+            Task::Assemble => ASSEMBLER_NAMES,
+            Task::Disassemble => DISASSEMBLER_NAMES,
+            Task::Reassemble => REASSEMBLER_NAMES,
+        }
+    }
+
+    // name の AI が読む、チューニングできるパラメータ
+    pub fn params(&self, name: &str, config: &Config) -> Vec<Param> {
+        match *self {
+            Task::Assemble => assembler_params(name, config),
+            Task::Disassemble => disassembler_params(name, config),
+            Task::Reassemble => reassembler_params(name, config),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HarnessError {
+    message: String,
+}
+
+impl HarnessError {
+    pub fn new(message: String) -> HarnessError {
+        HarnessError { message }
+    }
+}
+
+impl fmt::Display for HarnessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HarnessError: {}", self.message)
+    }
+}
+
+impl Error for HarnessError {
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+fn harness_error(message: String) -> Box<Error> {
+    Box::new(HarnessError::new(message))
+}
+
+pub fn panic_message(payload: Box<::std::any::Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        return s.to_string();
+    }
+    if let Some(s) = payload.downcast_ref::<String>() {
+        return s.clone();
+    }
+    String::from("unknown panic")
+}
+
+// trace を source から最後まで実行して、target と一致するかを調べる。
+// 途中で bot の数が MAX_BOTS を超えたり、シミュレータが panic した場合もエラーにする。
+pub fn check_trace(source: &Model, target: &Model, trace: &[Command]) -> Result<i64, Box<Error>> {
+    let mut state = State::initial_with_model(source);
+    let mut offset = 0;
+    let mut step = 0;
+    while offset < trace.len() {
+        let bot_cnt = state.get_bot_count();
+        if bot_cnt == 0 {
+            return Err(harness_error(format!(
+                "commands remain after halt: step={}",
+                step
+            )));
+        }
+        if offset + bot_cnt > trace.len() {
+            return Err(harness_error(format!(
+                "trace ends in the middle of step {}",
+                step
+            )));
+        }
+        let commands = &trace[offset..offset + bot_cnt];
+        let result = {
+            let state = &mut state;
+            panic::catch_unwind(panic::AssertUnwindSafe(move || {
+                state.update_time_step(commands)
+            }))
+        };
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                return Err(harness_error(format!("step {}: {}", step, err)));
+            }
+            Err(payload) => {
+                return Err(harness_error(format!(
+                    "step {}: simulator panicked: {}",
+                    step,
+                    panic_message(payload)
+                )));
+            }
+        }
+        if state.get_bot_count() > MAX_BOTS {
+            return Err(harness_error(format!(
+                "step {}: too many bots: {}",
+                step,
+                state.get_bot_count()
+            )));
+        }
+        offset += bot_cnt;
+        step += 1;
+    }
+    state.end_check(target)?;
+    Ok(state.get_energy())
+}
+
+// NaiveReassembleAI などが使う AI が指定されていなければ default を使う
+pub fn harness_config() -> Config {
+    let mut config = Config::new();
+    if config.assembler.is_empty() {
+        config.assembler = String::from("default");
+    }
+    if config.disassembler.is_empty() {
+        config.disassembler = String::from("default");
+    }
+    config
+}
+
+// name の AI に task を解かせて、その trace を検査する
+pub fn run_case(task: Task, name: &str, source: &Model, target: &Model) -> Result<i64, Box<Error>> {
+    run_case_with_config(task, name, &harness_config(), source, target)
+}
+
+// task を name の AI で解いた trace。検査はしない。
+pub fn build_trace(
+    task: Task,
+    name: &str,
+    config: &Config,
+    source: &Model,
+    target: &Model,
+) -> Vec<Command> {
+    let name = name.to_string();
+    match task {
+        Task::Assemble => build_assembler(&name, config, target).assemble(target),
+        Task::Disassemble => build_disassembler(&name, config, source).disassemble(source),
+        Task::Reassemble => {
+            build_reassembler(&name, config, source, target).reassemble(source, target)
+        }
+    }
+}
+
+// run_case と同じだが、AI に渡す config を指定する
+pub fn run_case_with_config(
+    task: Task,
+    name: &str,
+    config: &Config,
+    source: &Model,
+    target: &Model,
+) -> Result<i64, Box<Error>> {
+    let config = config.clone();
+    let name = name.to_string();
+    let result = panic::catch_unwind(|| build_trace(task, &name, &config, source, target));
+    match result {
+        Ok(trace) => check_trace(source, target, &trace),
+        Err(payload) => Err(harness_error(format!(
+            "AI panicked: {}",
+            panic_message(payload)
+        ))),
+    }
+}
+
+fn full_voxels(model: &Model) -> Vec<Position> {
+    let r = model.matrix.len();
+    let mut voxels = vec![];
+    for y in (0..r).rev() {
+        for x in 0..r {
+            for z in 0..r {
+                if model.matrix[x][y][z] == Voxel::Full {
+                    voxels.push(Position::new(x as i32, y as i32, z as i32));
+                }
+            }
+        }
+    }
+    voxels
+}
+
+// fails が true を返す間、voxel を 1 つずつ消して (source, target) を小さくする。
+// 消した結果浮いた voxel も一緒に消すので、どちらのモデルも常に床につながっている。
+pub fn shrink<F>(source: &Model, target: &Model, fails: F) -> (Model, Model)
+where
+    F: Fn(&Model, &Model) -> bool,
+{
+    let mut models = [source.clone(), target.clone()];
+    loop {
+        let mut shrunk = false;
+        for i in 0..2 {
+            // 上の方の voxel から順に消してみる
+            for p in full_voxels(&models[i]) {
+                if models[i].voxel_at(p) == Voxel::Void {
+                    continue;
+                }
+                let mut candidate = models.clone();
+                candidate[i].set_voxel_at(p, Voxel::Void);
+                remove_floating_voxels(&mut candidate[i]);
+                if fails(&candidate[0], &candidate[1]) {
+                    models = candidate;
+                    shrunk = true;
+                }
+            }
+        }
+        if !shrunk {
+            break;
+        }
+    }
+    (models[0].clone(), models[1].clone())
+}
+
+// 失敗したモデルの保存先。GOLD_FIXTURE_DIR がなければ保存しない。
+pub fn fixture_dir() -> Option<PathBuf> {
+    env::var("GOLD_FIXTURE_DIR").ok().map(PathBuf::from)
+}
+
+// {task}_{name}_{case}.txt の 1 行目に task と AI の名前、2 行目にエラーを書き、モデルを .mdl で並べて置く
+pub fn save_fixture(
+    dir: &Path,
+    task: Task,
+    name: &str,
+    case: &str,
+    source: &Model,
+    target: &Model,
+    message: &str,
+) -> Result<(), Box<Error>> {
+    fs::create_dir_all(dir)?;
+    let prefix = format!("{}_{}_{}", task.name(), name, case);
+    let text = format!("{} {}\n{}\n", task.name(), name, message);
+    fs::write(dir.join(format!("{}.txt", prefix)), text)?;
+    if task != Task::Assemble {
+        write_model_file(&dir.join(format!("{}_src.mdl", prefix)), source)?;
+    }
+    if task != Task::Disassemble {
+        write_model_file(&dir.join(format!("{}_tgt.mdl", prefix)), target)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub task: Task,
+    pub name: String,
+    pub case: String,
+    pub message: String,
+}
+
+// 生成したモデルで name の AI を動かし、失敗したものを縮小して保存する
+pub fn check_ai(task: Task, name: &str, resolutions: &[usize], seeds: u64) -> Vec<Failure> {
+    let mut failures = vec![];
+    for &r in resolutions.iter() {
+        let empty = Model::initial(r);
+        for shape in Shape::all() {
+            for seed in 0..seeds {
+                let (source, target) = match task {
+                    Task::Assemble => (empty.clone(), generate(shape, r, seed)),
+                    Task::Disassemble => (generate(shape, r, seed), empty.clone()),
+                    Task::Reassemble => generate_pair(shape, shape, r, seed, 0.5),
+                };
+                let message = match run_case(task, name, &source, &target) {
+                    Ok(_) => continue,
+                    Err(err) => err.to_string(),
+                };
+                let case = format!("{}_{}_{}", shape.name(), r, seed);
+                let (source, target) = shrink(&source, &target, |s, t| {
+                    run_case(task, name, s, t).is_err()
+                });
+                if let Some(dir) = fixture_dir() {
+                    let _ = save_fixture(&dir, task, name, &case, &source, &target, &message);
+                }
+                failures.push(Failure {
+                    task,
+                    name: name.to_string(),
+                    case,
+                    message,
+                });
+            }
+        }
+    }
+    failures
+}
+
+// 登録されていても失敗することがわかっている AI。
+// gvoid と gvoid_2d は幅が 1 の部分があると panic する。bruteforce と reverse_gvoid, reverse_gvoid_2d はそれを使うので失敗する。
+#[cfg(test)]
+const KNOWN_FAILURES: &[(Task, &str)] = &[
+    (Task::Disassemble, "gvoid"),
+    (Task::Disassemble, "gvoid_2d"),
+    (Task::Assemble, "reverse_gvoid"),
+    (Task::Assemble, "reverse_gvoid_2d"),
+    (Task::Reassemble, "bruteforce"),
+];
+
+// 登録されている AI のうち KNOWN_FAILURES 以外を全部試して、失敗したものを返す。
+// 小さいモデルは seed を変えて 2 つずつ、大きいモデルは 1 つずつ試す。
+#[cfg(test)]
+fn check_registered_ais(task: Task) -> Vec<Failure> {
+    let mut failures = vec![];
+    for name in task.ai_names() {
+        if KNOWN_FAILURES.contains(&(task, *name)) {
+            continue;
+        }
+        failures.extend(check_ai(task, name, &[5, 8], 2));
+        failures.extend(check_ai(task, name, &[12], 1));
+    }
+    for f in failures.iter() {
+        eprintln!("{} {} {}: {}", f.task.name(), f.name, f.case, f.message);
+    }
+    failures
+}
+
+// save_fixture で保存した失敗を dir から読んで、もう一度解かせる。
+// 返り値は (txt のパス, エラー) で、今も失敗するものだけを返す。
+pub fn replay_fixtures(dir: &Path) -> Result<Vec<(PathBuf, String)>, Box<Error>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.extension().map_or(false, |e| e == "txt"));
+    paths.sort();
+    let mut failures = vec![];
+    for path in paths {
+        let text = fs::read_to_string(&path)?;
+        let header = text.lines().next().unwrap_or("").split(' ').collect::<Vec<_>>();
+        let task = match header.first().and_then(|t| Task::from_name(t)) {
+            Some(task) if header.len() == 2 => task,
+            _ => return Err(harness_error(format!("invalid fixture: {}", path.display()))),
+        };
+        let prefix = path.with_extension("");
+        let read = |suffix: &str| -> Result<Option<Model>, Box<Error>> {
+            let path = PathBuf::from(format!("{}_{}.mdl", prefix.display(), suffix));
+            if !path.exists() {
+                return Ok(None);
+            }
+            Ok(Some(Model::new(&mut BufReader::new(File::open(path)?))?))
+        };
+        let (source, target) = match (read("src")?, read("tgt")?) {
+            (Some(source), Some(target)) => (source, target),
+            (Some(source), None) => {
+                let r = source.matrix.len();
+                (source, Model::initial(r))
+            }
+            (None, Some(target)) => (Model::initial(target.matrix.len()), target),
+            (None, None) => return Err(harness_error(format!("no model: {}", path.display()))),
+        };
+        if let Err(err) = run_case(task, header[1], &source, &target) {
+            failures.push((path, err.to_string()));
+        }
+    }
+    Ok(failures)
+}
+
+#[test]
+fn test_check_trace() {
+    let source = Model::initial(3);
+    let target = Model::initial(3);
+    assert!(check_trace(&source, &target, &[Command::Halt]).is_ok());
+    assert!(check_trace(&source, &target, &[Command::Wait]).is_err());
+    assert!(check_trace(&source, &target, &[Command::Halt, Command::Halt]).is_err());
+    // 何もしない trace では target は作れない
+    let mut target = Model::initial(3);
+    target.matrix[1][0][1] = Voxel::Full;
+    assert!(check_trace(&source, &target, &[Command::Halt]).is_err());
+}
+
+#[test]
+fn test_shrink() {
+    let target = generate(Shape::Blob, 8, 0);
+    // (x, z) = (1, 1) の柱が 2 段以上あると失敗する、という仮想の AI
+    let fails = |_: &Model, t: &Model| {
+        t.matrix[1][0][1] == Voxel::Full && t.matrix[1][1][1] == Voxel::Full
+    };
+    let mut target = target;
+    target.matrix[1][0][1] = Voxel::Full;
+    target.matrix[1][1][1] = Voxel::Full;
+    let (_, shrunk) = shrink(&Model::initial(8), &target, fails);
+    let mut expected = Model::initial(8);
+    expected.matrix[1][0][1] = Voxel::Full;
+    expected.matrix[1][1][1] = Voxel::Full;
+    assert_eq!(shrunk, expected);
+}
+
+#[test]
+fn test_replay_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+    let failures = replay_fixtures(&dir).unwrap();
+    for &(ref path, ref message) in failures.iter() {
+        eprintln!("{}: {}", path.display(), message);
+    }
+    assert!(failures.is_empty());
+}
+
+#[test]
+fn test_registered_assemblers() {
+    assert!(check_registered_ais(Task::Assemble).is_empty());
+}
+
+#[test]
+fn test_registered_disassemblers() {
+    assert!(check_registered_ais(Task::Disassemble).is_empty());
+}
+
+#[test]
+fn test_registered_reassemblers() {
+    assert!(check_registered_ais(Task::Reassemble).is_empty());
+}
+
+#[test]
+#[ignore]
+fn test_registered_ais_known_failures() {
+    let mut failures = vec![];
+    for &(task, name) in KNOWN_FAILURES.iter() {
+        failures.extend(check_ai(task, name, &[5, 8], 2));
+    }
+    assert!(failures.is_empty());
+}
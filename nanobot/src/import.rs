@@ -0,0 +1,325 @@
+#![allow(dead_code)]
+
+use common::*;
+use model::Model;
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+use std::error::*;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+// .mdl が扱える最大の解像度
+pub const MAX_RESOLUTION: usize = 250;
+
+// 取り込み時に捨てた voxel の数
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default)]
+pub struct ImportReport {
+    pub resolution: usize,
+    pub imported: usize,
+    pub clipped: usize,
+    pub floating: usize,
+}
+
+#[derive(Debug)]
+pub struct ImportError {
+    message: String,
+}
+
+impl ImportError {
+    pub fn new(message: String) -> ImportError {
+        ImportError { message }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ImportError: {}", self.message)
+    }
+}
+
+impl Error for ImportError {
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+fn parse_error(message: &str) -> Box<Error> {
+    Box::new(ImportError::new(message.to_string()))
+}
+
+fn read_u32(input: &[u8], offset: usize) -> Result<u32, Box<Error>> {
+    if offset + 4 > input.len() {
+        return Err(parse_error("unexpected end of vox data"));
+    }
+    Ok(u32::from(input[offset])
+        | u32::from(input[offset + 1]) << 8
+        | u32::from(input[offset + 2]) << 16
+        | u32::from(input[offset + 3]) << 24)
+}
+
+// MagicaVoxel の .vox から最初のモデルの voxel 座標を読み出す。
+// z が上向きなので (x, y, z) を (x, z, y) に入れ替えて返す。
+pub fn decode_vox(input: &[u8]) -> Result<Vec<Position>, Box<Error>> {
+    if input.len() < 8 || &input[0..4] != b"VOX " {
+        return Err(parse_error("not a vox file"));
+    }
+    let mut offset = 8;
+    let mut voxels = None;
+    while offset + 12 <= input.len() {
+        let id = &input[offset..offset + 4];
+        let content = read_u32(input, offset + 4)? as usize;
+        let children = read_u32(input, offset + 8)? as usize;
+        offset += 12;
+        if id == b"MAIN" {
+            // MAIN の子チャンクをそのまま読み進める
+            offset += content;
+            continue;
+        }
+        if offset + content > input.len() {
+            return Err(parse_error("unexpected end of vox data"));
+        }
+        if id == b"XYZI" && voxels.is_none() {
+            let n = read_u32(input, offset)? as usize;
+            if 4 + n * 4 > content {
+                return Err(parse_error("broken XYZI chunk"));
+            }
+            let mut v = Vec::with_capacity(n);
+            for i in 0..n {
+                let p = offset + 4 + i * 4;
+                v.push(Position::new(
+                    input[p] as i32,
+                    input[p + 2] as i32,
+                    input[p + 1] as i32,
+                ));
+            }
+            voxels = Some(v);
+        }
+        offset += content + children;
+    }
+    voxels.ok_or_else(|| parse_error("XYZI chunk not found"))
+}
+
+// binvox (https://www.patrickmin.com/binvox/binvox.html) の voxel 座標を読み出す。
+// binvox は y が上向きで、y, z, x の順に変化する。
+pub fn decode_binvox(input: &[u8]) -> Result<Vec<Position>, Box<Error>> {
+    let mut offset = 0;
+    let mut dims = None;
+    loop {
+        let end = match input[offset..].iter().position(|&b| b == b'\n') {
+            Some(i) => offset + i,
+            None => return Err(parse_error("binvox header is not terminated")),
+        };
+        let line = String::from_utf8_lossy(&input[offset..end]).trim().to_string();
+        offset = end + 1;
+        if line.starts_with("#binvox") || line.starts_with("translate") || line.starts_with("scale") {
+            continue;
+        }
+        if line.starts_with("dim") {
+            let v: Vec<usize> = line
+                .split_whitespace()
+                .skip(1)
+                .map(|s| s.parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()?;
+            if v.len() != 3 {
+                return Err(parse_error("invalid binvox dim"));
+            }
+            dims = Some((v[0], v[1], v[2]));
+            continue;
+        }
+        if line == "data" {
+            break;
+        }
+        return Err(parse_error("unknown binvox header"));
+    }
+    let (dx, dy, dz) = dims.ok_or_else(|| parse_error("binvox dim not found"))?;
+
+    let mut voxels = vec![];
+    let mut index = 0;
+    let total = dx * dy * dz;
+    while offset + 1 < input.len() && index < total {
+        let value = input[offset];
+        let count = input[offset + 1] as usize;
+        offset += 2;
+        if value != 0 {
+            for i in index..min(index + count, total) {
+                let x = i / (dz * dy);
+                let z = (i / dy) % dz;
+                let y = i % dy;
+                voxels.push(Position::new(x as i32, y as i32, z as i32));
+            }
+        }
+        index += count;
+    }
+    Ok(voxels)
+}
+
+// 任意の voxel の集合から、仕様を満たす Model を作る。
+// bounding box を (1, 0, 1) に寄せて、R-1 の境界からはみ出す部分は切り落とし、
+// 床につながっていない voxel は取り除く。
+// resolution が None の場合は入るだけの最小の R を選ぶ。
+pub fn build_model(
+    voxels: &[Position],
+    resolution: Option<usize>,
+) -> Result<(Model, ImportReport), Box<Error>> {
+    if voxels.is_empty() {
+        return Err(parse_error("no voxels"));
+    }
+    let min_x = voxels.iter().map(|p| p.x).min().unwrap();
+    let min_y = voxels.iter().map(|p| p.y).min().unwrap();
+    let min_z = voxels.iter().map(|p| p.z).min().unwrap();
+    let max_x = voxels.iter().map(|p| p.x).max().unwrap();
+    let max_y = voxels.iter().map(|p| p.y).max().unwrap();
+    let max_z = voxels.iter().map(|p| p.z).max().unwrap();
+
+    let needed = max(
+        max((max_x - min_x) as usize + 3, (max_z - min_z) as usize + 3),
+        (max_y - min_y) as usize + 2,
+    );
+    let r = match resolution {
+        Some(r) => r,
+        None => min(needed, MAX_RESOLUTION),
+    };
+    if r < 3 || r > MAX_RESOLUTION {
+        return Err(parse_error(&format!("invalid resolution: {}", r)));
+    }
+
+    let mut report = ImportReport {
+        resolution: r,
+        ..Default::default()
+    };
+    let mut model = Model::initial(r);
+    let ri = r as i32;
+    for v in voxels.iter() {
+        let p = Position::new(v.x - min_x + 1, v.y - min_y, v.z - min_z + 1);
+        if p.x > ri - 2 || p.y > ri - 2 || p.z > ri - 2 {
+            report.clipped += 1;
+            continue;
+        }
+        if model.voxel_at(p) == Voxel::Void {
+            model.set_voxel_at(p, Voxel::Full);
+            report.imported += 1;
+        }
+    }
+
+    report.floating = remove_floating_voxels(&mut model);
+    report.imported -= report.floating;
+    if report.imported == 0 {
+        return Err(parse_error("no grounded voxels"));
+    }
+    Ok((model, report))
+}
+
+// 床につながっていない voxel を Void にして、その数を返す
+pub fn remove_floating_voxels(model: &mut Model) -> usize {
+    let r = model.matrix.len();
+    let mut grounded = vec![vec![vec![false; r]; r]; r];
+    let mut que = VecDeque::new();
+    for x in 0..r {
+        for z in 0..r {
+            if model.matrix[x][0][z] == Voxel::Full {
+                grounded[x][0][z] = true;
+                que.push_back(Position::new(x as i32, 0, z as i32));
+            }
+        }
+    }
+    while let Some(p) = que.pop_front() {
+        for n in adjacent(p) {
+            if n.x < 0 || n.y < 0 || n.z < 0 || n.x >= r as i32 || n.y >= r as i32 || n.z >= r as i32
+            {
+                continue;
+            }
+            let (x, y, z) = (n.x as usize, n.y as usize, n.z as usize);
+            if grounded[x][y][z] || model.matrix[x][y][z] == Voxel::Void {
+                continue;
+            }
+            grounded[x][y][z] = true;
+            que.push_back(n);
+        }
+    }
+
+    let mut removed = 0;
+    for x in 0..r {
+        for y in 0..r {
+            for z in 0..r {
+                if model.matrix[x][y][z] == Voxel::Full && !grounded[x][y][z] {
+                    model.matrix[x][y][z] = Voxel::Void;
+                    removed += 1;
+                }
+            }
+        }
+    }
+    removed
+}
+
+pub fn read_vox_file(
+    path: &Path,
+    resolution: Option<usize>,
+) -> Result<(Model, ImportReport), Box<Error>> {
+    let mut f = fs::File::open(path)?;
+    let mut buffer = vec![];
+    f.read_to_end(&mut buffer)?;
+    build_model(&decode_vox(&buffer)?, resolution)
+}
+
+pub fn read_binvox_file(
+    path: &Path,
+    resolution: Option<usize>,
+) -> Result<(Model, ImportReport), Box<Error>> {
+    let mut f = fs::File::open(path)?;
+    let mut buffer = vec![];
+    f.read_to_end(&mut buffer)?;
+    build_model(&decode_binvox(&buffer)?, resolution)
+}
+
+#[test]
+fn test_decode_vox_roundtrip() {
+    use export::encode_vox;
+
+    let mut model = Model::initial(4);
+    model.matrix[1][0][1] = Voxel::Full;
+    model.matrix[1][1][1] = Voxel::Full;
+    model.matrix[2][0][2] = Voxel::Full;
+    let voxels = decode_vox(&encode_vox(&model)).unwrap();
+    assert_eq!(voxels.len(), 3);
+
+    let (imported, report) = build_model(&voxels, Some(4)).unwrap();
+    assert_eq!(imported, model);
+    assert_eq!(report.imported, 3);
+    assert_eq!(report.clipped, 0);
+    assert_eq!(report.floating, 0);
+}
+
+#[test]
+fn test_decode_binvox() {
+    let mut bytes = b"#binvox 1\ndim 2 2 2\ntranslate 0 0 0\nscale 1\ndata\n".to_vec();
+    // (x, z, y) = (0, 0, 0), (0, 0, 1) が Full
+    bytes.extend_from_slice(&[1, 2, 0, 6]);
+    let voxels = decode_binvox(&bytes).unwrap();
+    assert_eq!(voxels, vec![Position::new(0, 0, 0), Position::new(0, 1, 0)]);
+
+    let (model, report) = build_model(&voxels, None).unwrap();
+    assert_eq!(report.resolution, 3);
+    assert_eq!(model.matrix[1][0][1], Voxel::Full);
+    assert_eq!(model.matrix[1][1][1], Voxel::Full);
+}
+
+#[test]
+fn test_build_model_clips_and_removes_floating_voxels() {
+    let voxels = vec![
+        Position::new(10, 5, 10),
+        Position::new(10, 6, 10),
+        // 床につながっていない
+        Position::new(12, 7, 10),
+        // R=5 では入りきらない
+        Position::new(14, 5, 10),
+    ];
+    let (model, report) = build_model(&voxels, Some(5)).unwrap();
+    assert_eq!(report.clipped, 1);
+    assert_eq!(report.floating, 1);
+    assert_eq!(report.imported, 2);
+    assert_eq!(model.matrix[1][0][1], Voxel::Full);
+    assert_eq!(model.matrix[1][1][1], Voxel::Full);
+    assert_eq!(model.matrix[3][2][1], Voxel::Void);
+}
@@ -1,5 +1,16 @@
 pub mod ai;
+pub mod anytime;
 pub mod common;
+pub mod export;
+pub mod fuzz;
+pub mod generator;
+pub mod geometry;
+pub mod harness;
+pub mod import;
 pub mod model;
+pub mod report;
 pub mod state;
+pub mod symmetry;
+pub mod timeline;
+pub mod tuning;
 pub mod union_find;
@@ -1,7 +1,13 @@
 mod ai;
+mod anytime;
 mod common;
+mod export;
+mod generator;
+mod geometry;
+mod import;
 mod model;
 mod state;
+mod symmetry;
 mod union_find;
 
 use std::env;
@@ -9,11 +15,15 @@ use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use ai::builder::*;
-use ai::config::Config;
+use ai::config::{Config, Param};
+use anytime::*;
 use common::write_trace_file;
-use model::Model;
+use generator::*;
+use model::{write_model_file, Model};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -27,6 +37,7 @@ fn main() {
         "assemble" => assemble(&args),
         "disassemble" => disassemble(&args),
         "reassemble" => reassemble(&args),
+        "gen" => gen(&args),
         _ => {
             usage(&args);
             process::exit(1);
@@ -42,9 +53,18 @@ fn assemble(args: &Vec<String>) {
     let trace_output_path = Path::new(&args[3]);
     let config = Config::new();
     let name = env::var("GOLD_AI").expect("failed to get AI from ENV");
-    let mut ai = build_assembler(&name, &config, &target);
-    let commands = ai.assemble(&target);
-    write_trace_file(trace_output_path, &commands).expect("failed to write trace");
+    print_params(&assembler_params(&name, &config), &config);
+    let source = Model::initial(target.matrix.len());
+    let model = target.clone();
+    let high_steps = Arc::new(Mutex::new(None));
+    let reported = high_steps.clone();
+    let solution = solve(&source, &target, deadline(), move || {
+        let mut ai = build_assembler(&name, &config, &model);
+        let trace = ai.assemble(&model);
+        *reported.lock().unwrap() = ai.high_steps();
+        trace
+    });
+    write_solution(trace_output_path, solution, &high_steps);
 }
 
 fn disassemble(args: &Vec<String>) {
@@ -55,9 +75,18 @@ fn disassemble(args: &Vec<String>) {
     let trace_output_path = Path::new(&args[3]);
     let config = Config::new();
     let name = env::var("GOLD_AI").expect("failed to get AI from ENV");
-    let mut ai = build_disassembler(&name, &config, &source);
-    let commands = ai.disassemble(&source);
-    write_trace_file(trace_output_path, &commands).expect("failed to write trace");
+    print_params(&disassembler_params(&name, &config), &config);
+    let target = Model::initial(source.matrix.len());
+    let model = source.clone();
+    let high_steps = Arc::new(Mutex::new(None));
+    let reported = high_steps.clone();
+    let solution = solve(&source, &target, deadline(), move || {
+        let mut ai = build_disassembler(&name, &config, &model);
+        let trace = ai.disassemble(&model);
+        *reported.lock().unwrap() = ai.high_steps();
+        trace
+    });
+    write_solution(trace_output_path, solution, &high_steps);
 }
 
 fn reassemble(args: &Vec<String>) {
@@ -77,19 +106,126 @@ fn reassemble(args: &Vec<String>) {
     let trace_output_path = Path::new(&args[4]);
     let config = Config::new();
     let name = env::var("GOLD_AI").expect("failed to get AI from ENV");
-    let mut ai = build_reassembler(&name, &config, &source, &target);
-    let commands = ai.reassemble(&source, &target);
-    write_trace_file(trace_output_path, &commands).expect("failed to write trace");
+    print_params(&reassembler_params(&name, &config), &config);
+    let (s, t) = (source.clone(), target.clone());
+    let high_steps = Arc::new(Mutex::new(None));
+    let reported = high_steps.clone();
+    let solution = solve(&source, &target, deadline(), move || {
+        let mut ai = build_reassembler(&name, &config, &s, &t);
+        let trace = ai.reassemble(&s, &t);
+        *reported.lock().unwrap() = ai.high_steps();
+        trace
+    });
+    write_solution(trace_output_path, solution, &high_steps);
+}
+
+// AI が読むパラメータの値を、tune が書き出す settings と同じ形で表示する
+fn print_params(params: &[Param], config: &Config) {
+    if params.is_empty() {
+        return;
+    }
+    let settings = params
+        .iter()
+        .map(|p| format!("{}={}", p.name, (p.get)(config)))
+        .collect::<Vec<_>>();
+    eprintln!("params: {}", settings.join(" "));
+}
+
+// GOLD_DEADLINE (秒) があれば、AI をその時間で打ち切る
+fn deadline() -> Option<Duration> {
+    env::var("GOLD_DEADLINE").ok().map(|s| {
+        let seconds = s.parse::<f64>().expect("invalid GOLD_DEADLINE");
+        Duration::from_millis((seconds * 1000.0) as u64)
+    })
+}
+
+// State で検査を通った trace だけを書き出す。AI が使えなければ default trace になる。
+// high_steps は AI が数えた High の step 数で、AI の trace を書き出すときだけ表示する。
+fn write_solution(
+    path: &Path,
+    solution: Result<Solution, Box<std::error::Error>>,
+    high_steps: &Mutex<Option<usize>>,
+) {
+    let solution = match solution {
+        Ok(solution) => solution,
+        Err(err) => {
+            eprintln!("no valid trace: {}", err);
+            process::exit(1);
+        }
+    };
+    match solution.outcome {
+        Outcome::Solved => {
+            if let Some(n) = *high_steps.lock().unwrap() {
+                eprintln!("high steps: {}", n);
+            }
+        }
+        Outcome::TimedOut => eprintln!("deadline exceeded, using the default trace"),
+        Outcome::Failed(message) => eprintln!("AI failed ({}), using the default trace", message),
+    }
+    eprintln!("energy: {}", solution.energy);
+    write_trace_file(path, &solution.trace).expect("failed to write trace");
+}
+
+fn parse_shape(name: &str) -> Shape {
+    match Shape::from_name(name) {
+        Some(shape) => shape,
+        None => {
+            let names: Vec<_> = Shape::all().iter().map(|s| s.name()).collect();
+            eprintln!("unknown shape: {} (one of {})", name, names.join(", "));
+            process::exit(1);
+        }
+    }
+}
+
+fn gen(args: &Vec<String>) {
+    if args.len() != 6 && args.len() != 8 && args.len() != 9 {
+        usage(&args);
+        process::exit(1);
+    }
+
+    let shape = parse_shape(&args[2]);
+    let r = args[3].parse::<usize>().expect("invalid resolution");
+    if r < MIN_RESOLUTION || r > 250 {
+        eprintln!("resolution must be in [{}, 250]", MIN_RESOLUTION);
+        process::exit(1);
+    }
+    let seed = args[4].parse::<u64>().expect("invalid seed");
+
+    if args.len() == 6 {
+        let model = generate(shape, r, seed);
+        write_model_file(Path::new(&args[5]), &model).expect("failed to write model");
+        return;
+    }
+
+    let overlap = args[7].parse::<f64>().expect("invalid overlap");
+    if overlap < 0.0 || overlap > 1.0 {
+        eprintln!("overlap must be in [0, 1]");
+        process::exit(1);
+    }
+    let target_shape = if args.len() > 8 {
+        parse_shape(&args[8])
+    } else {
+        shape
+    };
+    let (source, target) = generate_pair(shape, target_shape, r, seed, overlap);
+    write_model_file(Path::new(&args[5]), &source).expect("failed to write source");
+    write_model_file(Path::new(&args[6]), &target).expect("failed to write target");
 }
 
 fn usage(args: &Vec<String>) {
     eprintln!(
-        "not enough arguments
+        "invalid arguments
 
 Example:
   $ {0} assemble    target.mdl output_trace.nbt
   $ {0} disassemble source.mdl output_trace.nbt
   $ {0} reassemble  source.mdl target.mdl output_trace.nbt
+  $ {0} gen         shape R seed output.mdl
+  $ {0} gen         shape R seed source.mdl target.mdl overlap [target_shape]
+
+shape: tower, overhang, arch, hollow_shell, blob, thin_wall, pillars
+overlap: probability that each source voxel is kept in the target (0.0 - 1.0);
+         kept voxels that would float are dropped, so fewer may be kept
 
 NOTE: Use following environment variables to configure AIs
 
@@ -99,6 +235,8 @@ GOLD_AI=default
 
 ## Optional
 
+GOLD_DEADLINE=seconds (give up the AI and write the default trace after this time)
+
 ### GOLD_AI=default
 
 GOLD_ASSEMBLER=default
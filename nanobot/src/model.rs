@@ -2,7 +2,10 @@
 
 use common::*;
 use std::error::*;
+use std::fs;
 use std::io::BufRead;
+use std::io::Write;
+use std::path::Path;
 
 #[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Debug)]
 pub struct Model {
@@ -51,6 +54,28 @@ impl Model {
     pub fn set_voxel_at(&mut self, p: Position, v: Voxel) {
         self.matrix[p.x as usize][p.y as usize][p.z as usize] = v
     }
+    pub fn encode(&self) -> Vec<u8> {
+        let r = self.matrix.len();
+        let mut buffer = vec![0; 1 + (r * r * r + 7) / 8];
+        buffer[0] = r as u8;
+        for x in 0..r {
+            for y in 0..r {
+                for z in 0..r {
+                    if self.matrix[x][y][z] == Voxel::Full {
+                        let pos = x * r * r + y * r + z;
+                        buffer[1 + pos / 8] |= 1 << (pos % 8);
+                    }
+                }
+            }
+        }
+        buffer
+    }
+}
+
+pub fn write_model_file(path: &Path, model: &Model) -> Result<(), Box<Error>> {
+    let mut buffer = fs::File::create(path)?;
+    buffer.write_all(&model.encode())?;
+    Ok(())
 }
 
 #[test]
@@ -67,3 +92,14 @@ fn test_3x3_model_with_2_full_voxels() {
     assert_eq!(Voxel::Full, model.matrix[1][0][1]);
     assert_eq!(Voxel::Full, model.matrix[1][1][1]);
 }
+
+#[test]
+fn test_encode_model() {
+    let mut model = Model::initial(3);
+    model.matrix[1][0][1] = Voxel::Full;
+    model.matrix[1][1][1] = Voxel::Full;
+    let bytes = model.encode();
+    assert_eq!(bytes, vec![3, 0b0000000, 0b00100100, 0b00000000, 0b00000000]);
+    let decoded = Model::new(&mut &bytes[..]).unwrap();
+    assert_eq!(decoded, model);
+}
@@ -0,0 +1,451 @@
+use common::*;
+use export::encode_png;
+use state::{Observer, State};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+// trace を実行しながら、どの bot がどの step でどの voxel を埋めたか消したか (provenance) と、
+// energy を空間のどこで使ったかを集める Observer。score の --report と --heatmap で使う。
+//
+// energy はそれが起きた位置の voxel に割り当てる。
+//   - 毎 step の bot 1 体あたりの 20 は、その step の初めに bot がいる位置
+//   - 移動は通った voxel (出発点は除く) に 1 マスあたり 2、LMove の 4 は曲がり角
+//   - Fill, Void, GFill, GVoid は対象の voxel、Fission は子の位置、Fusion は primary の位置
+// harmonics による R^3 に比例する分だけはどこにも割り当てず global に入れる。
+// なので voxel ごとの energy と global を全部足すと State の energy と一致する。
+// Void で Full を消すと energy は減るので、voxel の energy は負にもなる。
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Fill,
+    Void,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::Fill => "fill",
+            Action::Void => "void",
+        }
+    }
+}
+
+// voxel が実際に変わった 1 回分
+#[derive(Clone, Copy, Debug)]
+pub struct VoxelChange {
+    pub pos: Position,
+    pub step: usize,
+    pub bid: Bid,
+    pub action: Action,
+}
+
+// bot ごとの集計
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BotStats {
+    // 生きていた step の数
+    pub steps: usize,
+    pub filled: usize,
+    pub voided: usize,
+    // 動いた距離の合計
+    pub moved: i64,
+    pub energy: i64,
+}
+
+pub struct Report {
+    r: usize,
+    step: usize,
+    // Position::index の順
+    energy: Vec<i64>,
+    global_energy: i64,
+    changes: Vec<VoxelChange>,
+    bots: BTreeMap<Bid, BotStats>,
+    // step の初めの bot の位置
+    positions: HashMap<Bid, Position>,
+    // 直前のコマンドが LMove なら、次の on_move の行き先が曲がり角
+    lmove_corner: bool,
+}
+
+impl Report {
+    pub fn new(r: usize) -> Report {
+        Report {
+            r,
+            step: 0,
+            energy: vec![0; r * r * r],
+            global_energy: 0,
+            changes: vec![],
+            bots: BTreeMap::new(),
+            positions: HashMap::new(),
+            lmove_corner: false,
+        }
+    }
+
+    fn add(&mut self, bid: Bid, p: Position, energy: i64) {
+        self.energy[p.index(self.r)] += energy;
+        self.bot_mut(bid).energy += energy;
+    }
+
+    fn bot_mut(&mut self, bid: Bid) -> &mut BotStats {
+        self.bots.entry(bid).or_insert_with(BotStats::default)
+    }
+
+    fn record(&mut self, bid: Bid, p: Position, action: Action) {
+        self.changes.push(VoxelChange {
+            pos: p,
+            step: self.step,
+            bid,
+            action,
+        });
+    }
+
+    pub fn get_steps(&self) -> usize {
+        self.step
+    }
+    pub fn get_energy_at(&self, p: Position) -> i64 {
+        self.energy[p.index(self.r)]
+    }
+    pub fn get_global_energy(&self) -> i64 {
+        self.global_energy
+    }
+    pub fn get_total_energy(&self) -> i64 {
+        self.global_energy + self.energy.iter().sum::<i64>()
+    }
+    // 起きた順
+    pub fn get_changes(&self) -> &[VoxelChange] {
+        &self.changes
+    }
+    pub fn get_bot_stats(&self) -> &BTreeMap<Bid, BotStats> {
+        &self.bots
+    }
+
+    // 高さ y の層の energy の合計
+    pub fn layer_energy(&self, y: usize) -> i64 {
+        let mut sum = 0;
+        for z in 0..self.r {
+            for x in 0..self.r {
+                sum += self.energy[x + y * self.r + z * self.r * self.r];
+            }
+        }
+        sum
+    }
+
+    // 一辺 block の立方体ごとの energy の合計。0 でないものだけを、角の位置と一緒に返す。
+    pub fn regions(&self, block: usize) -> Vec<(Position, i64)> {
+        let n = (self.r + block - 1) / block;
+        let mut sums = vec![0; n * n * n];
+        for (i, &e) in self.energy.iter().enumerate() {
+            let (x, y, z) = (i % self.r, i / self.r % self.r, i / (self.r * self.r));
+            sums[x / block + y / block * n + z / block * n * n] += e;
+        }
+        let mut regions = vec![];
+        for x in 0..n {
+            for y in 0..n {
+                for z in 0..n {
+                    let e = sums[x + y * n + z * n * n];
+                    if e != 0 {
+                        let p = Position::new(
+                            (x * block) as i32,
+                            (y * block) as i32,
+                            (z * block) as i32,
+                        );
+                        regions.push((p, e));
+                    }
+                }
+            }
+        }
+        regions
+    }
+
+    pub fn to_json(&self, block: usize) -> String {
+        let mut s = String::new();
+        write!(
+            s,
+            "{{\"resolution\":{},\"steps\":{},\"energy\":{},\"global_energy\":{}",
+            self.r,
+            self.step,
+            self.get_total_energy(),
+            self.global_energy
+        )
+        .unwrap();
+
+        s.push_str(",\"bots\":[");
+        for (i, (bid, stats)) in self.bots.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(
+                s,
+                "{{\"bid\":{},\"steps\":{},\"filled\":{},\"voided\":{},\"moved\":{},\"energy\":{}}}",
+                bid.0, stats.steps, stats.filled, stats.voided, stats.moved, stats.energy
+            )
+            .unwrap();
+        }
+
+        s.push_str("],\"layers\":[");
+        for y in 0..self.r {
+            if y > 0 {
+                s.push(',');
+            }
+            write!(s, "{}", self.layer_energy(y)).unwrap();
+        }
+
+        write!(s, "],\"block\":{},\"regions\":[", block).unwrap();
+        for (i, &(p, e)) in self.regions(block).iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(
+                s,
+                "{{\"x\":{},\"y\":{},\"z\":{},\"energy\":{}}}",
+                p.x, p.y, p.z, e
+            )
+            .unwrap();
+        }
+
+        s.push_str("],\"changes\":[");
+        for (i, c) in self.changes.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(
+                s,
+                "{{\"x\":{},\"y\":{},\"z\":{},\"step\":{},\"bid\":{},\"action\":\"{}\"}}",
+                c.pos.x,
+                c.pos.y,
+                c.pos.z,
+                c.step,
+                c.bid.0,
+                c.action.name()
+            )
+            .unwrap();
+        }
+        s.push_str("]}");
+        s
+    }
+
+    // 高さ y の層を、x を横、z を縦にした画像にする。
+    // 正の energy は赤、負は青。濃さは絶対値の対数で、全部の層を通した最大で揃える
+    // (bot が待っている場所だけが濃くなりすぎないように)。
+    pub fn encode_heatmap(&self, y: usize) -> Vec<u8> {
+        let max = self
+            .energy
+            .iter()
+            .map(|e| e.abs())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        // 小さいモデルでも見えるように、1 辺がだいたい 256 px になるまで拡大する
+        let scale = (256 / self.r).max(1);
+        let size = self.r * scale;
+        let mut rgb = Vec::with_capacity(size * size * 3);
+        for row in 0..size {
+            for col in 0..size {
+                let (x, z) = (col / scale, row / scale);
+                let e = self.energy[x + y * self.r + z * self.r * self.r];
+                let t = (1.0 + e.abs() as f64).ln() / (1.0 + max as f64).ln();
+                let fade = 255 - (255.0 * t) as u8;
+                if e >= 0 {
+                    rgb.extend_from_slice(&[255, fade, fade]);
+                } else {
+                    rgb.extend_from_slice(&[fade, fade, 255]);
+                }
+            }
+        }
+        encode_png(size, size, &rgb)
+    }
+
+    // energy を使った層ごとに dir/layer_YYY.png を書き出して、書いた枚数を返す
+    pub fn write_heatmaps(&self, dir: &Path) -> Result<usize, Box<Error>> {
+        fs::create_dir_all(dir)?;
+        let mut count = 0;
+        for y in 0..self.r {
+            let offset = y * self.r;
+            let used = (0..self.r).any(|z| {
+                let start = offset + z * self.r * self.r;
+                self.energy[start..start + self.r].iter().any(|&e| e != 0)
+            });
+            if !used {
+                continue;
+            }
+            let mut file = fs::File::create(dir.join(format!("layer_{:03}.png", y)))?;
+            file.write_all(&self.encode_heatmap(y))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl Observer for Report {
+    fn on_step_begin(&mut self, state: &State) {
+        let volume = (self.r * self.r * self.r) as i64;
+        self.global_energy += volume
+            * match state.get_harmonics() {
+                Harmonics::Low => 3,
+                Harmonics::High => 30,
+            };
+        self.positions.clear();
+        for bot in state.get_bots() {
+            self.positions.insert(bot.bid, bot.pos);
+            self.bot_mut(bot.bid).steps += 1;
+            self.add(bot.bid, bot.pos, 20);
+        }
+    }
+
+    fn on_step_end(&mut self, _state: &State) {
+        self.step += 1;
+    }
+
+    fn on_command(&mut self, _bid: Bid, command: &Command) {
+        self.lmove_corner = match *command {
+            Command::LMove(_, _) => true,
+            _ => false,
+        };
+    }
+
+    fn on_fill(&mut self, bid: Bid, p: Position, before: Voxel) {
+        match before {
+            Voxel::Void => {
+                self.add(bid, p, 12);
+                self.bot_mut(bid).filled += 1;
+                self.record(bid, p, Action::Fill);
+            }
+            Voxel::Full => self.add(bid, p, 6),
+        }
+    }
+
+    fn on_void(&mut self, bid: Bid, p: Position, before: Voxel) {
+        match before {
+            Voxel::Full => {
+                self.add(bid, p, -12);
+                self.bot_mut(bid).voided += 1;
+                self.record(bid, p, Action::Void);
+            }
+            Voxel::Void => self.add(bid, p, 3),
+        }
+    }
+
+    fn on_move(&mut self, bid: Bid, from: Position, to: Position) {
+        for p in Region(from, to).iter() {
+            if p != from {
+                self.add(bid, p, 2);
+                self.bot_mut(bid).moved += 1;
+            }
+        }
+        if self.lmove_corner {
+            self.add(bid, to, 4);
+            self.lmove_corner = false;
+        }
+    }
+
+    fn on_fission(&mut self, parent: Bid, child: &Nanobot) {
+        self.add(parent, child.pos, 24);
+    }
+
+    fn on_fusion(&mut self, primary: Bid, _secondary: Bid) {
+        let p = self.positions[&primary];
+        self.add(primary, p, -24);
+    }
+}
+
+#[test]
+fn test_report_energy() {
+    use ai::builder::*;
+    use ai::config::Config;
+    use ai::dflt::dflt_trace;
+    use generator::{generate, generate_pair, Shape};
+    use model::Model;
+    let r = 12;
+    let config = Config::new();
+    let empty = Model::initial(r);
+    let model = generate(Shape::Arch, r, 0);
+    let tower = generate(Shape::Tower, r, 0);
+    let (source, target) = generate_pair(Shape::Blob, Shape::Tower, r, 0, 0.5);
+    // Fission/Fusion や GVoid を使う trace も含める
+    let problems = vec![
+        (
+            empty.clone(),
+            model.clone(),
+            build_assembler(&String::from("default"), &config, &model).assemble(&model),
+        ),
+        (
+            tower.clone(),
+            empty.clone(),
+            build_disassembler(&String::from("gvoid"), &config, &tower).disassemble(&tower),
+        ),
+        (source.clone(), target.clone(), dflt_trace(&source, &target)),
+    ];
+    for (source, target, trace) in problems {
+        let mut report = Report::new(r);
+        let mut state = State::initial_with_model(&source);
+        state.execute_trace_observed(&trace, &mut report).unwrap();
+        state.end_check(&target).unwrap();
+        assert_eq!(report.get_total_energy(), state.get_energy());
+        let by_bots: i64 = report.get_bot_stats().values().map(|s| s.energy).sum();
+        assert_eq!(by_bots + report.get_global_energy(), state.get_energy());
+        let by_layers: i64 = (0..r).map(|y| report.layer_energy(y)).sum();
+        assert_eq!(by_layers + report.get_global_energy(), state.get_energy());
+        let by_regions: i64 = report.regions(5).iter().map(|&(_, e)| e).sum();
+        assert_eq!(by_regions + report.get_global_energy(), state.get_energy());
+
+        // 最後に起きた変更をたどると target になる
+        let mut last = HashMap::new();
+        for c in report.get_changes() {
+            assert!(c.step < report.get_steps());
+            last.insert(c.pos, c.action);
+        }
+        for x in 0..r {
+            for y in 0..r {
+                for z in 0..r {
+                    let p = Position::new(x as i32, y as i32, z as i32);
+                    let expected = match last.get(&p) {
+                        Some(&Action::Fill) => Voxel::Full,
+                        Some(&Action::Void) => Voxel::Void,
+                        None => source.voxel_at(p),
+                    };
+                    assert_eq!(target.voxel_at(p), expected);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_report_provenance() {
+    let mut state = State::initial(4);
+    let mut report = Report::new(4);
+    state
+        .execute_trace_observed(
+            &[
+                Command::Fission(NCD::new(1, 0, 0), 0),
+                Command::Fill(NCD::new(0, 0, 1)),
+                Command::LMove(SLCD::new(0, 0, 2), SLCD::new(-1, 0, 0)),
+                Command::Wait,
+                Command::Void(NCD::new(0, 0, -1)),
+            ],
+            &mut report,
+        )
+        .unwrap();
+    let changes: Vec<_> = report
+        .get_changes()
+        .iter()
+        .map(|c| (c.pos, c.step, c.bid, c.action))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            (Position::new(0, 0, 1), 1, Bid(1), Action::Fill),
+            (Position::new(0, 0, 1), 2, Bid(2), Action::Void),
+        ]
+    );
+    let stats = report.get_bot_stats();
+    assert_eq!(stats[&Bid(1)].steps, 3);
+    assert_eq!(stats[&Bid(2)].moved, 3);
+    // 曲がり角には 2 (通過) と 4 (LMove) が入る
+    assert_eq!(report.get_energy_at(Position::new(1, 0, 2)), 6);
+    assert_eq!(report.get_total_energy(), state.get_energy());
+    assert!(report.to_json(2).contains("\"action\":\"void\""));
+}
@@ -2,11 +2,13 @@
 
 use common::*;
 use model::Model;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::error::*;
 use std::fmt;
 use std::iter::Extend;
+use std::mem;
 use union_find::*;
 
 #[derive(Clone, Debug)]
@@ -22,8 +24,31 @@ pub struct State {
     connectivity_is_dirty: bool,
     // dirtyフラグがたっているが、groundedであることは確実であるというフラグ
     must_be_grounded_on_dirty: bool,
+    // dirty な間に &self の問い合わせのために作り直した connectivity と Full の voxel の数。voxel を書き換えたら捨てる。
+    fresh_connectivity: RefCell<Option<(UnionFind, i32)>>,
 
     full_voxel_count: i32,
+
+    // checkpoint の積み重ね。空でない間は voxel の書き換えを voxel_log に覚えておく。
+    checkpoints: Vec<Checkpoint>,
+    // (位置, 書き換える前の voxel)
+    voxel_log: Vec<(Position, Voxel)>,
+}
+
+// checkpoint を取った時点に戻すための情報。
+// 行列と connectivity は書き換えの記録から戻すので、コピーするのは bot と数値だけ。
+#[derive(Clone, Debug)]
+struct Checkpoint {
+    energy: i64,
+    harmonics: Harmonics,
+    bots: Vec<Nanobot>,
+    connectivity_is_dirty: bool,
+    must_be_grounded_on_dirty: bool,
+    full_voxel_count: i32,
+    voxel_log_len: usize,
+    connectivity_mark: usize,
+    // この checkpoint の後で recalculate_connectivity が置き換えた、checkpoint の時点の connectivity
+    replaced_connectivity: Option<UnionFind>,
 }
 
 impl State {
@@ -39,6 +64,9 @@ impl State {
             connectivity_is_dirty: false,
             full_voxel_count: 0,
             must_be_grounded_on_dirty: true,
+            fresh_connectivity: RefCell::new(None),
+            checkpoints: vec![],
+            voxel_log: vec![],
         }
     }
     pub fn initial_with_model(model: &Model) -> State {
@@ -49,7 +77,14 @@ impl State {
         state
     }
     pub fn end_check(&self, model: &Model) -> Result<(), Box<Error>> {
-        assert!(self.matrix.len() == model.matrix.len());
+        if self.matrix.len() != model.matrix.len() {
+            let message = format!(
+                "resolution mismatch: state={}, model={}",
+                self.matrix.len(),
+                model.matrix.len()
+            );
+            return Err(Box::new(SimulationError::new(message)));
+        }
         if self.bots.len() != 0 {
             let message = format!("Exist active nanobots");
             return Err(Box::new(SimulationError::new(message)));
@@ -66,6 +101,168 @@ impl State {
     pub fn get_bot_count(&self) -> usize {
         self.bots.len()
     }
+    pub fn get_bots(&self) -> &[Nanobot] {
+        &self.bots
+    }
+    pub fn get_harmonics(&self) -> Harmonics {
+        self.harmonics
+    }
+    pub fn get_voxel(&self, p: Position) -> Voxel {
+        self.voxel_at(p)
+    }
+    pub fn get_resolution(&self) -> usize {
+        self.matrix.len()
+    }
+    // p にいる bot
+    pub fn get_bot_at(&self, p: Position) -> Option<&Nanobot> {
+        self.find_bot_by_coordinate(p).map(|i| &self.bots[i])
+    }
+    // p が Full で、床につながっていれば true
+    pub fn is_grounded_at(&self, p: Position) -> bool {
+        if self.voxel_at(p) == Voxel::Void {
+            return false;
+        }
+        if self.connectivity_is_dirty && self.must_be_grounded_on_dirty {
+            return true;
+        }
+        let r = self.matrix.len();
+        self.with_connectivity(|connectivity, _| connectivity.find(p.index(r)) == connectivity.find(r * r * r))
+    }
+    // 床につながっていない Full の voxel を、つながっている塊ごとに分けたもの。
+    // 塊は最初の voxel の (x, y, z) の順、塊の中も (x, y, z) の順に並べる。
+    pub fn get_floating_components(&self) -> Vec<Vec<Position>> {
+        if !self.has_floating_voxel() {
+            return vec![];
+        }
+        let r = self.matrix.len();
+        self.with_connectivity(|connectivity, _| {
+            let ground = connectivity.find(r * r * r);
+            let mut index = HashMap::new();
+            let mut components: Vec<Vec<Position>> = vec![];
+            for x in 0..r {
+                for y in 0..r {
+                    for z in 0..r {
+                        if self.matrix[x][y][z] == Voxel::Void {
+                            continue;
+                        }
+                        let p = Position::new(x as i32, y as i32, z as i32);
+                        let root = connectivity.find(p.index(r));
+                        if root == ground {
+                            continue;
+                        }
+                        let i = *index.entry(root).or_insert_with(|| {
+                            components.push(vec![]);
+                            components.len() - 1
+                        });
+                        components[i].push(p);
+                    }
+                }
+            }
+            components
+        })
+    }
+    pub fn get_model(&self) -> Model {
+        Model {
+            matrix: self.matrix.clone(),
+        }
+    }
+    // 床につながっていない Full の voxel があれば true
+    pub fn has_floating_voxel(&self) -> bool {
+        if self.connectivity_is_dirty && self.must_be_grounded_on_dirty {
+            return false;
+        }
+        let r = self.matrix.len();
+        self.with_connectivity(|connectivity, count| connectivity.find_size(r * r * r) - 1 != count as usize)
+    }
+
+    // 今の voxel の connectivity と Full の voxel の数で f を呼ぶ。
+    // dirty なら作り直すが、self.connectivity は書き換えずに fresh_connectivity に覚えておく。
+    fn with_connectivity<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&UnionFind, i32) -> T,
+    {
+        if !self.connectivity_is_dirty {
+            return f(&self.connectivity, self.full_voxel_count);
+        }
+        let mut fresh = self.fresh_connectivity.borrow_mut();
+        if fresh.is_none() {
+            *fresh = Some(self.build_connectivity());
+        }
+        let &(ref connectivity, count) = fresh.as_ref().unwrap();
+        f(connectivity, count)
+    }
+
+    // 今の状態を覚えておく。rollback でここに戻り、commit で忘れる。入れ子にできる。
+    // 行列全体はコピーしないので、大きな R でも clone よりずっと軽い。
+    pub fn checkpoint(&mut self) {
+        let connectivity_mark = self.connectivity.mark();
+        self.checkpoints.push(Checkpoint {
+            energy: self.energy,
+            harmonics: self.harmonics,
+            bots: self.bots.clone(),
+            connectivity_is_dirty: self.connectivity_is_dirty,
+            must_be_grounded_on_dirty: self.must_be_grounded_on_dirty,
+            full_voxel_count: self.full_voxel_count,
+            voxel_log_len: self.voxel_log.len(),
+            connectivity_mark,
+            replaced_connectivity: None,
+        });
+    }
+
+    // 最後の checkpoint の時点に戻して、その checkpoint を捨てる
+    pub fn rollback(&mut self) {
+        let checkpoint = self.checkpoints.pop().expect("no checkpoint to roll back");
+        while self.voxel_log.len() > checkpoint.voxel_log_len {
+            let (p, v) = self.voxel_log.pop().unwrap();
+            self.matrix[p.x as usize][p.y as usize][p.z as usize] = v;
+        }
+        *self.fresh_connectivity.get_mut() = None;
+        if let Some(connectivity) = checkpoint.replaced_connectivity {
+            self.connectivity = connectivity;
+        }
+        self.connectivity.undo(checkpoint.connectivity_mark);
+        self.energy = checkpoint.energy;
+        self.harmonics = checkpoint.harmonics;
+        self.bots = checkpoint.bots;
+        self.connectivity_is_dirty = checkpoint.connectivity_is_dirty;
+        self.must_be_grounded_on_dirty = checkpoint.must_be_grounded_on_dirty;
+        self.full_voxel_count = checkpoint.full_voxel_count;
+        self.end_checkpoint();
+    }
+
+    // 最後の checkpoint を捨てて、それからの変更を確定する。
+    // 外側の checkpoint に戻すときは、この checkpoint の間の変更もまとめて戻す。
+    pub fn commit(&mut self) {
+        let checkpoint = self.checkpoints.pop().expect("no checkpoint to commit");
+        if let Some(outer) = self.checkpoints.last_mut() {
+            if outer.replaced_connectivity.is_none() {
+                outer.replaced_connectivity = checkpoint.replaced_connectivity;
+            }
+        }
+        self.end_checkpoint();
+    }
+
+    fn end_checkpoint(&mut self) {
+        if self.checkpoints.is_empty() {
+            self.voxel_log.clear();
+            self.connectivity.forget();
+        }
+    }
+
+    // update_time_step と同じだが、失敗したときは何も変えない
+    pub fn try_time_step(&mut self, commands: &[Command]) -> Result<(), Box<Error>> {
+        self.checkpoint();
+        match self.update_time_step(commands) {
+            Ok(()) => {
+                self.commit();
+                Ok(())
+            }
+            Err(err) => {
+                self.rollback();
+                Err(err)
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -128,10 +325,108 @@ fn couple_volatile_coordinates(p1: Position, p2: Position) -> VolatileCoordinate
     vc
 }
 
+// update_time_step の中で起きたことを知らせてもらうためのもの。
+// 解析の道具はこれを実装して、State の中身を真似せずに voxel や bot の動きを追える。
+//
+// 通知は起きた順で、step の途中で失敗したときはそこまでの通知は取り消されない。
+// GFill と GVoid は canonical な region を持つ bot の bid で、region の voxel ごとに通知する。
+pub trait Observer {
+    // step を始める前 (energy を足す前) の状態
+    fn on_step_begin(&mut self, _state: &State) {}
+    // step が最後まで通った後の状態
+    fn on_step_end(&mut self, _state: &State) {}
+    // bid の bot がコマンドを実行する直前。Wait や Halt も含めて、bot ごとに 1 回ずつ呼ばれる。
+    fn on_command(&mut self, _bid: Bid, _command: &Command) {}
+    // Fill で p を埋めた。before が Full なら何も変わっていない。
+    fn on_fill(&mut self, _bid: Bid, _p: Position, _before: Voxel) {}
+    // Void で p を消した。before が Void なら何も変わっていない。
+    fn on_void(&mut self, _bid: Bid, _p: Position, _before: Voxel) {}
+    // まっすぐな 1 回の移動。LMove では 2 回呼ばれる。
+    fn on_move(&mut self, _bid: Bid, _from: Position, _to: Position) {}
+    fn on_fission(&mut self, _parent: Bid, _child: &Nanobot) {}
+    fn on_fusion(&mut self, _primary: Bid, _secondary: Bid) {}
+    // Flip した後の harmonics
+    fn on_flip(&mut self, _bid: Bid, _harmonics: Harmonics) {}
+}
+
+// 何もしない Observer
+pub struct NullObserver;
+
+impl Observer for NullObserver {}
+
+// 並んでいる Observer に順に同じ通知を送る
+impl<'a> Observer for Vec<&'a mut Observer> {
+    fn on_step_begin(&mut self, state: &State) {
+        for o in self.iter_mut() {
+            o.on_step_begin(state);
+        }
+    }
+    fn on_step_end(&mut self, state: &State) {
+        for o in self.iter_mut() {
+            o.on_step_end(state);
+        }
+    }
+    fn on_command(&mut self, bid: Bid, command: &Command) {
+        for o in self.iter_mut() {
+            o.on_command(bid, command);
+        }
+    }
+    fn on_fill(&mut self, bid: Bid, p: Position, before: Voxel) {
+        for o in self.iter_mut() {
+            o.on_fill(bid, p, before);
+        }
+    }
+    fn on_void(&mut self, bid: Bid, p: Position, before: Voxel) {
+        for o in self.iter_mut() {
+            o.on_void(bid, p, before);
+        }
+    }
+    fn on_move(&mut self, bid: Bid, from: Position, to: Position) {
+        for o in self.iter_mut() {
+            o.on_move(bid, from, to);
+        }
+    }
+    fn on_fission(&mut self, parent: Bid, child: &Nanobot) {
+        for o in self.iter_mut() {
+            o.on_fission(parent, child);
+        }
+    }
+    fn on_fusion(&mut self, primary: Bid, secondary: Bid) {
+        for o in self.iter_mut() {
+            o.on_fusion(primary, secondary);
+        }
+    }
+    fn on_flip(&mut self, bid: Bid, harmonics: Harmonics) {
+        for o in self.iter_mut() {
+            o.on_flip(bid, harmonics);
+        }
+    }
+}
+
 impl State {
     pub fn update_time_step(&mut self, commands: &[Command]) -> Result<(), Box<Error>> {
-        assert_eq!(commands.len(), self.bots.len());
-        assert!(self.bots.len() > 0);
+        self.update_time_step_observed(commands, &mut NullObserver)
+    }
+
+    pub fn update_time_step_observed(
+        &mut self,
+        commands: &[Command],
+        observer: &mut Observer,
+    ) -> Result<(), Box<Error>> {
+        if self.bots.is_empty() {
+            let message = format!("no nanobots are active: n_commands={}", commands.len());
+            return Err(Box::new(SimulationError::new(message)));
+        }
+        if commands.len() != self.bots.len() {
+            let message = format!(
+                "the number of commands is not equal to the number of nanobots: n_commands={}, n_nanobots={}",
+                commands.len(),
+                self.bots.len()
+            );
+            return Err(Box::new(SimulationError::new(message)));
+        }
+
+        observer.on_step_begin(self);
 
         let r = self.matrix.len();
 
@@ -146,7 +441,7 @@ impl State {
         let mut deleted_bot_bids = HashSet::new();
 
         for (i, command) in commands.iter().enumerate() {
-            let output = self.update_one(i, command)?;
+            let output = self.update_one_observed(i, command, observer)?;
 
             let vc = output.vc;
             if !vcs.is_disjoint(&vc) {
@@ -175,6 +470,37 @@ impl State {
             return Err(Box::new(SimulationError::new(message)));
         }
 
+        observer.on_step_end(self);
+        Ok(())
+    }
+
+    // trace を bot の数ずつ区切って最後まで実行する
+    pub fn execute_trace(&mut self, trace: &[Command]) -> Result<(), Box<Error>> {
+        self.execute_trace_observed(trace, &mut NullObserver)
+    }
+
+    pub fn execute_trace_observed(
+        &mut self,
+        trace: &[Command],
+        observer: &mut Observer,
+    ) -> Result<(), Box<Error>> {
+        let mut offset = 0;
+        while offset < trace.len() {
+            let bot_cnt = self.bots.len();
+            if bot_cnt == 0 {
+                let message = format!("commands remain after halt: offset={}", offset);
+                return Err(Box::new(SimulationError::new(message)));
+            }
+            if offset + bot_cnt > trace.len() {
+                let message = format!(
+                    "trace ends in the middle of a time step: offset={}, n_nanobots={}",
+                    offset, bot_cnt
+                );
+                return Err(Box::new(SimulationError::new(message)));
+            }
+            self.update_time_step_observed(&trace[offset..offset + bot_cnt], observer)?;
+            offset += bot_cnt;
+        }
         Ok(())
     }
 
@@ -299,40 +625,66 @@ impl State {
     }
 
     fn recalculate_connectivity(&mut self) {
-        let r = self.matrix.len();
+        let (connectivity, count) = match self.fresh_connectivity.get_mut().take() {
+            Some(fresh) => fresh,
+            None => self.build_connectivity(),
+        };
+        let old = mem::replace(&mut self.connectivity, connectivity);
+        if let Some(checkpoint) = self.checkpoints.last_mut() {
+            if checkpoint.replaced_connectivity.is_none() {
+                checkpoint.replaced_connectivity = Some(old);
+            }
+            self.connectivity.mark();
+        }
+        self.full_voxel_count = count;
 
-        self.connectivity = UnionFind::new(r * r * r + 1);
-        self.full_voxel_count = 0;
+        self.connectivity_is_dirty = false;
+        self.must_be_grounded_on_dirty = !self.does_floating_voxel_exist_with_cache();
+    }
 
+    // 今の voxel から作った connectivity と、Full の voxel の数
+    fn build_connectivity(&self) -> (UnionFind, i32) {
+        let r = self.matrix.len();
+        let mut connectivity = UnionFind::new(r * r * r + 1);
+        let mut count = 0;
         for (x, vx) in self.matrix.iter().enumerate() {
             for (y, vy) in vx.iter().enumerate() {
                 for (z, &voxel) in vy.iter().enumerate() {
                     if voxel == Voxel::Full {
                         let p = Position::new(x as i32, y as i32, z as i32);
                         if y == 0 {
-                            self.connectivity.union_set(p.index(r), r * r * r);
+                            connectivity.union_set(p.index(r), r * r * r);
                         }
                         for pp in adjacent(p) {
                             if self.is_valid_coordinate(&pp) && self.voxel_at(pp) == Voxel::Full {
-                                self.connectivity.union_set(p.index(r), pp.index(r));
+                                connectivity.union_set(p.index(r), pp.index(r));
                             }
                         }
-                        self.full_voxel_count += 1;
+                        count += 1;
                     }
                 }
             }
         }
-
-        self.connectivity_is_dirty = false;
-        self.must_be_grounded_on_dirty = !self.does_floating_voxel_exist_with_cache();
+        (connectivity, count)
     }
 
     pub fn update_one(
         &mut self,
         nanobot_index: usize,
         command: &Command,
+    ) -> Result<UpdateOneOutput, Box<Error>> {
+        self.update_one_observed(nanobot_index, command, &mut NullObserver)
+    }
+
+    fn update_one_observed(
+        &mut self,
+        nanobot_index: usize,
+        command: &Command,
+        observer: &mut Observer,
     ) -> Result<UpdateOneOutput, Box<Error>> {
         let c = self.bots[nanobot_index].pos;
+        let bid = self.bots[nanobot_index].bid;
+        observer.on_command(bid, command);
 
         match command {
             Command::Halt => {
@@ -366,17 +718,22 @@ impl State {
                     Harmonics::Low => Harmonics::High,
                     Harmonics::High => Harmonics::Low,
                 };
+                observer.on_flip(bid, self.harmonics);
                 Ok(UpdateOneOutput::from_single_volatile_coordinate(c))
             }
 
             Command::SMove(llcd) => {
                 let vc = self.move_straight(llcd, nanobot_index, command)?;
+                observer.on_move(bid, c, self.bots[nanobot_index].pos);
                 Ok(UpdateOneOutput::from_vc(vc))
             }
 
             Command::LMove(slcd1, slcd2) => {
                 let mut vc1 = self.move_straight(slcd1, nanobot_index, command)?;
+                let mid = self.bots[nanobot_index].pos;
+                observer.on_move(bid, c, mid);
                 let vc2 = self.move_straight(slcd2, nanobot_index, command)?;
+                observer.on_move(bid, mid, self.bots[nanobot_index].pos);
                 self.energy += 4;
                 vc1.extend(&vc2);
 
@@ -391,7 +748,8 @@ impl State {
                     return Err(Box::new(SimulationError::new(message)));
                 }
 
-                self.fill_voxel(new_c);
+                let before = self.fill_voxel(new_c);
+                observer.on_fill(bid, new_c, before);
 
                 let vc = couple_volatile_coordinates(c, new_c);
                 Ok(UpdateOneOutput::from_vc(vc))
@@ -417,6 +775,7 @@ impl State {
 
                 let new_bot = bot.fission(ncd, *m);
                 self.energy += 24;
+                observer.on_fission(bid, &new_bot);
 
                 Ok(UpdateOneOutput {
                     vc: couple_volatile_coordinates(c, new_c),
@@ -433,7 +792,8 @@ impl State {
                     return Err(Box::new(SimulationError::new(message)));
                 }
 
-                self.erase_voxel(new_c);
+                let before = self.erase_voxel(new_c);
+                observer.on_void(bid, new_c, before);
 
                 let vc = couple_volatile_coordinates(c, new_c);
                 Ok(UpdateOneOutput::from_vc(vc))
@@ -454,6 +814,7 @@ impl State {
                 let bot = &mut self.bots[nanobot_index];
                 bot.fusion(&mut secondary_bot);
                 self.energy -= 24;
+                observer.on_fusion(bid, secondary_bot.bid);
 
                 Ok(UpdateOneOutput {
                     vc: couple_volatile_coordinates(c, secondary_c),
@@ -493,7 +854,8 @@ impl State {
                 self.must_be_grounded_on_dirty = false;
 
                 for p in region.iter() {
-                    match self.voxel_at(p) {
+                    let before = self.voxel_at(p);
+                    match before {
                         Voxel::Full => {
                             self.set_voxel_at(p, Voxel::Void);
                             self.energy -= 12;
@@ -504,6 +866,7 @@ impl State {
                             self.energy += 3;
                         }
                     }
+                    observer.on_void(bid, p, before);
                 }
 
                 let mut vc = VolatileCoordinates::new();
@@ -537,7 +900,8 @@ impl State {
                 }
 
                 for p in region.iter() {
-                    self.fill_voxel(p);
+                    let before = self.fill_voxel(p);
+                    observer.on_fill(bid, p, before);
                 }
 
                 let mut vc = VolatileCoordinates::new();
@@ -554,6 +918,11 @@ impl State {
     }
 
     fn set_voxel_at(&mut self, p: Position, v: Voxel) {
+        if !self.checkpoints.is_empty() {
+            let old = self.voxel_at(p);
+            self.voxel_log.push((p, old));
+        }
+        *self.fresh_connectivity.get_mut() = None;
         self.matrix[p.x as usize][p.y as usize][p.z as usize] = v
     }
 
@@ -593,11 +962,13 @@ impl State {
         Ok(Region(c, new_c).iter().collect())
     }
 
-    fn fill_voxel(&mut self, c: Position) {
+    // 埋める前の voxel を返す
+    fn fill_voxel(&mut self, c: Position) -> Voxel {
         let r = self.matrix.len();
         self.must_be_grounded_on_dirty = false;
 
-        match self.voxel_at(c) {
+        let before = self.voxel_at(c);
+        match before {
             Voxel::Void => {
                 self.set_voxel_at(c, Voxel::Full);
                 self.energy += 12;
@@ -615,10 +986,13 @@ impl State {
             }
             Voxel::Full => self.energy += 6,
         }
+        before
     }
 
-    fn erase_voxel(&mut self, c: Position) {
-        match self.voxel_at(c) {
+    // 消す前の voxel を返す
+    fn erase_voxel(&mut self, c: Position) -> Voxel {
+        let before = self.voxel_at(c);
+        match before {
             Voxel::Full => {
                 self.set_voxel_at(c, Voxel::Void);
                 self.energy -= 12;
@@ -633,6 +1007,7 @@ impl State {
                 self.energy += 3;
             }
         }
+        before
     }
 
     fn can_omit_connectivity_recalculation(&mut self, c: Position) -> bool {
@@ -662,7 +1037,7 @@ impl State {
         }
     }
 
-    fn is_valid_coordinate(&self, p: &Position) -> bool {
+    pub fn is_valid_coordinate(&self, p: &Position) -> bool {
         let r = self.matrix.len() as i32;
         if p.x < 0 || p.x >= r {
             return false;
@@ -1428,3 +1803,233 @@ fn end_check_test() {
         assert!(r.is_err());
     }
 }
+
+#[test]
+fn test_dflt_trace_energy() {
+    use ai::dflt::dflt_trace;
+    use generator::{generate, Shape};
+    // 1 体で High のまま動く default trace の energy は、コマンドから直接数えられる
+    let r = 10;
+    let model = generate(Shape::Arch, r, 0);
+    let empty = Model::initial(r);
+    for &(source, target) in [(&empty, &model), (&model, &empty)].iter() {
+        let trace = dflt_trace(source, target);
+        let mut state = State::initial_with_model(source);
+        state.execute_trace(&trace).unwrap();
+        state.end_check(target).unwrap();
+
+        let volume = (r * r * r) as i64;
+        // 最初の Flip と最後の Halt の step だけ Low
+        let mut expected = 2 * 3 * volume + (trace.len() as i64 - 2) * 30 * volume + 20 * trace.len() as i64;
+        for c in trace.iter() {
+            expected += match *c {
+                Command::SMove(ref llcd) => 2 * llcd.manhattan_length() as i64,
+                Command::Fill(_) => 12,
+                Command::Void(_) => -12,
+                _ => 0,
+            };
+        }
+        assert_eq!(state.get_energy(), expected);
+    }
+}
+
+#[cfg(test)]
+fn assert_same_state(a: &mut State, b: &mut State) {
+    assert!(a.matrix == b.matrix);
+    assert_eq!(a.energy, b.energy);
+    assert_eq!(a.harmonics, b.harmonics);
+    assert_eq!(a.full_voxel_count, b.full_voxel_count);
+    assert_eq!(a.bots.len(), b.bots.len());
+    for (x, y) in a.bots.iter().zip(b.bots.iter()) {
+        assert_eq!((x.bid, x.pos, &x.seeds), (y.bid, y.pos, &y.seeds));
+    }
+    assert_eq!(a.has_floating_voxel(), b.has_floating_voxel());
+}
+
+#[test]
+fn test_try_time_step() {
+    let mut state = State::initial(5);
+    state
+        .update_time_step(&[Command::SMove(LLCD::new(0, 2, 0))])
+        .unwrap();
+    let mut expected = state.clone();
+    // Low で浮いた voxel を作ろうとすると失敗し、何も変わらない
+    assert!(state.try_time_step(&[Command::Fill(NCD::new(1, 0, 0))]).is_err());
+    assert_same_state(&mut state, &mut expected);
+    state.try_time_step(&[Command::Flip]).unwrap();
+    state.try_time_step(&[Command::Fill(NCD::new(1, 0, 0))]).unwrap();
+    assert_eq!(state.get_harmonics(), Harmonics::High);
+    assert_eq!(state.voxel_at(Position::new(1, 2, 0)), Voxel::Full);
+}
+
+#[test]
+fn test_rollback() {
+    use ai::dflt::dflt_trace;
+    use generator::{generate, Shape};
+    let r = 10;
+    let model = generate(Shape::Arch, r, 0);
+    let trace = dflt_trace(&model, &Model::initial(r));
+    let mut state = State::initial_with_model(&model);
+    state.execute_trace(&trace[..50]).unwrap();
+    let mut expected = state.clone();
+
+    // 入れ子の checkpoint。内側を commit しても、外側の rollback でまとめて戻る。
+    state.checkpoint();
+    state.execute_trace(&trace[50..100]).unwrap();
+    state.checkpoint();
+    state.execute_trace(&trace[100..150]).unwrap();
+    // connectivity を計算し直させる
+    state.does_floating_voxel_exist();
+    let mut middle = state.clone();
+    state.checkpoint();
+    state.execute_trace(&trace[150..]).unwrap();
+    state.rollback();
+    assert_same_state(&mut state, &mut middle);
+    state.commit();
+    state.rollback();
+    assert_same_state(&mut state, &mut expected);
+    assert!(state.checkpoints.is_empty() && state.voxel_log.is_empty());
+
+    state.execute_trace(&trace[50..]).unwrap();
+    state.end_check(&Model::initial(r)).unwrap();
+    expected.execute_trace(&trace[50..]).unwrap();
+    assert_eq!(state.get_energy(), expected.get_energy());
+}
+
+#[test]
+fn test_query() {
+    let mut model = Model::initial(6);
+    // 柱の上に腕を伸ばし、腕の先から下に 1 つぶら下げる
+    for y in 0..3 {
+        model.set_voxel_at(Position::new(1, y, 1), Voxel::Full);
+    }
+    for x in 2..5 {
+        model.set_voxel_at(Position::new(x, 2, 1), Voxel::Full);
+    }
+    model.set_voxel_at(Position::new(4, 1, 1), Voxel::Full);
+    let mut state = State::initial_with_model(&model);
+    assert_eq!(state.get_resolution(), 6);
+    assert!(state.is_valid_coordinate(&Position::new(5, 5, 5)));
+    assert!(!state.is_valid_coordinate(&Position::new(6, 0, 0)));
+    assert_eq!(state.get_bot_at(Position::zero()).unwrap().bid, Bid(1));
+    assert!(state.get_bot_at(Position::new(1, 0, 0)).is_none());
+    assert!(state.is_grounded_at(Position::new(4, 1, 1)));
+    assert!(!state.is_grounded_at(Position::new(0, 1, 1)));
+    assert!(state.get_floating_components().is_empty());
+
+    // 腕の付け根を消すと、腕とぶら下がった voxel が 1 つの塊として浮く
+    state
+        .execute_trace(&[
+            Command::SMove(LLCD::new(0, 2, 0)),
+            Command::Flip,
+            Command::Void(NCD::new(1, 0, 1)),
+        ])
+        .unwrap();
+    assert_eq!(state.get_bots()[0].pos, Position::new(0, 2, 0));
+    assert!(state.is_grounded_at(Position::new(1, 1, 1)));
+    assert!(!state.is_grounded_at(Position::new(4, 1, 1)));
+    assert_eq!(
+        state.get_floating_components(),
+        vec![vec![
+            Position::new(2, 2, 1),
+            Position::new(3, 2, 1),
+            Position::new(4, 1, 1),
+            Position::new(4, 2, 1),
+        ]]
+    );
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct CountingObserver {
+    steps: usize,
+    commands: usize,
+    filled: usize,
+    voided: usize,
+    moved: i64,
+    fissions: usize,
+    fusions: usize,
+    flips: usize,
+    last_energy: i64,
+}
+
+#[cfg(test)]
+impl Observer for CountingObserver {
+    fn on_step_end(&mut self, state: &State) {
+        self.steps += 1;
+        self.last_energy = state.get_energy();
+    }
+    fn on_command(&mut self, _bid: Bid, _command: &Command) {
+        self.commands += 1;
+    }
+    fn on_fill(&mut self, _bid: Bid, _p: Position, before: Voxel) {
+        if before == Voxel::Void {
+            self.filled += 1;
+        }
+    }
+    fn on_void(&mut self, _bid: Bid, _p: Position, before: Voxel) {
+        if before == Voxel::Full {
+            self.voided += 1;
+        }
+    }
+    fn on_move(&mut self, _bid: Bid, from: Position, to: Position) {
+        self.moved += Region(from, to).iter().count() as i64 - 1;
+    }
+    fn on_fission(&mut self, _parent: Bid, _child: &Nanobot) {
+        self.fissions += 1;
+    }
+    fn on_fusion(&mut self, _primary: Bid, _secondary: Bid) {
+        self.fusions += 1;
+    }
+    fn on_flip(&mut self, _bid: Bid, _harmonics: Harmonics) {
+        self.flips += 1;
+    }
+}
+
+#[test]
+fn test_observer() {
+    use ai::builder::build_assembler;
+    use ai::config::Config;
+    use ai::dflt::dflt_trace;
+    use generator::{generate, Shape};
+    let r = 10;
+    let model = generate(Shape::Tower, r, 0);
+    let empty = Model::initial(r);
+    let full_count = model
+        .matrix
+        .iter()
+        .flat_map(|vx| vx.iter().flat_map(|vy| vy.iter()))
+        .filter(|&&v| v == Voxel::Full)
+        .count();
+
+    for &(source, target) in [(&empty, &model), (&model, &empty)].iter() {
+        let trace = dflt_trace(source, target);
+        let mut observer = CountingObserver::default();
+        let mut state = State::initial_with_model(source);
+        state.execute_trace_observed(&trace, &mut observer).unwrap();
+        // bot は 1 体なので、コマンドと step の数は同じ
+        assert_eq!(observer.steps, trace.len());
+        assert_eq!(observer.commands, trace.len());
+        assert_eq!(observer.filled + observer.voided, full_count);
+        assert_eq!(observer.flips, 2);
+        assert_eq!(observer.last_energy, state.get_energy());
+        let moved: i64 = trace
+            .iter()
+            .map(|c| match *c {
+                Command::SMove(ref llcd) => llcd.manhattan_length() as i64,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(observer.moved, moved);
+    }
+
+    // 分裂した bot は全部合体して戻ってくる
+    let trace = build_assembler(&String::from("default"), &Config::new(), &model).assemble(&model);
+    let mut observer = CountingObserver::default();
+    let mut state = State::initial(r);
+    state.execute_trace_observed(&trace, &mut observer).unwrap();
+    assert_eq!(observer.commands, trace.len());
+    assert_eq!(observer.filled, full_count);
+    assert!(observer.fissions > 0);
+    assert_eq!(observer.fissions, observer.fusions);
+}
@@ -0,0 +1,233 @@
+#![allow(dead_code)]
+
+// モデルと trace の対称変換。
+// y 軸は重力があるので変えられず、xz 平面の 8 通り (x, z の入れ替えと反転の組合せ) だけを扱う。
+// 変換すると原点が別の角に移るので、trace の最初と最後に原点とその角の間の移動を足す。
+// x = 0, R-1 や z = 0, R-1 の面には Full の voxel がないので、この移動は必ずできる。
+
+use ai::utils::{move_straight_x, move_straight_z};
+use common::*;
+use model::Model;
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct Symmetry {
+    // x と z を入れ替えてから、反転する
+    pub swap_xz: bool,
+    pub mirror_x: bool,
+    pub mirror_z: bool,
+}
+
+impl Symmetry {
+    pub fn identity() -> Symmetry {
+        Symmetry {
+            swap_xz: false,
+            mirror_x: false,
+            mirror_z: false,
+        }
+    }
+
+    pub fn all() -> Vec<Symmetry> {
+        let mut ret = vec![];
+        for &swap_xz in [false, true].iter() {
+            for &mirror_x in [false, true].iter() {
+                for &mirror_z in [false, true].iter() {
+                    ret.push(Symmetry {
+                        swap_xz,
+                        mirror_x,
+                        mirror_z,
+                    });
+                }
+            }
+        }
+        ret
+    }
+
+    pub fn name(&self) -> String {
+        let mut names = vec![];
+        if self.swap_xz {
+            names.push("swap_xz");
+        }
+        if self.mirror_x {
+            names.push("mirror_x");
+        }
+        if self.mirror_z {
+            names.push("mirror_z");
+        }
+        if names.is_empty() {
+            names.push("identity");
+        }
+        names.join("+")
+    }
+
+    // 入れ替えてから反転する変換の逆は、反転してから入れ替える変換なので、
+    // 入れ替える場合は反転する軸も入れ替わる
+    pub fn inverse(&self) -> Symmetry {
+        if self.swap_xz {
+            Symmetry {
+                swap_xz: true,
+                mirror_x: self.mirror_z,
+                mirror_z: self.mirror_x,
+            }
+        } else {
+            *self
+        }
+    }
+
+    // 移動量などの差分ベクトルの変換
+    fn transform_vector(&self, x: i32, y: i32, z: i32) -> (i32, i32, i32) {
+        let (x, z) = if self.swap_xz { (z, x) } else { (x, z) };
+        let x = if self.mirror_x { -x } else { x };
+        let z = if self.mirror_z { -z } else { z };
+        (x, y, z)
+    }
+
+    pub fn transform_position(&self, p: &Position, r: usize) -> Position {
+        let r = r as i32;
+        let (x, z) = if self.swap_xz { (p.z, p.x) } else { (p.x, p.z) };
+        let x = if self.mirror_x { r - 1 - x } else { x };
+        let z = if self.mirror_z { r - 1 - z } else { z };
+        Position::new(x, p.y, z)
+    }
+
+    pub fn transform_model(&self, model: &Model) -> Model {
+        let r = model.matrix.len();
+        let mut ret = Model::initial(r);
+        for x in 0..r {
+            for y in 0..r {
+                for z in 0..r {
+                    let p = Position::new(x as i32, y as i32, z as i32);
+                    ret.set_voxel_at(self.transform_position(&p, r), model.voxel_at(p));
+                }
+            }
+        }
+        ret
+    }
+
+    pub fn transform_command(&self, command: &Command) -> Command {
+        match *command {
+            Command::SMove(llcd) => {
+                let (x, y, z) = self.transform_vector(llcd.x(), llcd.y(), llcd.z());
+                Command::SMove(LLCD::new(x, y, z))
+            }
+            Command::LMove(slcd1, slcd2) => {
+                let (x1, y1, z1) = self.transform_vector(slcd1.x(), slcd1.y(), slcd1.z());
+                let (x2, y2, z2) = self.transform_vector(slcd2.x(), slcd2.y(), slcd2.z());
+                Command::LMove(SLCD::new(x1, y1, z1), SLCD::new(x2, y2, z2))
+            }
+            Command::Fission(ncd, m) => Command::Fission(self.transform_ncd(&ncd), m),
+            Command::Fill(ncd) => Command::Fill(self.transform_ncd(&ncd)),
+            Command::Void(ncd) => Command::Void(self.transform_ncd(&ncd)),
+            Command::FusionP(ncd) => Command::FusionP(self.transform_ncd(&ncd)),
+            Command::FusionS(ncd) => Command::FusionS(self.transform_ncd(&ncd)),
+            Command::GFill(ncd, fcd) => {
+                Command::GFill(self.transform_ncd(&ncd), self.transform_fcd(&fcd))
+            }
+            Command::GVoid(ncd, fcd) => {
+                Command::GVoid(self.transform_ncd(&ncd), self.transform_fcd(&fcd))
+            }
+            Command::Halt | Command::Wait | Command::Flip => *command,
+        }
+    }
+
+    fn transform_ncd(&self, ncd: &NCD) -> NCD {
+        let (x, y, z) = self.transform_vector(ncd.x(), ncd.y(), ncd.z());
+        NCD::new(x, y, z)
+    }
+
+    fn transform_fcd(&self, fcd: &FCD) -> FCD {
+        let (x, y, z) = self.transform_vector(fcd.x(), fcd.y(), fcd.z());
+        FCD::new(x, y, z)
+    }
+
+    // 解像度 r のモデルに対する trace を、変換したモデルに対する trace にする。
+    // bid は変わらないので、各 step のコマンドの順番はそのまま。
+    pub fn transform_trace(&self, trace: &[Command], r: usize) -> Vec<Command> {
+        let corner = self.transform_position(&Position::zero(), r);
+        let mut ret = vec![];
+        // 原点から、変換後の原点がある角まで床の縁を通って行く
+        ret.extend(move_straight_x(corner.x));
+        ret.extend(move_straight_z(corner.z));
+        let (last, body) = match trace.split_last() {
+            Some((&Command::Halt, body)) => (Some(Command::Halt), body),
+            _ => (None, trace),
+        };
+        ret.extend(body.iter().map(|c| self.transform_command(c)));
+        if let Some(halt) = last {
+            ret.extend(move_straight_z(-corner.z));
+            ret.extend(move_straight_x(-corner.x));
+            ret.push(halt);
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+fn run_trace(source: &Model, target: &Model, trace: &[Command]) -> i64 {
+    use state::State;
+    let mut state = State::initial_with_model(source);
+    state.execute_trace(trace).unwrap();
+    state.end_check(target).unwrap();
+    state.get_energy()
+}
+
+#[test]
+fn test_symmetry_inverse() {
+    let r = 10;
+    let mut model = Model::initial(r);
+    model.matrix[1][0][2] = Voxel::Full;
+    model.matrix[1][1][2] = Voxel::Full;
+    model.matrix[3][0][7] = Voxel::Full;
+    let p = Position::new(1, 2, 3);
+    assert_eq!(Symmetry::all().len(), 8);
+    for s in Symmetry::all() {
+        let inv = s.inverse();
+        assert_eq!(inv.transform_position(&s.transform_position(&p, r), r), p);
+        assert_eq!(inv.transform_model(&s.transform_model(&model)), model);
+        let command = Command::LMove(SLCD::new(1, 0, 0), SLCD::new(0, 0, -3));
+        assert_eq!(
+            inv.transform_command(&s.transform_command(&command)),
+            command
+        );
+    }
+    let s = Symmetry {
+        swap_xz: true,
+        mirror_x: true,
+        mirror_z: false,
+    };
+    assert_eq!(s.transform_position(&p, r), Position::new(6, 2, 1));
+    assert_eq!(
+        s.transform_command(&Command::Fill(NCD::new(1, 1, 0))),
+        Command::Fill(NCD::new(0, 1, 1))
+    );
+}
+
+#[test]
+fn test_transform_trace() {
+    let r = 5;
+    let source = Model::initial(r);
+    let mut target = Model::initial(r);
+    target.matrix[1][0][1] = Voxel::Full;
+    target.matrix[1][0][2] = Voxel::Full;
+    let trace = vec![
+        Command::SMove(LLCD::new(0, 0, 1)),
+        Command::Fission(NCD::new(0, 1, 0), 0),
+        Command::Fill(NCD::new(1, 0, 1)),
+        Command::Fill(NCD::new(1, -1, 0)),
+        Command::FusionP(NCD::new(0, 1, 0)),
+        Command::FusionS(NCD::new(0, -1, 0)),
+        Command::SMove(LLCD::new(0, 0, -1)),
+        Command::Halt,
+    ];
+    run_trace(&source, &target, &trace);
+    assert_eq!(Symmetry::identity().transform_trace(&trace, r), trace);
+    for s in Symmetry::all() {
+        let transformed = s.transform_trace(&trace, r);
+        // 原点が動く場合は、角までの往復が増える
+        if s.transform_position(&Position::zero(), r) == Position::zero() {
+            assert_eq!(transformed.len(), trace.len());
+        } else {
+            assert!(transformed.len() > trace.len());
+        }
+        run_trace(&source, &s.transform_model(&target), &transformed);
+    }
+}
@@ -0,0 +1,356 @@
+use common::*;
+use state::{Observer, State};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+// trace の各 step の i 番目のコマンドがどの bot のものかを記録して、bot ごとに
+// 何かしていた (Active)、動いていた (Moving)、Wait していた (Waiting) step を並べる Observer。
+// score の --timeline で SVG のガントチャートと、分割の偏りを見るための数字を出す。
+//
+// Fusion で戻った seed はまた Fission で使われるので、同じ bid の bot が途中でいなくなって
+// また現れることがある。いない間は None にしておく。
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Activity {
+    Active,
+    Moving,
+    Waiting,
+}
+
+impl Activity {
+    fn of(command: &Command) -> Activity {
+        match *command {
+            Command::Wait => Activity::Waiting,
+            Command::SMove(_) | Command::LMove(_, _) => Activity::Moving,
+            _ => Activity::Active,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Activity::Active => "active",
+            Activity::Moving => "moving",
+            Activity::Waiting => "waiting",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match *self {
+            Activity::Active => "#4caf50",
+            Activity::Moving => "#2196f3",
+            Activity::Waiting => "#cccccc",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BotTimeline {
+    pub bid: Bid,
+    // 最初にコマンドを実行した step
+    pub start: usize,
+    // start からの step ごとの活動。いなかった step は None
+    pub activities: Vec<Option<Activity>>,
+}
+
+impl BotTimeline {
+    pub fn count(&self, activity: Activity) -> usize {
+        self.activities
+            .iter()
+            .filter(|&&a| a == Some(activity))
+            .count()
+    }
+
+    // 最後に Wait 以外のコマンドを出した step の次。ずっと Wait なら start
+    pub fn busy_until(&self) -> usize {
+        self.activities
+            .iter()
+            .rposition(|&a| a.is_some() && a != Some(Activity::Waiting))
+            .map_or(self.start, |i| self.start + i + 1)
+    }
+
+    // 同じ活動が続く区間ごとに (始まりの step, 長さ, 活動)
+    pub fn runs(&self) -> Vec<(usize, usize, Activity)> {
+        let mut runs: Vec<(usize, usize, Activity)> = vec![];
+        for (i, &a) in self.activities.iter().enumerate() {
+            let a = match a {
+                Some(a) => a,
+                None => continue,
+            };
+            let step = self.start + i;
+            if let Some(last) = runs.last_mut() {
+                if last.2 == a && last.0 + last.1 == step {
+                    last.1 += 1;
+                    continue;
+                }
+            }
+            runs.push((step, 1, a));
+        }
+        runs
+    }
+}
+
+pub struct Summary {
+    pub steps: usize,
+    // bot の数を全 step で足したもの
+    pub bot_steps: usize,
+    pub active: usize,
+    pub moving: usize,
+    pub waiting: usize,
+    // waiting / bot_steps
+    pub idle_ratio: f64,
+    // Wait 以外のコマンドを一番多く出した bot (同じなら bid の小さい方) と、その step 数。
+    // 分割した仕事が終わるまで、ほかの bot はこの bot を待つことになる。
+    // busy_until は最後に Fusion と Halt をする bot 1 がいつも一番になるので使わない。
+    pub critical_bot: Option<Bid>,
+    pub critical_busy: usize,
+    // Wait している bot の維持費 (1 体 1 step あたり 20)
+    pub wasted_energy: i64,
+}
+
+pub struct Timeline {
+    step: usize,
+    // step ごとに、コマンドの順に並べた bid
+    bids: Vec<Vec<Bid>>,
+    bots: BTreeMap<Bid, BotTimeline>,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline {
+            step: 0,
+            bids: vec![],
+            bots: BTreeMap::new(),
+        }
+    }
+
+    pub fn get_steps(&self) -> usize {
+        self.step
+    }
+    // step 番目の step で、i 番目のコマンドを実行した bot は get_bids(step)[i]
+    pub fn get_bids(&self, step: usize) -> &[Bid] {
+        &self.bids[step]
+    }
+    pub fn get_bots(&self) -> &BTreeMap<Bid, BotTimeline> {
+        &self.bots
+    }
+
+    pub fn summary(&self) -> Summary {
+        let count = |activity| self.bots.values().map(|b| b.count(activity)).sum::<usize>();
+        let (active, moving, waiting) = (
+            count(Activity::Active),
+            count(Activity::Moving),
+            count(Activity::Waiting),
+        );
+        let bot_steps = active + moving + waiting;
+        let mut critical_bot = None;
+        let mut critical_busy = 0;
+        for bot in self.bots.values() {
+            let busy = bot.count(Activity::Active) + bot.count(Activity::Moving);
+            if critical_bot.is_none() || busy > critical_busy {
+                critical_bot = Some(bot.bid);
+                critical_busy = busy;
+            }
+        }
+        Summary {
+            steps: self.step,
+            bot_steps,
+            active,
+            moving,
+            waiting,
+            idle_ratio: if bot_steps == 0 {
+                0.0
+            } else {
+                waiting as f64 / bot_steps as f64
+            },
+            critical_bot,
+            critical_busy,
+            wasted_energy: 20 * waiting as i64,
+        }
+    }
+
+    // bot ごとに 1 行、横軸を step にしたガントチャート
+    pub fn to_svg(&self) -> String {
+        let summary = self.summary();
+        let (left, top, row, width) = (70.0, 50.0, 14.0, 1000.0);
+        let scale = width / (self.step.max(1) as f64);
+        let height = top + row * self.bots.len() as f64 + 10.0;
+
+        let mut s = String::new();
+        writeln!(
+            s,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"monospace\" font-size=\"11\">",
+            left + width + 10.0,
+            height
+        )
+        .unwrap();
+        writeln!(
+            s,
+            "<text x=\"4\" y=\"14\">steps: {}, bot-steps: {}, idle: {:.1}%, wasted upkeep: {}</text>",
+            summary.steps,
+            summary.bot_steps,
+            summary.idle_ratio * 100.0,
+            summary.wasted_energy
+        )
+        .unwrap();
+        if let Some(bid) = summary.critical_bot {
+            writeln!(
+                s,
+                "<text x=\"4\" y=\"28\">critical: bot {} (busy {} steps)</text>",
+                bid.0, summary.critical_busy
+            )
+            .unwrap();
+        }
+        for (i, &a) in [Activity::Active, Activity::Moving, Activity::Waiting]
+            .iter()
+            .enumerate()
+        {
+            let x = left + 120.0 * i as f64;
+            writeln!(
+                s,
+                "<rect x=\"{}\" y=\"34\" width=\"10\" height=\"10\" fill=\"{}\"/><text x=\"{}\" y=\"43\">{}</text>",
+                x,
+                a.color(),
+                x + 14.0,
+                a.name()
+            )
+            .unwrap();
+        }
+
+        for (i, bot) in self.bots.values().enumerate() {
+            let y = top + row * i as f64;
+            writeln!(
+                s,
+                "<text x=\"4\" y=\"{}\">bot {}</text>",
+                y + row - 3.0,
+                bot.bid.0
+            )
+            .unwrap();
+            for (start, len, a) in bot.runs() {
+                writeln!(
+                    s,
+                    "<rect x=\"{:.2}\" y=\"{}\" width=\"{:.2}\" height=\"{}\" fill=\"{}\"/>",
+                    left + start as f64 * scale,
+                    y + 1.0,
+                    len as f64 * scale,
+                    row - 2.0,
+                    a.color()
+                )
+                .unwrap();
+            }
+        }
+        s.push_str("</svg>\n");
+        s
+    }
+
+    pub fn write_svg(&self, path: &Path) -> Result<(), Box<Error>> {
+        let mut file = fs::File::create(path)?;
+        file.write_all(self.to_svg().as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Observer for Timeline {
+    fn on_step_begin(&mut self, _state: &State) {
+        self.bids.push(vec![]);
+    }
+
+    fn on_step_end(&mut self, _state: &State) {
+        self.step += 1;
+    }
+
+    fn on_command(&mut self, bid: Bid, command: &Command) {
+        let step = self.step;
+        self.bids[step].push(bid);
+        let bot = self.bots.entry(bid).or_insert_with(|| BotTimeline {
+            bid,
+            start: step,
+            activities: vec![],
+        });
+        bot.activities.resize(step - bot.start, None);
+        bot.activities.push(Some(Activity::of(command)));
+    }
+}
+
+#[test]
+fn test_timeline() {
+    let mut state = State::initial(4);
+    let mut timeline = Timeline::new();
+    state
+        .execute_trace_observed(
+            &[
+                Command::Fission(NCD::new(1, 0, 0), 1),
+                Command::Wait,
+                Command::SMove(LLCD::new(0, 0, 2)),
+                Command::SMove(LLCD::new(0, 0, 2)),
+                Command::Fill(NCD::new(0, 0, -1)),
+                Command::FusionP(NCD::new(1, 0, 0)),
+                Command::FusionS(NCD::new(-1, 0, 0)),
+                Command::Fission(NCD::new(0, 0, 1), 0),
+                Command::Wait,
+                Command::Wait,
+            ],
+            &mut timeline,
+        )
+        .unwrap();
+    assert_eq!(timeline.get_steps(), 6);
+    assert_eq!(timeline.get_bids(0), &[Bid(1)]);
+    assert_eq!(timeline.get_bids(3), &[Bid(1), Bid(2)]);
+
+    let bots = timeline.get_bots();
+    assert_eq!(bots[&Bid(1)].busy_until(), 5);
+    // bot 2 は Fusion で一度いなくなり、また Fission で現れる
+    assert_eq!(
+        bots[&Bid(2)].activities,
+        vec![
+            Some(Activity::Moving),
+            Some(Activity::Active),
+            Some(Activity::Active),
+            None,
+            Some(Activity::Waiting),
+        ]
+    );
+    assert_eq!(
+        bots[&Bid(2)].runs(),
+        vec![
+            (1, 1, Activity::Moving),
+            (2, 2, Activity::Active),
+            (5, 1, Activity::Waiting),
+        ]
+    );
+
+    let summary = timeline.summary();
+    assert_eq!(summary.bot_steps, 10);
+    assert_eq!(summary.waiting, 3);
+    assert_eq!(summary.moving, 2);
+    assert_eq!(summary.active, 5);
+    assert_eq!(summary.wasted_energy, 60);
+    assert_eq!(summary.critical_bot, Some(Bid(1)));
+    assert_eq!(summary.critical_busy, 4);
+
+    let svg = timeline.to_svg();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains("critical: bot 1"));
+}
+
+#[test]
+fn test_timeline_upkeep() {
+    use ai::builder::build_assembler;
+    use ai::config::Config;
+    use generator::{generate, Shape};
+    let model = generate(Shape::Pillars, 16, 0);
+    let trace = build_assembler(&String::from("default"), &Config::new(), &model).assemble(&model);
+    let mut timeline = Timeline::new();
+    let mut state = State::initial(16);
+    state.execute_trace_observed(&trace, &mut timeline).unwrap();
+
+    let summary = timeline.summary();
+    assert_eq!(summary.bot_steps, trace.len());
+    let commands: usize = (0..summary.steps).map(|s| timeline.get_bids(s).len()).sum();
+    assert_eq!(commands, trace.len());
+    let waits = trace.iter().filter(|&&c| c == Command::Wait).count();
+    assert_eq!(summary.wasted_energy, 20 * waits as i64);
+}
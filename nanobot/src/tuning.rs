@@ -0,0 +1,356 @@
+extern crate rand;
+
+// AI の数値のパラメータを問題ごとに探して、energy が一番小さくなる組を記録する道具。
+//
+// 試す値は builder の *_params が返すパラメータの範囲から作り、AI を anytime の run_with_deadline で
+// 残りの時間を締め切りにして動かし、trace を State で最後まで実行して energy を測る。
+// trace が通らなかった組と締め切りに間に合わなかった組は、energy が無限大として扱う。
+// 探し方は 3 通り:
+//   grid: 各パラメータの範囲を GRID_POINTS 等分した値の、全部の組合せ
+//   random: 範囲から一様に選んだ組
+//   halving: ランダムに選んだ HALVING_CANDIDATES 個の組を seed を変えながら測り、平均の良い半分を残すのを
+//            1 つになるまで繰り返す (successive halving)。乱数を使う AI で運の良い組を選ばないため。
+// どれも最初に今の Config の値 (既定値) を測り、時間を使い切ったらそこまでで一番良い組を返す。
+// 結果は 1 問 1 行の TSV に追記する。
+
+use self::rand::Rng;
+use ai::config::{Config, Param};
+use anytime::run_with_deadline;
+use generator::seeded_rng;
+use harness::*;
+use model::Model;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// grid で 1 つのパラメータについて試す値の数
+const GRID_POINTS: i64 = 3;
+// halving で最初に用意する組の数
+const HALVING_CANDIDATES: usize = 16;
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Search {
+    Grid,
+    Random,
+    Halving,
+}
+
+impl Search {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Search::Grid => "grid",
+            Search::Random => "random",
+            Search::Halving => "halving",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Search> {
+        [Search::Grid, Search::Random, Search::Halving]
+            .iter()
+            .cloned()
+            .find(|s| s.name() == name)
+    }
+}
+
+pub struct Problem {
+    pub name: String,
+    pub task: Task,
+    pub source: Model,
+    pub target: Model,
+}
+
+pub struct Tuned {
+    pub problem: String,
+    pub task: Task,
+    pub ai: String,
+    pub search: Search,
+    pub params: Vec<Param>,
+    pub default_energy: Option<i64>,
+    pub best_values: Vec<i64>,
+    pub best_energy: Option<i64>,
+    // 測った回数 (halving では seed ごとに 1 回と数える)
+    pub trials: usize,
+}
+
+impl Tuned {
+    // 一番良かった組を、環境変数の形 (GOLD_A=1 GOLD_B=2) で並べたもの
+    pub fn settings(&self) -> String {
+        self.params
+            .iter()
+            .zip(self.best_values.iter())
+            .map(|(p, v)| format!("{}={}", p.name, v))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn energy_name(energy: Option<i64>) -> String {
+    energy.map_or(String::from("-"), |e| e.to_string())
+}
+
+// 失敗を一番悪い値にして比べるための energy
+fn score(energy: Option<i64>) -> i64 {
+    energy.unwrap_or(i64::max_value())
+}
+
+struct Tuner<'a> {
+    problem: &'a Problem,
+    ai: &'a str,
+    base: Config,
+    params: Vec<Param>,
+    trials: usize,
+    // この時刻を過ぎたら AI を待たない
+    deadline: Instant,
+}
+
+impl<'a> Tuner<'a> {
+    fn evaluate(&mut self, values: &[i64], seed: u64) -> Option<i64> {
+        let mut config = self.base.clone();
+        for (p, &v) in self.params.iter().zip(values.iter()) {
+            (p.set)(&mut config, v);
+        }
+        config.seed = seed;
+        self.trials += 1;
+        let now = Instant::now();
+        if now >= self.deadline {
+            return None;
+        }
+        // 時間切れの AI のスレッドは止められないが、そのときは予算も使い切っているので探すのをやめる
+        let p = self.problem;
+        let (task, ai) = (p.task, self.ai.to_string());
+        let (source, target) = (p.source.clone(), p.target.clone());
+        let run = move || build_trace(task, &ai, &config, &source, &target);
+        run_with_deadline(&p.source, &p.target, Some(self.deadline - now), run)
+            .ok()
+            .map(|(_, energy)| energy)
+    }
+
+    fn random_values<R: Rng>(&self, rng: &mut R) -> Vec<i64> {
+        self.params
+            .iter()
+            .map(|p| rng.gen_range(p.min, p.max + 1))
+            .collect()
+    }
+
+    // grid で試す組を全部並べる
+    fn grid_values(&self) -> Vec<Vec<i64>> {
+        let mut grid = vec![vec![]];
+        for p in self.params.iter() {
+            let mut points = (0..GRID_POINTS)
+                .map(|i| p.min + (p.max - p.min) * i / (GRID_POINTS - 1))
+                .collect::<Vec<_>>();
+            points.dedup();
+            grid = grid
+                .iter()
+                .flat_map(|values| {
+                    points.iter().map(move |&v| {
+                        let mut values = values.clone();
+                        values.push(v);
+                        values
+                    })
+                })
+                .collect();
+        }
+        grid
+    }
+}
+
+// problem を ai で解くときのパラメータを、budget の時間まで search で探す
+pub fn tune(problem: &Problem, ai: &str, search: Search, budget: Duration, seed: u64) -> Tuned {
+    let start = Instant::now();
+    let base = harness_config();
+    let params = problem.task.params(ai, &base);
+    let mut tuner = Tuner {
+        problem,
+        ai,
+        base,
+        params,
+        trials: 0,
+        deadline: start + budget,
+    };
+    let default_values = tuner
+        .params
+        .iter()
+        .map(|p| (p.get)(&tuner.base))
+        .collect::<Vec<_>>();
+    let default_energy = tuner.evaluate(&default_values, seed);
+    let mut best = (default_energy, default_values.clone());
+    let mut rng = seeded_rng(seed);
+
+    if !tuner.params.is_empty() {
+        match search {
+            Search::Grid => {
+                for values in tuner.grid_values() {
+                    if start.elapsed() >= budget {
+                        break;
+                    }
+                    if values == default_values {
+                        continue;
+                    }
+                    let energy = tuner.evaluate(&values, seed);
+                    if score(energy) < score(best.0) {
+                        best = (energy, values);
+                    }
+                }
+            }
+            Search::Random => {
+                while start.elapsed() < budget {
+                    let values = tuner.random_values(&mut rng);
+                    let energy = tuner.evaluate(&values, seed);
+                    if score(energy) < score(best.0) {
+                        best = (energy, values);
+                    }
+                }
+            }
+            Search::Halving => {
+                // (値, 最初の seed での energy, energy の合計, 測った seed の数)
+                let mut pool = vec![(default_values.clone(), default_energy, 0i64, 0i64)];
+                while pool.len() < HALVING_CANDIDATES {
+                    pool.push((tuner.random_values(&mut rng), None, 0, 0));
+                }
+                let mut round = 0;
+                'rounds: while pool.len() > 1 {
+                    for entry in pool.iter_mut() {
+                        let energy = if round == 0 && entry.0 == default_values {
+                            default_energy
+                        } else {
+                            if start.elapsed() >= budget {
+                                break 'rounds;
+                            }
+                            tuner.evaluate(&entry.0, seed + round)
+                        };
+                        if round == 0 {
+                            entry.1 = energy;
+                        }
+                        entry.2 = entry.2.saturating_add(score(energy));
+                        entry.3 += 1;
+                    }
+                    pool.sort_by_key(|&(_, _, sum, n)| sum / n);
+                    let half = (pool.len() + 1) / 2;
+                    pool.truncate(half);
+                    round += 1;
+                }
+                // 途中で時間切れになったら、測った seed が多くて平均が良い組を選ぶ
+                let chosen = pool
+                    .into_iter()
+                    .filter(|entry| entry.3 > 0)
+                    .min_by_key(|&(_, _, sum, n)| (-n, sum / n))
+                    .unwrap();
+                best = (chosen.1, chosen.0);
+            }
+        }
+    }
+
+    Tuned {
+        problem: problem.name.clone(),
+        task: problem.task,
+        ai: ai.to_string(),
+        search,
+        params: tuner.params,
+        default_energy,
+        best_values: best.1,
+        best_energy: best.0,
+        trials: tuner.trials,
+    }
+}
+
+// tuned を path の TSV に 1 行追記する。新しいファイルなら先に見出しの行を書く。
+pub fn record(path: &Path, tuned: &Tuned) -> Result<(), Box<Error>> {
+    let is_new = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if is_new {
+        writeln!(
+            file,
+            "problem\ttask\tai\tsearch\ttrials\tdefault_energy\tbest_energy\tsettings"
+        )?;
+    }
+    writeln!(
+        file,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        tuned.problem,
+        tuned.task.name(),
+        tuned.ai,
+        tuned.search.name(),
+        tuned.trials,
+        energy_name(tuned.default_energy),
+        energy_name(tuned.best_energy),
+        tuned.settings()
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn assemble_problem(shape: ::generator::Shape, r: usize) -> Problem {
+    Problem {
+        name: format!("{}_{}", shape.name(), r),
+        task: Task::Assemble,
+        source: Model::initial(r),
+        target: ::generator::generate(shape, r, 0),
+    }
+}
+
+#[test]
+fn test_tune_grid() {
+    let problem = assemble_problem(::generator::Shape::Pillars, 8);
+    let tuned = tune(
+        &problem,
+        "layer_fill",
+        Search::Grid,
+        Duration::from_secs(60),
+        0,
+    );
+    // GOLD_MAX_GROUPS の 1, 5, 9 と既定値の 9 なので 3 回
+    assert_eq!(tuned.trials, 3);
+    assert!(score(tuned.best_energy) <= score(tuned.default_energy));
+    assert!(tuned.settings().starts_with("GOLD_MAX_GROUPS="));
+}
+
+#[test]
+fn test_tune_halving() {
+    let problem = assemble_problem(::generator::Shape::Tower, 6);
+    let tuned = tune(&problem, "bfs", Search::Halving, Duration::from_secs(60), 0);
+    // 16 + 8 + 4 + 2 組を測る。既定値の最初の 1 回は先に測ってある。
+    assert_eq!(tuned.trials, 30);
+    assert_eq!(tuned.best_values.len(), 3);
+    assert!(tuned.best_energy.is_some());
+}
+
+#[test]
+fn test_tune_deadline() {
+    // 1 回の AI が予算より長くかかっても、予算を使い切ったところで打ち切る
+    let problem = assemble_problem(::generator::Shape::Blob, 60);
+    let start = Instant::now();
+    let tuned = tune(
+        &problem,
+        "bfs",
+        Search::Random,
+        Duration::from_millis(100),
+        0,
+    );
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(tuned.best_energy, None);
+}
+
+#[test]
+fn test_record() {
+    let problem = assemble_problem(::generator::Shape::Tower, 5);
+    let tuned = tune(
+        &problem,
+        "cuboid",
+        Search::Random,
+        Duration::from_secs(0),
+        0,
+    );
+    assert_eq!(tuned.trials, 1);
+    let path = ::std::env::temp_dir().join("gold_test_record.tsv");
+    let _ = ::std::fs::remove_file(&path);
+    record(&path, &tuned).unwrap();
+    record(&path, &tuned).unwrap();
+    let text = ::std::fs::read_to_string(&path).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("tower_5\tassemble\tcuboid\trandom\t1\t"));
+    let _ = ::std::fs::remove_file(&path);
+}
@@ -2,26 +2,71 @@
 
 use std::mem;
 
+// mark を呼んでからの parent の書き換えを覚えておき、undo でその時点に戻せる。
+// 経路圧縮による書き換えも覚えるので、戻した後は mark の時点と全く同じになる。
 #[derive(Clone, Debug)]
 pub struct UnionFind {
     parent: Vec<i32>,
+    // (添字, 書き換える前の値)
+    history: Vec<(usize, i32)>,
+    recording: bool,
 }
 
 impl UnionFind {
     pub fn new(n: usize) -> UnionFind {
         UnionFind {
             parent: vec![-1; n],
+            history: vec![],
+            recording: false,
         }
     }
+    fn set_parent(&mut self, x: usize, p: i32) {
+        if self.recording {
+            self.history.push((x, self.parent[x]));
+        }
+        self.parent[x] = p;
+    }
+    // 今の状態に戻すための印を返し、それ以降の書き換えを覚え始める
+    pub fn mark(&mut self) -> usize {
+        self.recording = true;
+        self.history.len()
+    }
+    // mark が返した印の時点に戻す
+    pub fn undo(&mut self, mark: usize) {
+        while self.history.len() > mark {
+            let (x, p) = self.history.pop().unwrap();
+            self.parent[x] = p;
+        }
+    }
+    // 覚えた書き換えを捨てて、覚えるのをやめる
+    pub fn forget(&mut self) {
+        self.recording = false;
+        self.history.clear();
+    }
     pub fn root(&mut self, x: usize) -> usize {
         let p = self.parent[x];
         return if p < 0 {
             x
         } else {
-            self.parent[x] = self.root(p as usize) as i32;
-            self.parent[x] as usize
+            let root = self.root(p as usize);
+            if p != root as i32 {
+                self.set_parent(x, root as i32);
+            }
+            root
         };
     }
+    // 経路圧縮をしない root。&self で呼べる
+    pub fn find(&self, x: usize) -> usize {
+        let mut x = x;
+        while self.parent[x] >= 0 {
+            x = self.parent[x] as usize;
+        }
+        x
+    }
+    // 経路圧縮をしない size
+    pub fn find_size(&self, x: usize) -> usize {
+        -self.parent[self.find(x)] as usize
+    }
     pub fn union_set(&mut self, x: usize, y: usize) -> bool {
         let mut x = self.root(x);
         let mut y = self.root(y);
@@ -31,8 +76,9 @@ impl UnionFind {
         if self.parent[y] < self.parent[x] {
             mem::swap(&mut x, &mut y);
         }
-        self.parent[x] += self.parent[y];
-        self.parent[y] = x as i32;
+        let size = self.parent[x] + self.parent[y];
+        self.set_parent(x, size);
+        self.set_parent(y, x as i32);
         true
     }
     pub fn find_set(&mut self, x: usize, y: usize) -> bool {
@@ -56,3 +102,23 @@ fn union_find() {
     assert!(r == 1 || r == 2 || r == 3);
     assert!(ufind.size(3) == 3);
 }
+
+#[test]
+fn union_find_undo() {
+    let mut ufind = UnionFind::new(10);
+    ufind.union_set(0, 1);
+    let mark = ufind.mark();
+    ufind.union_set(2, 3);
+    ufind.union_set(1, 3);
+    assert!(ufind.find_set(0, 2));
+    assert_eq!(ufind.size(0), 4);
+    ufind.undo(mark);
+    assert!(!ufind.find_set(0, 2));
+    assert!(!ufind.find_set(2, 3));
+    assert!(ufind.find_set(0, 1));
+    assert_eq!(ufind.size(0), 2);
+    ufind.forget();
+    ufind.union_set(4, 5);
+    ufind.undo(0);
+    assert!(ufind.find_set(4, 5));
+}